@@ -1,13 +1,16 @@
 extern crate proc_macro;
 use quote::quote;
 use syn::{
-    parse_macro_input, Attribute, Data, DeriveInput, Error, Fields, FieldsNamed, Ident, Lit,
-    LitStr, Meta, MetaList, MetaNameValue, NestedMeta,
+    parse_macro_input, Attribute, Data, DataEnum, DeriveInput, Error, Fields, FieldsNamed, Ident,
+    Lit, LitInt, LitStr, Meta, MetaList, MetaNameValue, NestedMeta,
 };
 
 #[proc_macro_derive(Den, attributes(den))]
 pub fn derive_den(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let item = parse_macro_input!(input as DeriveInput);
+    if let Data::Enum(ref data) = item.data {
+        return derive_enum(&item.ident, data);
+    }
     let struct_name = item.ident;
     let fields = extract_struct_fields(&item.data);
     let decode = fields.named.iter().map(|field| {
@@ -105,6 +108,97 @@ pub fn derive_den(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     gen.into()
 }
 
+/// Packet enums: every variant wraps one packet and is tagged with
+/// `#[den(id = N)]`. The ID byte is written before the packet and picks the
+/// variant when decoding.
+fn derive_enum(enum_name: &Ident, data: &DataEnum) -> proc_macro::TokenStream {
+    let mut decode = vec![];
+    let mut encode = vec![];
+    let mut size = vec![];
+    for variant in &data.variants {
+        let ident = &variant.ident;
+        let id = match (&variant.fields, parse_variant_id(&variant.attrs)) {
+            (Fields::Unnamed(fields), Some(Ok(id))) if fields.unnamed.len() == 1 => id,
+            (_, Some(Err(error))) => return error.to_compile_error().into(),
+            _ => {
+                return Error::new_spanned(
+                    variant,
+                    "expected a single-field tuple variant with `den(id = N)`",
+                )
+                .to_compile_error()
+                .into()
+            }
+        };
+        decode.push(quote! {
+            #id => Self::#ident(byte_util::Den::decode(bytes)?)
+        });
+        encode.push(quote! {
+            Self::#ident(packet) => {
+                byte_util::Den::encode(&#id, bytes)?;
+                byte_util::Den::encode(packet, bytes)
+            }
+        });
+        size.push(quote! {
+            Self::#ident(packet) => byte_util::Den::size(packet)
+        });
+    }
+
+    let gen = quote! {
+        impl byte_util::Den for #enum_name {
+            fn decode(bytes: &mut std::io::Cursor<&[u8]>) -> std::io::Result<Self> {
+                let id: u8 = byte_util::Den::decode(bytes)?;
+                Ok(match id {
+                    #(#decode,)*
+                    id => {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("unknown packet id {:#04x}", id),
+                        ))
+                    }
+                })
+            }
+            fn encode(&self, bytes : &mut std::io::Cursor<Vec<u8>>) -> std::io::Result<()> {
+                match self {
+                    #(#encode)*
+                }
+            }
+            fn size(&self) -> usize {
+                1 + match self {
+                    #(#size,)*
+                }
+            }
+        }
+    };
+
+    gen.into()
+}
+
+/// Parses `#[den(id = N)]` into a `u8` literal.
+fn parse_variant_id(attrs: &[Attribute]) -> Option<Result<LitInt, Error>> {
+    attrs.iter().find_map(|attr| {
+        let meta = attr.parse_meta().ok()?;
+        let Meta::List(MetaList { path, nested, .. }) = &meta else {
+            return None;
+        };
+        (path.get_ident()? == "den").then_some(())?;
+        let Some(NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+            path,
+            lit: Lit::Int(id),
+            ..
+        }))) = nested.first()
+        else {
+            return Some(Err(Error::new_spanned(meta, "expected `den(id = N)`")));
+        };
+        if *path.get_ident()? != "id" {
+            return Some(Err(Error::new_spanned(meta, "expected `den(id = N)`")));
+        }
+        Some(
+            id.base10_parse::<u8>()
+                .map(|value| LitInt::new(&format!("{value}u8"), id.span())),
+        )
+    })
+}
+
 fn extract_struct_fields(data: &Data) -> &FieldsNamed {
     match *data {
         Data::Struct(ref data) => match data.fields {
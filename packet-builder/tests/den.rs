@@ -54,3 +54,38 @@ fn den() {
 
     dbg!(hoge);
 }
+
+#[derive(Debug, PartialEq, Den)]
+struct Text {
+    #[den(with = "Big")]
+    length: u16,
+}
+
+#[derive(Debug, PartialEq, Den)]
+struct Empty {}
+
+#[derive(Debug, PartialEq, Den)]
+enum Packet {
+    #[den(id = 0xfe)]
+    Text(Text),
+    #[den(id = 1)]
+    Empty(Empty),
+}
+
+#[test]
+fn den_enum() {
+    let packet = Packet::Text(Text { length: 0x1234 });
+    let mut cursor = std::io::Cursor::new(vec![]);
+    packet.encode(&mut cursor).unwrap();
+    let bytes = cursor.into_inner();
+    assert_eq!(bytes, [0xfe, 0x12, 0x34]);
+    assert_eq!(packet.size(), bytes.len());
+
+    let decoded = Packet::decode(&mut std::io::Cursor::new(&bytes[..])).unwrap();
+    assert_eq!(decoded, packet);
+    let empty = Packet::decode(&mut std::io::Cursor::new(&[1u8][..])).unwrap();
+    assert_eq!(empty, Packet::Empty(Empty {}));
+
+    let unknown = Packet::decode(&mut std::io::Cursor::new(&[2u8][..]));
+    assert_eq!(unknown.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+}
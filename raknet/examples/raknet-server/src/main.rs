@@ -1,7 +1,17 @@
-use std::io::Cursor;
-
+use packet_builder::*;
 use raknet::*;
-use tokio::io::AsyncReadExt;
+
+#[derive(Den)]
+struct Text {
+    #[den(with = "RakString")]
+    text: String,
+}
+
+#[derive(Den)]
+enum Packet {
+    #[den(id = 0xfe)]
+    Text(Text),
+}
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
@@ -15,24 +25,18 @@ async fn main() -> std::io::Result<()> {
 }
 
 async fn handle(stream: RakStream, info: StreamInformation) {
-    let (s, mut r) = stream.split();
+    let (s, mut r) = Framed::<Packet>::new(stream).split();
     while let Some(packet) = r.receive().await {
-        if packet.is_empty() {
-            continue;
-        }
-
-        if packet[0] == 0xfe {
-            let mut cursor = Cursor::new(&packet[..]);
-            let text_length = cursor.read_u16().await.unwrap();
-            if text_length == 0 {
-                s.disconnect();
-                break;
+        match packet {
+            Ok(Packet::Text(Text { text })) => {
+                if text.is_empty() {
+                    s.disconnect();
+                    break;
+                }
+                println!("from : {}, msg : {}", info.address, text);
             }
-            let mut text_buffer = vec![0u8; text_length as usize];
-            cursor.read_exact(&mut text_buffer).await.unwrap();
-            let text = String::from_utf8(text_buffer).unwrap();
-            println!("from : {}, msg : {}", info.address, text);
+            Err(err) => eprintln!("from : {}, {}", info.address, err),
         }
     }
-    todo!();
+    println!("from : {}, disconnected", info.address);
 }
@@ -0,0 +1,57 @@
+use std::{net::SocketAddr, pin::Pin, sync::Arc};
+
+use futures::Future;
+
+/// What a client asks for in its OpenConnectionRequest2, the last offline
+/// packet before the listener sets up a connection.
+///
+/// There is no protocol version: the listener already turned away clients
+/// of other versions at OpenConnectionRequest1, reporting them as
+/// `HandshakeFailure::IncompatibleProtocol`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionAttempt {
+    pub address: SocketAddr,
+    pub guid: i64,
+    pub mtu: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcceptDecision {
+    Accept,
+    /// Answers with the RakNet reply for `RejectReason`.
+    Reject(RejectReason),
+    /// Sends nothing, so the client times out.
+    Drop,
+}
+
+/// The offline replies RakNet clients understand as a refused connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    /// ID_CONNECTION_BANNED
+    Banned,
+    /// ID_NO_FREE_INCOMING_CONNECTIONS
+    ServerFull,
+    /// ID_IP_RECENTLY_CONNECTED
+    RecentlyConnected,
+    /// ID_ALREADY_CONNECTED
+    AlreadyConnected,
+    /// ID_INCOMPATIBLE_PROTOCOL_VERSION
+    IncompatibleProtocol,
+}
+
+impl RejectReason {
+    pub(crate) fn packet_id(self) -> u8 {
+        match self {
+            RejectReason::Banned => 0x17,
+            RejectReason::ServerFull => 0x14,
+            RejectReason::RecentlyConnected => 0x1a,
+            RejectReason::AlreadyConnected => 0x12,
+            RejectReason::IncompatibleProtocol => 0x19,
+        }
+    }
+}
+
+/// Decides whether to set up a connection for a `ConnectionAttempt`.
+pub type AcceptFilter = Arc<
+    dyn Fn(ConnectionAttempt) -> Pin<Box<dyn Future<Output = AcceptDecision> + Send>> + Send + Sync,
+>;
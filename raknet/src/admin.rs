@@ -0,0 +1,83 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use futures::channel::mpsc;
+
+use crate::StreamInformation;
+
+/// A request from `Listener` to the loops that own the connections.
+pub(crate) enum AdminCommand {
+    Kick { id: u64, reason: String },
+    Broadcast { ids: Vec<u64>, payload: Vec<u8> },
+}
+
+/// The connections of every shard of a listener, so `Listener` can list and
+/// look them up without going through the loops. Connections are registered
+/// once they complete the handshake and their stream is queued for `accept`.
+#[derive(Clone, Default)]
+pub(crate) struct ConnectionRegistry {
+    next_id: Arc<AtomicU64>,
+    connections: Arc<Mutex<HashMap<u64, StreamInformation>>>,
+    command_senders: Arc<Mutex<Vec<mpsc::UnboundedSender<AdminCommand>>>>,
+}
+
+impl ConnectionRegistry {
+    /// IDs come from one counter so they are unique across shards.
+    pub fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn insert(&self, information: StreamInformation) {
+        let mut connections = self.connections.lock().unwrap();
+        connections.insert(information.id, information);
+    }
+
+    /// Returns `false` if the connection was not registered.
+    pub fn remove(&self, id: u64) -> bool {
+        self.connections.lock().unwrap().remove(&id).is_some()
+    }
+
+    pub fn get(&self, id: u64) -> Option<StreamInformation> {
+        self.connections.lock().unwrap().get(&id).cloned()
+    }
+
+    /// Sorted by ID, so in the order the handshakes started.
+    pub fn list(&self) -> Vec<StreamInformation> {
+        let connections = self.connections.lock().unwrap();
+        let mut list = connections.values().cloned().collect::<Vec<_>>();
+        list.sort_by_key(|information| information.id);
+        list
+    }
+
+    pub fn find(
+        &self,
+        mut predicate: impl FnMut(&StreamInformation) -> bool,
+    ) -> Option<StreamInformation> {
+        let connections = self.connections.lock().unwrap();
+        connections
+            .values()
+            .find(|information| predicate(information))
+            .cloned()
+    }
+
+    /// Creates the channel a shard loop receives commands on.
+    pub fn commands(&self) -> mpsc::UnboundedReceiver<AdminCommand> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.command_senders.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Sends a command to every shard. Shards that do not own the
+    /// connections it names ignore it.
+    pub fn send(&self, command: impl Fn() -> AdminCommand) {
+        let command_senders = self.command_senders.lock().unwrap();
+        for sender in command_senders.iter() {
+            _ = sender.unbounded_send(command());
+        }
+    }
+}
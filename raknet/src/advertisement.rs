@@ -0,0 +1,148 @@
+use std::{
+    fmt,
+    io::{Error, ErrorKind},
+    str::FromStr,
+};
+
+/// The Bedrock `server_id` carried by `UnconnectedPong`, e.g.
+/// `MCPE;Dedicated Server;560;1.19.50;0;10;13253860892328930865;Bedrock level;Survival;1;19132;19133;`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerAdvertisement {
+    pub edition: String,
+    pub motd: String,
+    pub protocol: u32,
+    pub version: String,
+    pub online_players: u32,
+    pub max_players: u32,
+    pub server_guid: i64,
+    pub level_name: String,
+    pub game_mode: String,
+    pub game_mode_numeric: u8,
+    pub ipv4_port: Option<u16>,
+    pub ipv6_port: Option<u16>,
+}
+
+impl Default for ServerAdvertisement {
+    fn default() -> Self {
+        Self {
+            edition: "MCPE".to_owned(),
+            motd: String::new(),
+            protocol: 0,
+            version: String::new(),
+            online_players: 0,
+            max_players: 0,
+            server_guid: 0,
+            level_name: String::new(),
+            game_mode: "Survival".to_owned(),
+            game_mode_numeric: 1,
+            ipv4_port: None,
+            ipv6_port: None,
+        }
+    }
+}
+
+fn invalid(field: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("invalid server advertisement field `{}`", field),
+    )
+}
+
+fn parse_field<T: FromStr>(value: Option<&str>, field: &str) -> std::io::Result<T> {
+    value
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| invalid(field))
+}
+
+fn parse_optional<T: FromStr>(value: Option<&str>, field: &str) -> std::io::Result<Option<T>> {
+    match value {
+        None | Some("") => Ok(None),
+        Some(value) => value.parse().map(Some).map_err(|_| invalid(field)),
+    }
+}
+
+impl FromStr for ServerAdvertisement {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::io::Result<Self> {
+        let mut fields = s.split(';');
+        let edition = fields.next().unwrap_or_default().to_owned();
+        if edition.is_empty() {
+            return Err(invalid("edition"));
+        }
+        let motd = fields.next().ok_or_else(|| invalid("motd"))?.to_owned();
+        let protocol = parse_field(fields.next(), "protocol")?;
+        let version = fields.next().ok_or_else(|| invalid("version"))?.to_owned();
+        let online_players = parse_field(fields.next(), "online_players")?;
+        let max_players = parse_field(fields.next(), "max_players")?;
+        // Vanilla servers print the GUID as an unsigned number.
+        let server_guid = match fields.next() {
+            None | Some("") => 0,
+            Some(guid) => guid
+                .parse::<i64>()
+                .or_else(|_| guid.parse::<u64>().map(|guid| guid as i64))
+                .map_err(|_| invalid("server_guid"))?,
+        };
+        let level_name = fields.next().unwrap_or_default().to_owned();
+        let game_mode = fields.next().unwrap_or_default().to_owned();
+        let game_mode_numeric = parse_optional(fields.next(), "game_mode_numeric")?.unwrap_or(0);
+        let ipv4_port = parse_optional(fields.next(), "ipv4_port")?;
+        let ipv6_port = parse_optional(fields.next(), "ipv6_port")?;
+
+        Ok(Self {
+            edition,
+            motd,
+            protocol,
+            version,
+            online_players,
+            max_players,
+            server_guid,
+            level_name,
+            game_mode,
+            game_mode_numeric,
+            ipv4_port,
+            ipv6_port,
+        })
+    }
+}
+
+/// Text fields cannot contain the separator, so it is dropped.
+fn sanitize(text: &str) -> String {
+    text.replace(';', "")
+}
+
+impl fmt::Display for ServerAdvertisement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{};{};{};{};{};{};{};{};{};{};",
+            sanitize(&self.edition),
+            sanitize(&self.motd),
+            self.protocol,
+            sanitize(&self.version),
+            self.online_players,
+            self.max_players,
+            self.server_guid as u64,
+            sanitize(&self.level_name),
+            sanitize(&self.game_mode),
+            self.game_mode_numeric,
+        )?;
+        let port = |port: Option<u16>| port.map(|port| port.to_string()).unwrap_or_default();
+        if self.ipv4_port.is_some() || self.ipv6_port.is_some() {
+            write!(f, "{};{};", port(self.ipv4_port), port(self.ipv6_port))?;
+        }
+        Ok(())
+    }
+}
+
+impl From<ServerAdvertisement> for String {
+    fn from(advertisement: ServerAdvertisement) -> Self {
+        advertisement.to_string()
+    }
+}
+
+impl From<&ServerAdvertisement> for String {
+    fn from(advertisement: &ServerAdvertisement) -> Self {
+        advertisement.to_string()
+    }
+}
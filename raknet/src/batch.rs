@@ -0,0 +1,226 @@
+use std::{
+    array, io,
+    mem::{self, MaybeUninit},
+    net::{SocketAddr, UdpSocket},
+    os::fd::{AsRawFd, BorrowedFd, RawFd},
+    ptr,
+};
+
+use async_io::Async;
+use socket2::{SockAddr, SockAddrStorage};
+
+/// Datagrams read or written per syscall.
+pub const BATCH_SIZE: usize = 16;
+
+/// `recvmmsg`/`sendmmsg` on a duplicate of a socket's descriptor.
+pub(crate) struct BatchSocket {
+    socket: Async<UdpSocket>,
+}
+
+impl BatchSocket {
+    pub fn new(socket: &async_std::net::UdpSocket) -> io::Result<Self> {
+        // SAFETY: `socket` keeps the descriptor open while it is borrowed.
+        let fd = unsafe { BorrowedFd::borrow_raw(socket.as_raw_fd()) };
+        let socket = UdpSocket::from(fd.try_clone_to_owned()?);
+        Ok(Self {
+            socket: Async::new(socket)?,
+        })
+    }
+
+    /// Receives up to `slots.len() / slot_size` datagrams, one per slot, and
+    /// returns the size and sender of each.
+    pub async fn recv(
+        &self,
+        slots: &mut [MaybeUninit<u8>],
+        slot_size: usize,
+    ) -> io::Result<Vec<(usize, Option<SocketAddr>)>> {
+        self.socket
+            .read_with(|socket| recvmmsg(socket.as_raw_fd(), slots, slot_size))
+            .await
+    }
+
+    /// Sends every datagram to `addr`, resuming after a partial `sendmmsg`.
+    /// Returns how many were sent, and the error that stopped the rest.
+    pub async fn send(&self, datagrams: &[Vec<u8>], addr: SocketAddr) -> (usize, io::Result<()>) {
+        let address = SockAddr::from(addr);
+        let mut sent = 0;
+        while sent < datagrams.len() {
+            let result = self
+                .socket
+                .write_with(|socket| sendmmsg(socket.as_raw_fd(), &datagrams[sent..], &address))
+                .await;
+            match result {
+                Ok(count) => sent += count,
+                Err(err) => return (sent, Err(err)),
+            }
+        }
+        (sent, Ok(()))
+    }
+}
+
+fn recvmmsg(
+    fd: RawFd,
+    slots: &mut [MaybeUninit<u8>],
+    slot_size: usize,
+) -> io::Result<Vec<(usize, Option<SocketAddr>)>> {
+    let mut addresses: [SockAddrStorage; BATCH_SIZE] =
+        array::from_fn(|_| SockAddrStorage::zeroed());
+    // SAFETY: both are plain C structs for which all zeroes is valid.
+    let mut iovecs: [libc::iovec; BATCH_SIZE] = unsafe { mem::zeroed() };
+    let mut messages: [libc::mmsghdr; BATCH_SIZE] = unsafe { mem::zeroed() };
+
+    let mut count = 0;
+    for (slot, address) in slots.chunks_exact_mut(slot_size).zip(&mut addresses) {
+        iovecs[count] = libc::iovec {
+            iov_base: slot.as_mut_ptr().cast(),
+            iov_len: slot.len(),
+        };
+        let header = &mut messages[count].msg_hdr;
+        header.msg_name = (address as *mut SockAddrStorage).cast();
+        header.msg_namelen = address.size_of();
+        header.msg_iov = &mut iovecs[count];
+        header.msg_iovlen = 1;
+        count += 1;
+    }
+
+    // SAFETY: every message points at a live slot and address buffer.
+    let received = unsafe {
+        libc::recvmmsg(
+            fd,
+            messages.as_mut_ptr(),
+            count as libc::c_uint,
+            libc::MSG_DONTWAIT,
+            ptr::null_mut(),
+        )
+    };
+    if received < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut datagrams = Vec::with_capacity(received as usize);
+    for (message, address) in messages.iter().zip(addresses).take(received as usize) {
+        // SAFETY: the kernel initialized `msg_namelen` bytes of the address.
+        let address = unsafe { SockAddr::new(address, message.msg_hdr.msg_namelen) };
+        datagrams.push((message.msg_len as usize, address.as_socket()));
+    }
+    Ok(datagrams)
+}
+
+fn sendmmsg(fd: RawFd, datagrams: &[Vec<u8>], address: &SockAddr) -> io::Result<usize> {
+    // SAFETY: both are plain C structs for which all zeroes is valid.
+    let mut iovecs: [libc::iovec; BATCH_SIZE] = unsafe { mem::zeroed() };
+    let mut messages: [libc::mmsghdr; BATCH_SIZE] = unsafe { mem::zeroed() };
+
+    let count = datagrams.len().min(BATCH_SIZE);
+    for (index, datagram) in datagrams[..count].iter().enumerate() {
+        iovecs[index] = libc::iovec {
+            iov_base: datagram.as_ptr() as *mut libc::c_void,
+            iov_len: datagram.len(),
+        };
+        let header = &mut messages[index].msg_hdr;
+        header.msg_name = address.as_ptr() as *mut libc::c_void;
+        header.msg_namelen = address.len();
+        header.msg_iov = &mut iovecs[index];
+        header.msg_iovlen = 1;
+    }
+
+    // SAFETY: every message points at a live datagram and `address`.
+    let sent = unsafe { libc::sendmmsg(fd, messages.as_mut_ptr(), count as libc::c_uint, 0) };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(sent as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::task;
+
+    use super::*;
+    use crate::buffer::{ReceiveBuffer, MAX_DATAGRAM_SIZE};
+
+    async fn pair() -> (async_std::net::UdpSocket, BatchSocket, SocketAddr) {
+        let sender = async_std::net::UdpSocket::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let receiver = async_std::net::UdpSocket::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let addr = receiver.local_addr().unwrap();
+        (receiver, BatchSocket::new(&sender).unwrap(), addr)
+    }
+
+    /// Receives one batch with `BatchSocket::recv` and returns its datagrams.
+    /// The buffer is new, so it has a slot for each of a full batch.
+    async fn recv(socket: &BatchSocket) -> Vec<Vec<u8>> {
+        let mut buffer = ReceiveBuffer::new();
+        let slots = buffer.prepare_slots(BATCH_SIZE);
+        let received = socket.recv(slots, MAX_DATAGRAM_SIZE).await.unwrap();
+        received
+            .into_iter()
+            .map(|(size, _)| {
+                // SAFETY: the kernel wrote `size` bytes into the slot.
+                unsafe { buffer.take_slot(size) }.to_vec()
+            })
+            .collect()
+    }
+
+    fn datagrams(count: usize) -> Vec<Vec<u8>> {
+        (0..count).map(|i| vec![i as u8; 10 + i]).collect()
+    }
+
+    #[test]
+    fn recv_returns_partial_batches() {
+        task::block_on(async {
+            let (receiver, sender, addr) = pair().await;
+            let receiver = BatchSocket::new(&receiver).unwrap();
+
+            let sent = datagrams(3);
+            assert_eq!(sender.send(&sent, addr).await.0, 3);
+            assert_eq!(recv(&receiver).await, sent);
+
+            // More than a batch arrives as a full one and then the rest.
+            let sent = datagrams(BATCH_SIZE + 4);
+            assert_eq!(sender.send(&sent, addr).await.0, BATCH_SIZE + 4);
+            assert_eq!(recv(&receiver).await, sent[..BATCH_SIZE]);
+            assert_eq!(recv(&receiver).await, sent[BATCH_SIZE..]);
+        });
+    }
+
+    #[test]
+    fn recv_stops_at_the_slots_it_is_given() {
+        task::block_on(async {
+            let (receiver, sender, addr) = pair().await;
+            let receiver = BatchSocket::new(&receiver).unwrap();
+            let mut buffer = ReceiveBuffer::new();
+
+            let sent = datagrams(5);
+            sender.send(&sent, addr).await.1.unwrap();
+            let slots = buffer.prepare_slots(2);
+            let received = receiver.recv(slots, MAX_DATAGRAM_SIZE).await.unwrap();
+            assert_eq!(received.len(), 2);
+            for (size, _) in received {
+                // SAFETY: the kernel wrote `size` bytes into the slot.
+                unsafe { buffer.take_slot(size) };
+            }
+            assert_eq!(recv(&receiver).await, sent[2..]);
+        });
+    }
+
+    #[test]
+    fn send_reports_what_went_out_before_an_error() {
+        task::block_on(async {
+            let (receiver, sender, addr) = pair().await;
+            let receiver = BatchSocket::new(&receiver).unwrap();
+
+            // The third datagram is too large for UDP, so `sendmmsg` stops
+            // there and the retry for the rest fails.
+            let mut sent = datagrams(4);
+            sent[2] = vec![0; 70_000];
+            let (count, result) = sender.send(&sent, addr).await;
+            assert_eq!(count, 2);
+            assert_eq!(result.unwrap_err().raw_os_error(), Some(libc::EMSGSIZE));
+            assert_eq!(recv(&receiver).await, sent[..2]);
+        });
+    }
+}
@@ -0,0 +1,154 @@
+#[cfg(target_os = "linux")]
+use std::mem::MaybeUninit;
+
+use bytes::{Bytes, BytesMut};
+
+/// Largest datagram the loops receive.
+pub const MAX_DATAGRAM_SIZE: usize = 4096;
+/// Each allocation holds this many full-size datagrams.
+const ARENA_SIZE: usize = MAX_DATAGRAM_SIZE * 16;
+
+/// Receives datagrams into a shared arena and hands them out as `Bytes`.
+///
+/// Received datagrams are split off the arena without copying. Once every
+/// `Bytes` pointing into an allocation is dropped, the allocation is
+/// reclaimed for later datagrams, so a steady stream of packets reuses the
+/// same few buffers.
+pub(crate) struct ReceiveBuffer {
+    arena: BytesMut,
+}
+
+impl ReceiveBuffer {
+    pub fn new() -> Self {
+        Self {
+            arena: BytesMut::with_capacity(ARENA_SIZE),
+        }
+    }
+
+    /// Space for the next datagram.
+    pub fn prepare(&mut self) -> &mut [u8] {
+        if self.arena.capacity() < MAX_DATAGRAM_SIZE {
+            self.arena.clear();
+            self.arena.reserve(ARENA_SIZE);
+        }
+        self.arena.resize(MAX_DATAGRAM_SIZE, 0);
+        &mut self.arena[..]
+    }
+
+    /// Takes the first `size` bytes written since `prepare`.
+    pub fn take(&mut self, size: usize) -> Bytes {
+        self.arena.split_to(size).freeze()
+    }
+
+    /// Uninitialized space for up to `count` datagrams of
+    /// `MAX_DATAGRAM_SIZE` each, as many as the arena has left. A new arena
+    /// is only set up once the current one cannot hold another datagram.
+    #[cfg(target_os = "linux")]
+    pub fn prepare_slots(&mut self, count: usize) -> &mut [MaybeUninit<u8>] {
+        self.arena.clear();
+        if self.arena.capacity() < MAX_DATAGRAM_SIZE {
+            self.arena.reserve(ARENA_SIZE);
+        }
+        let slots = (self.arena.capacity() / MAX_DATAGRAM_SIZE).min(count);
+        &mut self.arena.spare_capacity_mut()[..slots * MAX_DATAGRAM_SIZE]
+    }
+
+    /// Takes the next slot, holding a datagram of `size` bytes.
+    ///
+    /// # Safety
+    ///
+    /// The first `size` bytes of the slot must have been written since
+    /// `prepare_slots`.
+    #[cfg(target_os = "linux")]
+    pub unsafe fn take_slot(&mut self, size: usize) -> Bytes {
+        // SAFETY: the caller guarantees the datagram was written.
+        unsafe { self.arena.set_len(size) };
+        let datagram = self.arena.split_to(size).freeze();
+        // The rest of the slot was never written, so it is skipped without
+        // becoming part of the arena's contents.
+        self.arena = self.arena.split_off(MAX_DATAGRAM_SIZE - size);
+        datagram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receive(buffer: &mut ReceiveBuffer, byte: u8, size: usize) -> Bytes {
+        buffer.prepare()[..size].fill(byte);
+        buffer.take(size)
+    }
+
+    #[test]
+    fn datagrams_are_split_off_one_arena() {
+        let mut buffer = ReceiveBuffer::new();
+        let first = receive(&mut buffer, 1, 10);
+        let second = receive(&mut buffer, 2, 300);
+        assert_eq!(first, vec![1; 10]);
+        assert_eq!(second, vec![2; 300]);
+        // Back to back in the same allocation, and writing the next one
+        // leaves them alone.
+        assert_eq!(second.as_ptr(), first[10..].as_ptr());
+        buffer.prepare().fill(3);
+        assert_eq!(first, vec![1; 10]);
+        assert_eq!(second, vec![2; 300]);
+    }
+
+    #[test]
+    fn arena_is_reused_once_its_datagrams_are_dropped() {
+        let slots = ARENA_SIZE / MAX_DATAGRAM_SIZE;
+        let mut buffer = ReceiveBuffer::new();
+        let start = receive(&mut buffer, 0, MAX_DATAGRAM_SIZE).as_ptr();
+        for i in 1..slots {
+            receive(&mut buffer, i as u8, MAX_DATAGRAM_SIZE);
+        }
+        // Every datagram of the used up arena is gone, so it is reclaimed.
+        let held = receive(&mut buffer, 1, MAX_DATAGRAM_SIZE);
+        assert_eq!(held.as_ptr(), start);
+
+        // With one of them still held, a new arena is set up instead.
+        for i in 1..slots {
+            receive(&mut buffer, i as u8, MAX_DATAGRAM_SIZE);
+        }
+        let next = receive(&mut buffer, 2, MAX_DATAGRAM_SIZE);
+        assert_ne!(next.as_ptr(), start);
+        assert_eq!(held, vec![1; MAX_DATAGRAM_SIZE]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn slots_are_taken_one_datagram_apart() {
+        let mut buffer = ReceiveBuffer::new();
+        let slots = buffer.prepare_slots(3);
+        assert_eq!(slots.len(), 3 * MAX_DATAGRAM_SIZE);
+        for (i, slot) in slots.chunks_exact_mut(MAX_DATAGRAM_SIZE).enumerate() {
+            slot[..100].fill(MaybeUninit::new(i as u8));
+        }
+        // SAFETY: the first 100 bytes of each slot were written above.
+        let datagrams = unsafe { [10, 100, 1].map(|size| buffer.take_slot(size)) };
+        assert_eq!(datagrams[0], vec![0; 10]);
+        assert_eq!(datagrams[1], vec![1; 100]);
+        assert_eq!(datagrams[2], vec![2; 1]);
+        let start = datagrams[0].as_ptr();
+        assert_eq!(
+            datagrams[2].as_ptr(),
+            start.wrapping_add(2 * MAX_DATAGRAM_SIZE)
+        );
+
+        // Only what is left of the arena is offered, however many are asked
+        // for.
+        let left = ARENA_SIZE / MAX_DATAGRAM_SIZE - 3;
+        assert_eq!(buffer.prepare_slots(64).len(), left * MAX_DATAGRAM_SIZE);
+        for _ in 0..left {
+            buffer.prepare_slots(1);
+            // SAFETY: an empty datagram has nothing to write.
+            unsafe { buffer.take_slot(0) };
+        }
+        // The arena is used up and still held, so a new one is set up.
+        let slots = buffer.prepare_slots(64);
+        assert_eq!(slots.len(), ARENA_SIZE);
+        let fresh = slots.as_ptr().cast::<u8>();
+        assert!(!(start..start.wrapping_add(ARENA_SIZE)).contains(&fresh));
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    io::{Error, ErrorKind},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use bytes::Bytes;
+use futures::{AsyncRead, AsyncWrite, SinkExt};
+
+use crate::{RakStream, RakStreamReceiver, RakStreamSender};
+
+/// Largest chunk a single write turns into a packet.
+const MAX_CHUNK_SIZE: usize = 16 * 1024;
+
+/// A byte stream over a `RakStream`, for code written against `AsyncRead`
+/// and `AsyncWrite`.
+///
+/// Writes are sent as reliable ordered packets of at most 16 KiB, each
+/// starting with `packet_id` so they are never taken for RakNet's own
+/// messages. Both ends must use a `ByteStream` with the same ID; a packet
+/// with any other ID fails the read with `InvalidData`.
+pub struct ByteStream {
+    sender: RakStreamSender,
+    receiver: RakStreamReceiver,
+    packet_id: u8,
+    pending: Bytes,
+}
+
+impl ByteStream {
+    pub fn new(stream: RakStream, packet_id: u8) -> Self {
+        let (sender, receiver) = stream.split();
+        Self {
+            sender,
+            receiver,
+            packet_id,
+            pending: Bytes::new(),
+        }
+    }
+}
+
+impl AsyncRead for ByteStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        while self.pending.is_empty() {
+            let Some(packet) = ready!(self.receiver.poll_receive_bytes(cx)) else {
+                return Poll::Ready(Ok(0));
+            };
+            if packet.first() != Some(&self.packet_id) {
+                return Poll::Ready(Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "packet is not part of the byte stream",
+                )));
+            }
+            self.pending = packet.slice(1..);
+        }
+
+        let size = buf.len().min(self.pending.len());
+        buf[..size].copy_from_slice(&self.pending[..size]);
+        self.pending = self.pending.slice(size..);
+        Poll::Ready(Ok(size))
+    }
+}
+
+impl AsyncWrite for ByteStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        ready!(self.sender.poll_ready_unpin(cx))?;
+        let size = buf.len().min(MAX_CHUNK_SIZE);
+        let mut packet = Vec::with_capacity(size + 1);
+        packet.push(self.packet_id);
+        packet.extend_from_slice(&buf[..size]);
+        self.sender.start_send_unpin(packet)?;
+        Poll::Ready(Ok(size))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.sender.poll_flush_unpin(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.sender.poll_close_unpin(cx)
+    }
+}
@@ -9,12 +9,12 @@ pub struct U24;
 impl DenWith<u32> for U24 {
     fn decode(bytes: &mut Cursor<&[u8]>) -> std::io::Result<u32> {
         let mut buf = [0; 3];
-        bytes.read_exact(&mut buf).unwrap();
-        Ok(((buf[0] as u32) << 16) | ((buf[1] as u32) << 8) | (buf[2] as u32))
+        bytes.read_exact(&mut buf)?;
+        Ok((buf[0] as u32) | ((buf[1] as u32) << 8) | ((buf[2] as u32) << 16))
     }
 
     fn encode(v: &u32, bytes: &mut Cursor<Vec<u8>>) -> std::io::Result<()> {
-        let buf = [(*v >> 16) as u8, (*v >> 8) as u8, *v as u8];
+        let buf = [*v as u8, (*v >> 8) as u8, (*v >> 16) as u8];
         bytes.write_all(&buf)
     }
 
@@ -23,6 +23,8 @@ impl DenWith<u32> for U24 {
     }
 }
 
+/// A string prefixed with its length as a big-endian `u16`, for
+/// `#[den(with = "RakString")]` fields.
 pub struct RakString;
 
 impl DenWith<String> for RakString {
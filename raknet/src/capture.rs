@@ -0,0 +1,220 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x1;
+const ENHANCED_PACKET_BLOCK: u32 = 0x6;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+/// Packets start with an IPv4 or IPv6 header, no link layer.
+const LINKTYPE_RAW: u16 = 101;
+const EPB_FLAGS: u16 = 2;
+const INBOUND: u32 = 1;
+const OUTBOUND: u32 = 2;
+const UDP: u8 = 17;
+const TTL: u8 = 64;
+
+/// Writes every datagram of a `Listener` or `RakStream` to a pcapng file.
+///
+/// Each datagram is wrapped in synthetic IP and UDP headers so the capture
+/// opens directly in Wireshark and other analyzers. Writes happen on the
+/// loop task and are flushed per datagram.
+#[derive(Clone)]
+pub struct Capture {
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl Capture {
+    pub fn create<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+
+    /// Writes the pcapng headers to `writer` and captures into it.
+    pub fn new<W: Write + Send + 'static>(mut writer: W) -> std::io::Result<Self> {
+        let mut section_header = vec![];
+        section_header.extend(BYTE_ORDER_MAGIC.to_le_bytes());
+        section_header.extend(1u16.to_le_bytes());
+        section_header.extend(0u16.to_le_bytes());
+        // Section length is not known up front.
+        section_header.extend((-1i64).to_le_bytes());
+        write_block(&mut writer, SECTION_HEADER_BLOCK, &section_header)?;
+
+        let mut interface_description = vec![];
+        interface_description.extend(LINKTYPE_RAW.to_le_bytes());
+        interface_description.extend(0u16.to_le_bytes());
+        interface_description.extend(0u32.to_le_bytes());
+        write_block(
+            &mut writer,
+            INTERFACE_DESCRIPTION_BLOCK,
+            &interface_description,
+        )?;
+        writer.flush()?;
+
+        Ok(Self {
+            writer: Arc::new(Mutex::new(Box::new(writer))),
+        })
+    }
+
+    fn write(
+        &self,
+        source: SocketAddr,
+        destination: SocketAddr,
+        flags: u32,
+        payload: &[u8],
+    ) -> std::io::Result<()> {
+        let packet = ip_packet(source, destination, payload);
+        let micros = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+
+        let mut block = vec![];
+        block.extend(0u32.to_le_bytes());
+        block.extend(((micros >> 32) as u32).to_le_bytes());
+        block.extend((micros as u32).to_le_bytes());
+        block.extend((packet.len() as u32).to_le_bytes());
+        block.extend((packet.len() as u32).to_le_bytes());
+        block.extend(&packet);
+        block.resize(block.len().next_multiple_of(4), 0);
+        block.extend(EPB_FLAGS.to_le_bytes());
+        block.extend(4u16.to_le_bytes());
+        block.extend(flags.to_le_bytes());
+        // opt_endofopt
+        block.extend([0u8; 4]);
+
+        let mut writer = self.writer.lock().unwrap();
+        write_block(&mut *writer, ENHANCED_PACKET_BLOCK, &block)?;
+        writer.flush()
+    }
+}
+
+fn write_block<W: Write + ?Sized>(
+    writer: &mut W,
+    block_type: u32,
+    body: &[u8],
+) -> std::io::Result<()> {
+    let length = (body.len() + 12) as u32;
+    writer.write_all(&block_type.to_le_bytes())?;
+    writer.write_all(&length.to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(&length.to_le_bytes())
+}
+
+fn checksum(data: &[u8], mut sum: u32) -> u16 {
+    for chunk in data.chunks(2) {
+        let word = match chunk {
+            [high, low] => u16::from_be_bytes([*high, *low]),
+            [high] => u16::from_be_bytes([*high, 0]),
+            _ => 0,
+        };
+        sum += word as u32;
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn ip_packet(source: SocketAddr, destination: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let udp_length = (payload.len() + 8) as u16;
+    let mut udp = vec![];
+    udp.extend(source.port().to_be_bytes());
+    udp.extend(destination.port().to_be_bytes());
+    udp.extend(udp_length.to_be_bytes());
+    udp.extend(0u16.to_be_bytes());
+    udp.extend(payload);
+
+    let (source_ip, destination_ip) = match (source.ip(), destination.ip()) {
+        (IpAddr::V4(source), IpAddr::V4(destination)) => {
+            let mut pseudo_header = vec![];
+            pseudo_header.extend(source.octets());
+            pseudo_header.extend(destination.octets());
+            pseudo_header.extend([0, UDP]);
+            pseudo_header.extend(udp_length.to_be_bytes());
+            set_udp_checksum(&mut udp, &pseudo_header);
+
+            let mut header = vec![0x45, 0];
+            header.extend((20 + udp_length).to_be_bytes());
+            // Identification, then don't fragment.
+            header.extend([0, 0, 0x40, 0, TTL, UDP, 0, 0]);
+            header.extend(source.octets());
+            header.extend(destination.octets());
+            let sum = checksum(&header, 0);
+            header[10..12].copy_from_slice(&sum.to_be_bytes());
+            header.extend(udp);
+            return header;
+        }
+        // Mixed families only happen on dual-stack sockets.
+        (source, destination) => (to_ipv6(source), to_ipv6(destination)),
+    };
+
+    let mut pseudo_header = vec![];
+    pseudo_header.extend(source_ip.octets());
+    pseudo_header.extend(destination_ip.octets());
+    pseudo_header.extend((udp_length as u32).to_be_bytes());
+    pseudo_header.extend([0, 0, 0, UDP]);
+    set_udp_checksum(&mut udp, &pseudo_header);
+
+    let mut header = vec![0x60, 0, 0, 0];
+    header.extend(udp_length.to_be_bytes());
+    header.extend([UDP, TTL]);
+    header.extend(source_ip.octets());
+    header.extend(destination_ip.octets());
+    header.extend(udp);
+    header
+}
+
+fn to_ipv6(ip: IpAddr) -> std::net::Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+fn set_udp_checksum(udp: &mut [u8], pseudo_header: &[u8]) {
+    let pseudo_sum = pseudo_header
+        .chunks(2)
+        .map(|chunk| u16::from_be_bytes([chunk[0], chunk[1]]) as u32)
+        .sum();
+    let sum = match checksum(udp, pseudo_sum) {
+        // Zero means "no checksum", so it is sent as all ones.
+        0 => 0xffff,
+        sum => sum,
+    };
+    udp[6..8].copy_from_slice(&sum.to_be_bytes());
+}
+
+/// The capture slot shared by a loop and its connections. Capturing is a
+/// no-op while it is empty.
+#[derive(Clone, Default)]
+pub(crate) struct CaptureHook {
+    capture: Arc<Mutex<Option<(Capture, SocketAddr)>>>,
+}
+
+impl CaptureHook {
+    pub fn set(&self, capture: Option<Capture>, local_addr: SocketAddr) {
+        *self.capture.lock().unwrap() = capture.map(|capture| (capture, local_addr));
+    }
+
+    pub fn clear(&self) {
+        *self.capture.lock().unwrap() = None;
+    }
+
+    pub fn sent(&self, remote: SocketAddr, payload: &[u8]) {
+        if let Some((capture, local_addr)) = &*self.capture.lock().unwrap() {
+            // A failing capture must not take the connection down with it.
+            _ = capture.write(*local_addr, remote, OUTBOUND, payload);
+        }
+    }
+
+    pub fn received(&self, remote: SocketAddr, payload: &[u8]) {
+        if let Some((capture, local_addr)) = &*self.capture.lock().unwrap() {
+            _ = capture.write(remote, *local_addr, INBOUND, payload);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+use futures::{Future, FutureExt};
+
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Source of time for every timer in the crate: keepalives, resends,
+/// handshake timeouts and ping timestamps.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration) -> Sleep;
+}
+
+/// The default clock, backed by `Instant::now` and `async_std` timers.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        async_std::task::sleep(duration).boxed()
+    }
+}
+
+/// A clock that only moves when `advance` is called.
+///
+/// Sleeps started on a `ManualClock` complete once the clock has been
+/// advanced past their deadline, which makes timeout and resend behaviour
+/// deterministic in tests.
+#[derive(Clone)]
+pub struct ManualClock {
+    inner: Arc<Mutex<ManualClockInner>>,
+}
+
+struct ManualClockInner {
+    now: Instant,
+    next_sleeper: u64,
+    sleepers: HashMap<u64, (Instant, Waker)>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(ManualClockInner {
+                now: Instant::now(),
+                next_sleeper: 0,
+                sleepers: HashMap::new(),
+            })),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let wakers = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.now += duration;
+            let now = inner.now;
+            let due = inner
+                .sleepers
+                .iter()
+                .filter(|(_, (deadline, _))| *deadline <= now)
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>();
+            due.into_iter()
+                .filter_map(|id| inner.sleepers.remove(&id))
+                .map(|(_, waker)| waker)
+                .collect::<Vec<_>>()
+        };
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.inner.lock().unwrap().now
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.next_sleeper;
+        inner.next_sleeper += 1;
+        Box::pin(ManualSleep {
+            id,
+            deadline: inner.now + duration,
+            clock: self.inner.clone(),
+        })
+    }
+}
+
+struct ManualSleep {
+    id: u64,
+    deadline: Instant,
+    clock: Arc<Mutex<ManualClockInner>>,
+}
+
+impl Future for ManualSleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut inner = self.clock.lock().unwrap();
+        if inner.now >= self.deadline {
+            inner.sleepers.remove(&self.id);
+            return Poll::Ready(());
+        }
+        inner
+            .sleepers
+            .insert(self.id, (self.deadline, cx.waker().clone()));
+        Poll::Pending
+    }
+}
+
+impl Drop for ManualSleep {
+    fn drop(&mut self) {
+        if let Ok(mut inner) = self.clock.lock() {
+            inner.sleepers.remove(&self.id);
+        }
+    }
+}
+
+pub(crate) fn default_clock() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
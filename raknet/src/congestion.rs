@@ -0,0 +1,59 @@
+use std::time::Instant;
+
+/// Datagrams a new connection may have in flight before its first ACK.
+const INITIAL_WINDOW: usize = 10;
+/// The window never shrinks below this many datagrams.
+const MIN_WINDOW: usize = 2;
+/// Keeps the window within what the peer's reliable window accepts.
+const MAX_WINDOW: usize = 1024;
+
+/// A byte-based congestion window in the manner of TCP Reno: slow start up
+/// to the first loss, additive increase after it, and halving at most once
+/// per round trip when datagrams are lost.
+pub(crate) struct CongestionWindow {
+    mtu: usize,
+    window: usize,
+    threshold: usize,
+    /// Losses of datagrams sent before this were already answered by a cut.
+    reduced_at: Option<Instant>,
+}
+
+impl CongestionWindow {
+    pub fn new(mtu: usize) -> Self {
+        Self {
+            mtu,
+            window: INITIAL_WINDOW * mtu,
+            threshold: MAX_WINDOW * mtu,
+            reduced_at: None,
+        }
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    /// Whether another datagram fits next to `in_flight` unacknowledged
+    /// bytes. A connection with nothing in flight may always send.
+    pub fn has_room(&self, in_flight: usize) -> bool {
+        in_flight == 0 || in_flight + self.mtu <= self.window
+    }
+
+    pub fn on_ack(&mut self, size: usize) {
+        let growth = match self.window < self.threshold {
+            true => size,
+            false => (self.mtu * size / self.window).max(1),
+        };
+        self.window = (self.window + growth).min(MAX_WINDOW * self.mtu);
+    }
+
+    /// Answers the loss of a datagram sent at `sent`, reported by a NACK or
+    /// its resend timeout.
+    pub fn on_loss(&mut self, sent: Instant, now: Instant) {
+        if self.reduced_at.is_some_and(|reduced_at| sent < reduced_at) {
+            return;
+        }
+        self.window = (self.window / 2).max(MIN_WINDOW * self.mtu);
+        self.threshold = self.window;
+        self.reduced_at = Some(now);
+    }
+}
@@ -1,17 +1,67 @@
-use std::sync::Arc;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
-use async_std::net::UdpSocket;
+use byte_util::Den;
+use bytes::{Bytes, BytesMut};
 use futures::channel::mpsc;
 
+use crate::{
+    capture::CaptureHook,
+    clock::Clock,
+    congestion::CongestionWindow,
+    event::{DisconnectReason, EventBroadcaster, HandshakeFailure, ListenerEvent},
+    frame::{
+        u24_add, u24_before, u24_distance, Ack, Frame, FrameSet, Priority, Reliability, Split,
+        ACK_ID, FRAME_SET_ID, NACK_ID,
+    },
+    instrument::{connection_span, debug, Span},
+    metrics::Metrics,
+    pacer::{BandwidthLimits, Pacer, Share},
+    packets::*,
+    remote_clock::RemoteClock,
+    socket::DatagramSocket,
+    stats::ConnectionStats,
+};
+
 pub enum ToStreamMsg {
-    Packet(Vec<u8>),
+    Packet(Bytes),
 }
 
 pub enum ToConnMsg {
-    Send(Vec<u8>),
+    Send(Vec<u8>, Priority),
     Disconnect,
 }
 
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(5);
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a disconnect may take to send what the bandwidth limits still
+/// hold back.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+const INITIAL_RTO: Duration = Duration::from_secs(1);
+const MIN_RTO: Duration = Duration::from_millis(100);
+const MAX_RTO: Duration = Duration::from_secs(2);
+
+pub const UDP_HEADER_SIZE: usize = 28;
+pub const MIN_MTU: usize = 576;
+pub const MAX_MTU: usize = 1500;
+const FRAME_SET_HEADER_SIZE: usize = 4;
+const MAX_FRAME_HEADER_SIZE: usize = 23;
+
+const ORDER_CHANNELS: usize = 32;
+const MAX_ORDER_QUEUE: usize = 1024;
+const MAX_RELIABLE_WINDOW: u32 = 4096;
+const MAX_NACK_GAP: u32 = 256;
+const MAX_SPLIT_COUNT: u32 = 4096;
+const MAX_SPLIT_PACKETS: usize = 16;
+
 enum ConnType {
     Incoming,
     Outgoing,
@@ -19,40 +69,1166 @@ enum ConnType {
 
 enum ConnStatus {
     Connecting(ConnectStatus),
+    Connected,
+    /// Sending the queued frames and then the disconnect notification
+    /// before closing.
+    Draining {
+        reason: DisconnectReason,
+        deadline: Instant,
+    },
+    Disconnected,
 }
 
+#[allow(clippy::enum_variant_names)]
 enum ConnectStatus {
     WaitingConnectionRequest,
     WaitingConnectionRequestAccepted,
     WaitingNewIncomingConnection,
 }
 
+struct SentDatagram {
+    sent: Instant,
+    size: usize,
+    frames: Vec<(Priority, Frame)>,
+}
+
+/// Frames waiting to be packed into a datagram, one queue per priority.
+#[derive(Default)]
+struct Outgoing {
+    queues: [VecDeque<Frame>; 4],
+}
+
+impl Outgoing {
+    fn push_back(&mut self, priority: Priority, frame: Frame) {
+        self.queues[priority as usize].push_back(frame);
+    }
+
+    fn push_front(&mut self, priority: Priority, frame: Frame) {
+        self.queues[priority as usize].push_front(frame);
+    }
+
+    /// The next frame to send, from the most urgent queue that has one.
+    fn front(&self) -> Option<&Frame> {
+        self.queues.iter().find_map(VecDeque::front)
+    }
+
+    fn pop_front(&mut self) -> Option<(Priority, Frame)> {
+        Priority::ALL
+            .into_iter()
+            .zip(&mut self.queues)
+            .find_map(|(priority, queue)| Some((priority, queue.pop_front()?)))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queues.iter().all(VecDeque::is_empty)
+    }
+
+    fn clear(&mut self) {
+        self.queues.iter_mut().for_each(VecDeque::clear);
+    }
+
+    fn depth(&self) -> [usize; 4] {
+        self.queues.each_ref().map(VecDeque::len)
+    }
+}
+
 pub struct Conn {
-    socket: Arc<UdpSocket>,
-    msg_sender: mpsc::Sender<ToStreamMsg>,
-    msg_receiver: mpsc::Receiver<ToConnMsg>,
+    socket: Arc<DatagramSocket>,
+    address: SocketAddr,
+    guid: i64,
+    mtu: usize,
+    clock: Arc<dyn Clock>,
+    events: EventBroadcaster,
+    capture: CaptureHook,
+    msg_sender: mpsc::UnboundedSender<ToStreamMsg>,
     conn_type: ConnType,
     status: ConnStatus,
+
+    created: Instant,
+    connected_at: Option<Instant>,
+    last_receive: Instant,
+    last_ping: Instant,
+    srtt: Option<Duration>,
+    rttvar: Duration,
+
+    send_sequence: u32,
+    reliable_index: u32,
+    sequence_index: u32,
+    order_index: u32,
+    split_id: u16,
+    outgoing: Outgoing,
+    recovery: BTreeMap<u32, SentDatagram>,
+    pacer: Option<Pacer>,
+    shared_pacer: Option<Share>,
+    congestion: CongestionWindow,
+    /// Bytes of the datagrams in `recovery`.
+    in_flight: usize,
+
+    expected_sequence: u32,
+    ack_queue: Vec<u32>,
+    nack_queue: HashSet<u32>,
+    reliable_window_start: u32,
+    reliable_window: HashSet<u32>,
+    highest_sequence: [u32; ORDER_CHANNELS],
+    expected_order: [u32; ORDER_CHANNELS],
+    order_queue: Vec<BTreeMap<u32, Bytes>>,
+    splits: HashMap<u16, Vec<Option<Bytes>>>,
+
+    stats: ConnectionStats,
+    shared_stats: Arc<Mutex<ConnectionStats>>,
+    remote_clock: Arc<Mutex<RemoteClock>>,
+    closed: Arc<AtomicBool>,
+    span: Span,
 }
 
 impl Conn {
+    #[allow(clippy::too_many_arguments)]
     pub fn incoming_connection(
-        socket: Arc<UdpSocket>,
-        msg_sender: mpsc::Sender<ToStreamMsg>,
-        msg_receiver: mpsc::Receiver<ToConnMsg>,
+        socket: Arc<DatagramSocket>,
+        address: SocketAddr,
+        guid: i64,
+        mtu: usize,
+        clock: Arc<dyn Clock>,
+        events: EventBroadcaster,
+        capture: CaptureHook,
+        msg_sender: mpsc::UnboundedSender<ToStreamMsg>,
+        limits: BandwidthLimits,
     ) -> Self {
+        Self::new(
+            socket,
+            address,
+            guid,
+            mtu,
+            clock,
+            events,
+            capture,
+            msg_sender,
+            limits,
+            ConnType::Incoming,
+            ConnectStatus::WaitingConnectionRequest,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn outgoing_connection(
+        socket: Arc<DatagramSocket>,
+        address: SocketAddr,
+        guid: i64,
+        mtu: usize,
+        clock: Arc<dyn Clock>,
+        events: EventBroadcaster,
+        capture: CaptureHook,
+        msg_sender: mpsc::UnboundedSender<ToStreamMsg>,
+        limits: BandwidthLimits,
+    ) -> Self {
+        let conn = Self::new(
+            socket,
+            address,
+            guid,
+            mtu,
+            clock,
+            events,
+            capture,
+            msg_sender,
+            limits,
+            ConnType::Outgoing,
+            ConnectStatus::WaitingConnectionRequestAccepted,
+        );
+        conn.span.record("client_guid", guid);
+        conn
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        socket: Arc<DatagramSocket>,
+        address: SocketAddr,
+        guid: i64,
+        mtu: usize,
+        clock: Arc<dyn Clock>,
+        events: EventBroadcaster,
+        capture: CaptureHook,
+        msg_sender: mpsc::UnboundedSender<ToStreamMsg>,
+        limits: BandwidthLimits,
+        conn_type: ConnType,
+        status: ConnectStatus,
+    ) -> Self {
+        let now = clock.now();
+        let remote_clock = RemoteClock::new(clock.clone(), now);
         Self {
             socket,
+            address,
+            guid,
+            mtu,
+            clock,
+            events,
+            capture,
             msg_sender,
-            msg_receiver,
-            conn_type: ConnType::Incoming,
-            status: ConnStatus::Connecting(ConnectStatus::WaitingConnectionRequest),
+            conn_type,
+            status: ConnStatus::Connecting(status),
+            created: now,
+            connected_at: None,
+            last_receive: now,
+            last_ping: now,
+            srtt: None,
+            rttvar: Duration::ZERO,
+            send_sequence: 0,
+            reliable_index: 0,
+            sequence_index: 0,
+            order_index: 0,
+            split_id: 0,
+            outgoing: Outgoing::default(),
+            recovery: BTreeMap::new(),
+            pacer: limits.connection.map(|rate| Pacer::new(rate, now)),
+            shared_pacer: limits.shared.map(|shared| Share::new(shared, now)),
+            congestion: CongestionWindow::new(mtu),
+            in_flight: 0,
+            expected_sequence: 0,
+            ack_queue: vec![],
+            nack_queue: HashSet::new(),
+            reliable_window_start: 0,
+            reliable_window: HashSet::new(),
+            highest_sequence: [0; ORDER_CHANNELS],
+            expected_order: [0; ORDER_CHANNELS],
+            order_queue: vec![BTreeMap::new(); ORDER_CHANNELS],
+            splits: HashMap::new(),
+            stats: ConnectionStats::default(),
+            shared_stats: Arc::default(),
+            remote_clock: Arc::new(Mutex::new(remote_clock)),
+            closed: Arc::default(),
+            span: connection_span(address, mtu),
+        }
+    }
+
+    /// Starts every 24-bit counter, sent and expected, at `index` instead of
+    /// zero. Both peers have to agree on it, so only tests use this.
+    #[cfg(test)]
+    pub fn set_initial_index(&mut self, index: u32) {
+        self.send_sequence = index;
+        self.reliable_index = index;
+        self.sequence_index = index;
+        self.order_index = index;
+        self.expected_sequence = index;
+        self.reliable_window_start = index;
+        self.highest_sequence = [index; ORDER_CHANNELS];
+        self.expected_order = [index; ORDER_CHANNELS];
+    }
+
+    /// The snapshot handed to the `RakStream`, refreshed on every flush.
+    pub fn stats(&self) -> Arc<Mutex<ConnectionStats>> {
+        self.shared_stats.clone()
+    }
+
+    /// The estimate of the peer's clock handed to the `RakStream`, refreshed
+    /// on every pong.
+    pub fn remote_clock(&self) -> Arc<Mutex<RemoteClock>> {
+        self.remote_clock.clone()
+    }
+
+    /// Set once the connection is closed, before the stream's channel ends.
+    pub fn closed_flag(&self) -> Arc<AtomicBool> {
+        self.closed.clone()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        matches!(self.status, ConnStatus::Connected)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        matches!(self.status, ConnStatus::Disconnected)
+    }
+
+    /// Sends `ConnectionRequest`, the first step of the online handshake.
+    pub async fn request_connection(&mut self) {
+        let request = ConnectionRequest {
+            guid: self.guid,
+            time: self.time(),
+            use_security: false,
+        };
+        self.queue_packet(request, 0x09, Reliability::Reliable);
+        debug!(parent: &self.span, "sent connection request");
+        self.flush().await;
+    }
+
+    pub async fn handle(&mut self, datagram: Bytes) {
+        let buffer = &datagram[..];
+        if buffer.is_empty() || self.is_closed() {
+            return;
         }
+
+        self.last_receive = self.clock.now();
+        self.stats.bytes_received += buffer.len() as u64;
+        self.stats.datagrams_received += 1;
+        match buffer[0] {
+            ACK_ID => match decode::<Ack>(buffer) {
+                Ok(ack) => self.handle_ack(ack),
+                Err(_) => self.events.malformed_packet(self.address, buffer),
+            },
+            NACK_ID => match decode::<Ack>(buffer) {
+                Ok(nack) => self.handle_nack(nack),
+                Err(_) => self.events.malformed_packet(self.address, buffer),
+            },
+            0x80..=0x8f => match FrameSet::decode_datagram(&datagram) {
+                Ok(frame_set) => self.handle_frame_set(frame_set).await,
+                Err(_) => self.events.malformed_packet(self.address, buffer),
+            },
+            _ => self.events.malformed_packet(self.address, buffer),
+        }
+        self.flush().await;
+    }
+
+    /// Handles a message from the `RakStream`. `None` means the stream was
+    /// dropped and the connection should be closed.
+    pub async fn handle_msg(&mut self, msg: Option<ToConnMsg>) {
+        match msg {
+            Some(ToConnMsg::Send(bytes, priority)) => {
+                if self.is_connected() {
+                    self.queue(Reliability::ReliableOrdered, priority, bytes);
+                    self.flush().await;
+                }
+            }
+            Some(ToConnMsg::Disconnect) | None => self.disconnect().await,
+        }
+    }
+
+    /// Drives every timer of the connection: handshake and idle timeouts,
+    /// keepalive pings, ACK/NACK flushing and resends.
+    pub async fn update(&mut self) {
+        if self.is_closed() {
+            return;
+        }
+
+        let now = self.clock.now();
+        match self.status {
+            ConnStatus::Connecting(_) if now >= self.created + HANDSHAKE_TIMEOUT => {
+                debug!(parent: &self.span, "handshake timed out");
+                self.close(DisconnectReason::TimedOut);
+                return;
+            }
+            ConnStatus::Connected | ConnStatus::Draining { .. }
+                if now >= self.last_receive + CONNECTION_TIMEOUT =>
+            {
+                debug!(parent: &self.span, "connection timed out");
+                self.close(DisconnectReason::TimedOut);
+                return;
+            }
+            ConnStatus::Draining {
+                ref reason,
+                deadline,
+            } if now >= deadline => {
+                self.close(reason.clone());
+                return;
+            }
+            _ => {}
+        }
+
+        if self.is_connected() && now >= self.last_ping + KEEPALIVE_INTERVAL {
+            self.last_ping = now;
+            let ping = ConnectedPing { time: self.time() };
+            self.queue_packet(ping, 0x00, Reliability::Unreliable);
+        }
+
+        self.send_acks().await;
+
+        let rto = self.rto();
+        let expired = self
+            .recovery
+            .iter()
+            .filter(|(_, datagram)| now >= datagram.sent + rto)
+            .map(|(sequence, _)| *sequence)
+            .collect::<Vec<_>>();
+        for sequence in expired {
+            if let Some(datagram) = self.take_in_flight(sequence) {
+                debug!(parent: &self.span, sequence, ?rto, "resending unacknowledged datagram");
+                self.congestion.on_loss(datagram.sent, now);
+                self.resend(datagram.frames);
+            }
+        }
+
+        self.flush().await;
+    }
+
+    /// The earliest instant `update` has work to do, or `None` once closed.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let mut deadline = match self.status {
+            ConnStatus::Connecting(_) => self.created + HANDSHAKE_TIMEOUT,
+            ConnStatus::Connected => {
+                (self.last_receive + CONNECTION_TIMEOUT).min(self.last_ping + KEEPALIVE_INTERVAL)
+            }
+            ConnStatus::Draining { deadline, .. } => {
+                deadline.min(self.last_receive + CONNECTION_TIMEOUT)
+            }
+            ConnStatus::Disconnected => return None,
+        };
+        if !self.ack_queue.is_empty() || !self.nack_queue.is_empty() {
+            deadline = self.clock.now();
+        }
+        if let Some(datagram) = self.oldest_in_flight() {
+            deadline = deadline.min(datagram.sent + self.rto());
+        }
+        // Frames held back by the bandwidth limits. Those held back by the
+        // congestion window wait for an ACK or the resend deadline instead.
+        if !self.outgoing.is_empty() && self.congestion.has_room(self.in_flight) {
+            deadline = deadline.min(self.send_ready_at());
+        }
+        Some(deadline)
     }
 
-    pub fn connect() -> Self {
-        todo!()
+    /// Sends a disconnect notification and closes the connection.
+    pub async fn disconnect(&mut self) {
+        self.disconnect_with(DisconnectReason::ClosedLocally).await;
     }
 
-    pub async fn handle(&mut self, buffer: &[u8]) {}
+    /// Like `disconnect`, reporting `reason` in the `Disconnected` event.
+    ///
+    /// A connected connection first sends what the bandwidth limits still
+    /// hold back, at the pace they allow, with the notification last. It
+    /// closes once that has been sent, or after `DRAIN_TIMEOUT`, when the
+    /// rest is dropped and counted in `frames_dropped`. Nothing sent or
+    /// received meanwhile reaches the stream.
+    pub async fn disconnect_with(&mut self, reason: DisconnectReason) {
+        match self.status {
+            ConnStatus::Connected => {}
+            ConnStatus::Connecting(_) => return self.shutdown(reason).await,
+            ConnStatus::Draining { .. } | ConnStatus::Disconnected => return,
+        }
+
+        self.status = ConnStatus::Draining {
+            reason,
+            deadline: self.clock.now() + DRAIN_TIMEOUT,
+        };
+        self.closed.store(true, Ordering::Release);
+        self.msg_sender.close_channel();
+        self.queue(Reliability::ReliableOrdered, Priority::Low, vec![0x15]);
+        self.flush().await;
+    }
+
+    /// Sends everything queued and the disconnect notification at once,
+    /// regardless of the bandwidth limits, and closes the connection. For
+    /// when there is no loop left to drain the queue from.
+    pub async fn shutdown(&mut self, reason: DisconnectReason) {
+        let reason = match &self.status {
+            ConnStatus::Disconnected => return,
+            ConnStatus::Draining { reason, .. } => reason.clone(),
+            _ => {
+                self.queue(Reliability::ReliableOrdered, Priority::Low, vec![0x15]);
+                reason
+            }
+        };
+        self.flush_frames(false).await;
+        self.close(reason);
+    }
+
+    fn is_draining(&self) -> bool {
+        matches!(self.status, ConnStatus::Draining { .. })
+    }
+
+    /// Counts the frames a disconnect gave up on.
+    fn report_dropped(&mut self) {
+        let dropped = self.outgoing.depth().iter().sum::<usize>();
+        if dropped > 0 {
+            debug!(parent: &self.span, dropped, "dropping frames queued at disconnect");
+            self.stats.frames_dropped += dropped as u64;
+            self.outgoing.clear();
+            self.publish_stats();
+        }
+    }
+
+    fn close(&mut self, reason: DisconnectReason) {
+        let event = match (&self.status, &reason) {
+            (ConnStatus::Connected | ConnStatus::Draining { .. }, reason) => {
+                Some(ListenerEvent::Disconnected {
+                    address: self.address,
+                    reason: reason.clone(),
+                })
+            }
+            (ConnStatus::Connecting(_), DisconnectReason::TimedOut) => {
+                Some(ListenerEvent::HandshakeFailed {
+                    address: self.address,
+                    reason: HandshakeFailure::TimedOut,
+                })
+            }
+            (ConnStatus::Connecting(_), DisconnectReason::ClosedByPeer) => {
+                Some(ListenerEvent::HandshakeFailed {
+                    address: self.address,
+                    reason: HandshakeFailure::ClosedByPeer,
+                })
+            }
+            _ => None,
+        };
+        if let Some(event) = event {
+            self.events.emit(event);
+        }
+        if self.is_draining() {
+            self.report_dropped();
+        }
+        debug!(parent: &self.span, ?reason, "connection closed");
+
+        self.status = ConnStatus::Disconnected;
+        self.closed.store(true, Ordering::Release);
+        self.msg_sender.close_channel();
+    }
+
+    fn set_connected(&mut self) {
+        if !self.is_connected() {
+            debug!(parent: &self.span, "connected");
+            self.status = ConnStatus::Connected;
+            self.connected_at = Some(self.clock.now());
+        }
+    }
+
+    fn time(&self) -> i64 {
+        self.clock.now().duration_since(self.created).as_millis() as i64
+    }
+
+    fn add_time_sample(&self, ping_time: i64, pong_time: i64) {
+        let now = self.time();
+        if let Ok(mut remote_clock) = self.remote_clock.lock() {
+            remote_clock.add_sample(ping_time, pong_time, now);
+        }
+    }
+
+    fn rto(&self) -> Duration {
+        match self.srtt {
+            Some(srtt) => (srtt + self.rttvar * 4).clamp(MIN_RTO, MAX_RTO),
+            None => INITIAL_RTO,
+        }
+    }
+
+    fn update_rtt(&mut self, sample: Duration) {
+        match self.srtt {
+            Some(srtt) => {
+                let diff = srtt.abs_diff(sample);
+                self.rttvar = (self.rttvar * 3 + diff) / 4;
+                self.srtt = Some((srtt * 7 + sample) / 8);
+            }
+            None => {
+                self.rttvar = sample / 2;
+                self.srtt = Some(sample);
+            }
+        }
+    }
+
+    fn max_frame_body(&self) -> usize {
+        self.mtu - UDP_HEADER_SIZE - FRAME_SET_HEADER_SIZE - MAX_FRAME_HEADER_SIZE
+    }
+
+    /// Queues a packet of the protocol itself, ahead of every payload.
+    fn queue_packet<P: Den>(&mut self, packet: P, id: u8, reliability: Reliability) {
+        if let Ok(body) = encode(packet, id) {
+            self.queue(reliability, Priority::Immediate, body);
+        }
+    }
+
+    fn queue(&mut self, reliability: Reliability, priority: Priority, body: Vec<u8>) {
+        let body = Bytes::from(body);
+        let mut frame = Frame::new(reliability, Bytes::new());
+        if reliability.is_sequenced() {
+            frame.order_index = self.order_index;
+            frame.sequence_index = self.sequence_index;
+            self.sequence_index = u24_add(self.sequence_index, 1);
+        } else if reliability.is_ordered() {
+            frame.order_index = self.order_index;
+            self.order_index = u24_add(self.order_index, 1);
+        }
+
+        let max = self.max_frame_body();
+        if body.len() <= max {
+            if reliability.is_reliable() {
+                frame.reliable_index = self.next_reliable_index();
+            }
+            frame.body = body;
+            self.outgoing.push_back(priority, frame);
+            return;
+        }
+
+        // Split packets are always sent reliably.
+        frame.reliability = match reliability {
+            Reliability::Unreliable => Reliability::Reliable,
+            Reliability::UnreliableSequenced => Reliability::ReliableSequenced,
+            Reliability::UnreliableWithAckReceipt => Reliability::ReliableWithAckReceipt,
+            reliability => reliability,
+        };
+        let count = body.len().div_ceil(max) as u32;
+        let id = self.split_id;
+        self.split_id = self.split_id.wrapping_add(1);
+        for (index, start) in (0..body.len()).step_by(max).enumerate() {
+            let mut piece = frame.clone();
+            piece.reliable_index = self.next_reliable_index();
+            piece.split = Some(Split {
+                count,
+                id,
+                index: index as u32,
+            });
+            piece.body = body.slice(start..(start + max).min(body.len()));
+            self.outgoing.push_back(priority, piece);
+        }
+    }
+
+    /// Resends get new sequence numbers, so the first one sent is the
+    /// lowest, except that sequences from before a wrap of the 24-bit
+    /// counter sort after the next one to be sent.
+    fn oldest_in_flight(&self) -> Option<&SentDatagram> {
+        self.recovery
+            .range(self.send_sequence..)
+            .chain(self.recovery.range(..self.send_sequence))
+            .map(|(_, datagram)| datagram)
+            .next()
+    }
+
+    fn next_reliable_index(&mut self) -> u32 {
+        let index = self.reliable_index;
+        self.reliable_index = u24_add(self.reliable_index, 1);
+        index
+    }
+
+    /// Queues lost frames again, ahead of the new ones of their priority.
+    fn resend(&mut self, frames: Vec<(Priority, Frame)>) {
+        self.stats.datagrams_lost += 1;
+        Metrics::add(&self.events.metrics().datagrams_resent, 1);
+        self.stats.frames_resent += frames.len() as u64;
+        for (priority, frame) in frames.into_iter().rev() {
+            self.outgoing.push_front(priority, frame);
+        }
+    }
+
+    /// Sends what the bandwidth limits allow, closing a draining connection
+    /// once nothing is left.
+    async fn flush(&mut self) {
+        self.flush_frames(true).await;
+        if let ConnStatus::Draining { reason, .. } = &self.status {
+            if self.outgoing.is_empty() {
+                self.close(reason.clone());
+            }
+        }
+    }
+
+    /// Packs the outgoing frames into frame sets and sends them. When
+    /// `paced`, stops once the bandwidth limits are used up and leaves the
+    /// rest queued for a later flush.
+    async fn flush_frames(&mut self, paced: bool) {
+        let max = self.mtu - UDP_HEADER_SIZE - FRAME_SET_HEADER_SIZE;
+        let now = self.clock.now();
+        let mut datagrams = vec![];
+        while !self.outgoing.is_empty() {
+            if paced && !(self.congestion.has_room(self.in_flight) && self.may_send(now)) {
+                break;
+            }
+            let mut frames = vec![];
+            let mut size = 0;
+            while let Some(frame) = self.outgoing.front() {
+                if !frames.is_empty() && size + frame.size() > max {
+                    break;
+                }
+                size += frame.size();
+                frames.extend(self.outgoing.pop_front());
+            }
+
+            let sequence = self.send_sequence;
+            self.send_sequence = u24_add(self.send_sequence, 1);
+            let reliable = frames
+                .iter()
+                .filter(|(_, frame)| frame.reliability.is_reliable())
+                .cloned()
+                .collect::<Vec<_>>();
+            let frames = frames.into_iter().map(|(_, frame)| frame).collect();
+            let Ok(buffer) = encode(FrameSet { sequence, frames }, FRAME_SET_ID) else {
+                continue;
+            };
+            self.charge(buffer.len());
+            if !reliable.is_empty() {
+                self.in_flight += buffer.len();
+                self.recovery.insert(
+                    sequence,
+                    SentDatagram {
+                        sent: now,
+                        size: buffer.len(),
+                        frames: reliable,
+                    },
+                );
+            }
+            datagrams.push(buffer);
+        }
+        if let Some(shared) = &mut self.shared_pacer {
+            let waiting = paced
+                && !self.outgoing.is_empty()
+                && self.congestion.has_room(self.in_flight)
+                && self.pacer.as_mut().is_none_or(|pacer| pacer.is_ready(now));
+            shared.set_waiting(waiting);
+        }
+        self.send_datagrams(&datagrams).await;
+        self.publish_stats();
+    }
+
+    fn take_in_flight(&mut self, sequence: u32) -> Option<SentDatagram> {
+        let datagram = self.recovery.remove(&sequence)?;
+        self.in_flight -= datagram.size;
+        Some(datagram)
+    }
+
+    /// Whether the bandwidth limits leave room for another datagram.
+    fn may_send(&mut self, now: Instant) -> bool {
+        let connection = self.pacer.as_mut().is_none_or(|pacer| pacer.is_ready(now));
+        let shared = self
+            .shared_pacer
+            .as_mut()
+            .is_none_or(|pacer| pacer.is_ready(now));
+        connection && shared
+    }
+
+    /// Counts `size` bytes against the bandwidth limits.
+    fn charge(&mut self, size: usize) {
+        if let Some(pacer) = &mut self.pacer {
+            pacer.consume(size);
+        }
+        if let Some(pacer) = &mut self.shared_pacer {
+            pacer.consume(size);
+        }
+    }
+
+    /// When the bandwidth limits next leave room for a datagram.
+    fn send_ready_at(&self) -> Instant {
+        let mut ready_at = self.clock.now();
+        if let Some(pacer) = &self.pacer {
+            ready_at = ready_at.max(pacer.ready_at());
+        }
+        if let Some(pacer) = &self.shared_pacer {
+            ready_at = ready_at.max(pacer.ready_at());
+        }
+        ready_at
+    }
+
+    fn publish_stats(&mut self) {
+        let stats = &mut self.stats;
+        stats.rtt = self.srtt;
+        stats.rtt_variance = self.rttvar;
+        stats.in_flight = self.recovery.len();
+        stats.queue_depth = self.outgoing.depth();
+        stats.congestion_window = self.congestion.window();
+        stats.packet_loss = if stats.frame_sets_sent == 0 {
+            0.0
+        } else {
+            stats.datagrams_lost as f64 / stats.frame_sets_sent as f64
+        };
+        stats.connected_for = self
+            .connected_at
+            .map(|connected_at| self.clock.now().saturating_duration_since(connected_at))
+            .unwrap_or_default();
+        if let Ok(mut shared) = self.shared_stats.lock() {
+            shared.clone_from(stats);
+        }
+    }
+
+    async fn send_acks(&mut self) {
+        if !self.ack_queue.is_empty() {
+            let ack = Ack::from_sequences(std::mem::take(&mut self.ack_queue));
+            if let Ok(buffer) = encode(ack, ACK_ID) {
+                self.send_raw(&buffer).await;
+            }
+        }
+
+        if !self.nack_queue.is_empty() {
+            self.stats.nacks_sent += self.nack_queue.len() as u64;
+            let nack = Ack::from_sequences(self.nack_queue.drain().collect());
+            if let Ok(buffer) = encode(nack, NACK_ID) {
+                self.send_raw(&buffer).await;
+            }
+        }
+    }
+
+    async fn send_raw(&mut self, buffer: &[u8]) {
+        // ACKs are never held back, but they still use up bandwidth.
+        self.charge(buffer.len());
+        match self.socket.send_to(buffer, self.address).await {
+            Ok(size) => {
+                self.capture.sent(self.address, buffer);
+                self.stats.bytes_sent += size as u64;
+                Metrics::add(&self.events.metrics().bytes_sent, size as u64);
+                self.stats.datagrams_sent += 1;
+            }
+            Err(err) => self.events.socket_error(err),
+        }
+    }
+
+    /// Sends a flush's frame sets in as few syscalls as the platform allows.
+    async fn send_datagrams(&mut self, datagrams: &[Vec<u8>]) {
+        if datagrams.is_empty() {
+            return;
+        }
+        let (sent, result) = self.socket.send_many(datagrams, self.address).await;
+        let metrics = self.events.metrics();
+        for buffer in &datagrams[..sent] {
+            self.capture.sent(self.address, buffer);
+            self.stats.bytes_sent += buffer.len() as u64;
+            Metrics::add(&metrics.bytes_sent, buffer.len() as u64);
+            self.stats.datagrams_sent += 1;
+            self.stats.frame_sets_sent += 1;
+        }
+        Metrics::add(&metrics.datagrams_sent, sent as u64);
+        // The reliable frames of the datagrams left unsent are resent once
+        // their timeout expires, like those of a lost datagram.
+        if let Err(err) = result {
+            self.events.socket_error(err);
+        }
+    }
+
+    fn handle_ack(&mut self, ack: Ack) {
+        let now = self.clock.now();
+        for sequence in ack.sequences() {
+            if let Some(datagram) = self.take_in_flight(sequence) {
+                self.update_rtt(now.saturating_duration_since(datagram.sent));
+                self.congestion.on_ack(datagram.size);
+            }
+        }
+    }
+
+    fn handle_nack(&mut self, nack: Ack) {
+        let now = self.clock.now();
+        for sequence in nack.sequences() {
+            self.stats.nacks_received += 1;
+            if let Some(datagram) = self.take_in_flight(sequence) {
+                debug!(parent: &self.span, sequence, "resending datagram reported lost");
+                self.congestion.on_loss(datagram.sent, now);
+                self.resend(datagram.frames);
+            }
+        }
+    }
+
+    async fn handle_frame_set(&mut self, frame_set: FrameSet) {
+        let sequence = frame_set.sequence;
+        self.ack_queue.push(sequence);
+        self.nack_queue.remove(&sequence);
+        if !u24_before(sequence, self.expected_sequence) {
+            let gap = u24_distance(self.expected_sequence, sequence);
+            if gap <= MAX_NACK_GAP {
+                let expected = self.expected_sequence;
+                self.nack_queue
+                    .extend((0..gap).map(|offset| u24_add(expected, offset)));
+            }
+            self.expected_sequence = u24_add(sequence, 1);
+        }
+
+        for frame in frame_set.frames {
+            self.handle_frame(frame).await;
+        }
+    }
+
+    async fn handle_frame(&mut self, frame: Frame) {
+        if frame.reliability.is_reliable() && !self.accept_reliable(frame.reliable_index) {
+            return;
+        }
+
+        let frame = match frame.split {
+            Some(split) => match self.reassemble(frame, split) {
+                Some(frame) => frame,
+                None => return,
+            },
+            None => frame,
+        };
+
+        let channel = frame.order_channel as usize;
+        if frame.reliability.is_ordered() && channel >= ORDER_CHANNELS {
+            return;
+        }
+
+        if frame.reliability.is_sequenced() {
+            if u24_before(frame.sequence_index, self.highest_sequence[channel]) {
+                return;
+            }
+            self.highest_sequence[channel] = u24_add(frame.sequence_index, 1);
+            self.handle_body(frame.body).await;
+        } else if frame.reliability.is_ordered() {
+            let expected = self.expected_order[channel];
+            if frame.order_index == expected {
+                self.expected_order[channel] = u24_add(expected, 1);
+                self.handle_body(frame.body).await;
+                while let Some(body) =
+                    self.order_queue[channel].remove(&self.expected_order[channel])
+                {
+                    self.expected_order[channel] = u24_add(self.expected_order[channel], 1);
+                    self.handle_body(body).await;
+                }
+            } else if !u24_before(frame.order_index, expected)
+                && self.order_queue[channel].len() < MAX_ORDER_QUEUE
+            {
+                self.order_queue[channel].insert(frame.order_index, frame.body);
+            }
+        } else {
+            self.handle_body(frame.body).await;
+        }
+    }
+
+    fn accept_reliable(&mut self, index: u32) -> bool {
+        // Indices before the window are a long way ahead of its start.
+        if u24_distance(self.reliable_window_start, index) >= MAX_RELIABLE_WINDOW
+            || !self.reliable_window.insert(index)
+        {
+            return false;
+        }
+
+        while self.reliable_window.remove(&self.reliable_window_start) {
+            self.reliable_window_start = u24_add(self.reliable_window_start, 1);
+        }
+        true
+    }
+
+    fn reassemble(&mut self, mut frame: Frame, split: Split) -> Option<Frame> {
+        if split.count == 0 || split.count > MAX_SPLIT_COUNT || split.index >= split.count {
+            return None;
+        }
+        if !self.splits.contains_key(&split.id) && self.splits.len() >= MAX_SPLIT_PACKETS {
+            return None;
+        }
+
+        let parts = self
+            .splits
+            .entry(split.id)
+            .or_insert_with(|| vec![None; split.count as usize]);
+        if parts.len() != split.count as usize {
+            return None;
+        }
+        parts[split.index as usize] = Some(std::mem::take(&mut frame.body));
+        if parts.iter().any(Option::is_none) {
+            return None;
+        }
+
+        let parts = self.splits.remove(&split.id)?;
+        let mut body = BytesMut::with_capacity(parts.iter().flatten().map(Bytes::len).sum());
+        for part in parts.into_iter().flatten() {
+            body.extend_from_slice(&part);
+        }
+        frame.body = body.freeze();
+        frame.split = None;
+        Some(frame)
+    }
+
+    async fn handle_body(&mut self, body: Bytes) {
+        if body.is_empty() {
+            return;
+        }
+
+        match (body[0], &self.conn_type, &self.status) {
+            (0x00, _, _) => {
+                if let Ok(ping) = decode::<ConnectedPing>(&body) {
+                    let pong = ConnectedPong {
+                        ping_time: ping.time,
+                        pong_time: self.time(),
+                    };
+                    self.queue_packet(pong, 0x03, Reliability::Unreliable);
+                }
+            }
+            (0x03, _, _) => {
+                if let Ok(pong) = decode::<ConnectedPong>(&body) {
+                    self.add_time_sample(pong.ping_time, pong.pong_time);
+                }
+            }
+            (
+                0x09,
+                ConnType::Incoming,
+                ConnStatus::Connecting(ConnectStatus::WaitingConnectionRequest),
+            ) => {
+                if let Ok(request) = decode::<ConnectionRequest>(&body) {
+                    self.span.record("client_guid", request.guid);
+                    debug!(parent: &self.span, "accepting connection request");
+                    let accepted = ConnectionRequestAccepted {
+                        client_address: self.address,
+                        system_index: 0,
+                        request_time: request.time,
+                        time: self.time(),
+                    };
+                    self.queue_packet(accepted, 0x10, Reliability::Reliable);
+                    self.status =
+                        ConnStatus::Connecting(ConnectStatus::WaitingNewIncomingConnection);
+                }
+            }
+            (
+                0x10,
+                ConnType::Outgoing,
+                ConnStatus::Connecting(ConnectStatus::WaitingConnectionRequestAccepted),
+            ) => {
+                if let Ok(accepted) = decode::<ConnectionRequestAccepted>(&body) {
+                    self.add_time_sample(accepted.request_time, accepted.time);
+                    let new_incoming_connection = NewIncomingConnection {
+                        server_address: self.address,
+                        ping_time: accepted.time,
+                        pong_time: self.time(),
+                    };
+                    self.queue_packet(new_incoming_connection, 0x13, Reliability::Reliable);
+                    self.set_connected();
+                }
+            }
+            (
+                0x13,
+                ConnType::Incoming,
+                ConnStatus::Connecting(ConnectStatus::WaitingNewIncomingConnection),
+            ) => {
+                if let Ok(incoming) = decode::<NewIncomingConnection>(&body) {
+                    self.add_time_sample(incoming.ping_time, incoming.pong_time);
+                }
+                self.set_connected();
+            }
+            (0x15, _, _) => self.close(DisconnectReason::ClosedByPeer),
+            (
+                _,
+                ConnType::Incoming,
+                ConnStatus::Connecting(ConnectStatus::WaitingNewIncomingConnection),
+            )
+            | (_, _, ConnStatus::Connected) => {
+                // A lost NewIncomingConnection must not hold back the data
+                // that follows it.
+                self.set_connected();
+                _ = self.msg_sender.unbounded_send(ToStreamMsg::Packet(body));
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::{future::timeout, net::UdpSocket, task};
+
+    use super::*;
+    use crate::clock::ManualClock;
+
+    /// A few datagrams short of the 24-bit limit.
+    const INITIAL_INDEX: u32 = 0xff_fff0;
+
+    struct Peer {
+        socket: Arc<UdpSocket>,
+        conn: Conn,
+        receiver: mpsc::UnboundedReceiver<ToStreamMsg>,
+    }
+
+    impl Peer {
+        /// Handles every datagram that has arrived for this side.
+        async fn receive(&mut self) {
+            let mut buffer = [0u8; 2048];
+            let wait = Duration::from_millis(20);
+            while let Ok(Ok((size, _))) = timeout(wait, self.socket.recv_from(&mut buffer)).await {
+                self.conn
+                    .handle(Bytes::copy_from_slice(&buffer[..size]))
+                    .await;
+            }
+        }
+
+        fn delivered(&mut self) -> Vec<Bytes> {
+            let mut delivered = vec![];
+            while let Ok(ToStreamMsg::Packet(payload)) = self.receiver.try_recv() {
+                delivered.push(payload);
+            }
+            delivered
+        }
+    }
+
+    async fn peers(clock: &ManualClock) -> (Peer, Peer) {
+        let client_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let server_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let client_addr = client_socket.local_addr().unwrap();
+        let server_addr = server_socket.local_addr().unwrap();
+        let clock: Arc<dyn Clock> = Arc::new(clock.clone());
+
+        let (sender, client_receiver) = mpsc::unbounded();
+        let mut client = Conn::outgoing_connection(
+            Arc::new(DatagramSocket::new(client_socket.clone())),
+            server_addr,
+            1,
+            1400,
+            clock.clone(),
+            EventBroadcaster::default(),
+            CaptureHook::default(),
+            sender,
+            BandwidthLimits::default(),
+        );
+        let (sender, server_receiver) = mpsc::unbounded();
+        let mut server = Conn::incoming_connection(
+            Arc::new(DatagramSocket::new(server_socket.clone())),
+            client_addr,
+            2,
+            1400,
+            clock,
+            EventBroadcaster::default(),
+            CaptureHook::default(),
+            sender,
+            BandwidthLimits::default(),
+        );
+        client.set_initial_index(INITIAL_INDEX);
+        server.set_initial_index(INITIAL_INDEX);
+        let client = Peer {
+            socket: client_socket,
+            conn: client,
+            receiver: client_receiver,
+        };
+        let server = Peer {
+            socket: server_socket,
+            conn: server,
+            receiver: server_receiver,
+        };
+        (client, server)
+    }
+
+    fn payload(i: usize, size: usize) -> Vec<u8> {
+        let mut payload = vec![i as u8; size];
+        payload[0] = 0xfe;
+        payload
+    }
+
+    #[test]
+    fn counters_wrap_at_24_bits() {
+        task::block_on(async {
+            // Nothing is resent unless the clock moves, so any resend below
+            // would come from an ACK that failed to match across the wrap.
+            let clock = ManualClock::new();
+            let (mut client, mut server) = peers(&clock).await;
+            client.conn.request_connection().await;
+            for _ in 0..10 {
+                server.receive().await;
+                client.receive().await;
+                if client.conn.is_connected() && server.conn.is_connected() {
+                    break;
+                }
+            }
+            assert!(client.conn.is_connected() && server.conn.is_connected());
+
+            // Small payloads move the sequence numbers and indices one at a
+            // time; the split ones use many reliable indices at once.
+            let sizes = (0..64)
+                .map(|i| if i % 8 == 7 { 5000 } else { 100 })
+                .collect::<Vec<_>>();
+            for (i, &size) in sizes.iter().enumerate() {
+                for peer in [&mut client, &mut server] {
+                    let msg = ToConnMsg::Send(payload(i, size), Priority::Medium);
+                    peer.conn.handle_msg(Some(msg)).await;
+                }
+            }
+            let mut received = (vec![], vec![]);
+            for _ in 0..100 {
+                server.receive().await;
+                client.receive().await;
+                server.conn.update().await;
+                client.conn.update().await;
+                received.0.extend(client.delivered());
+                received.1.extend(server.delivered());
+                let settled = client.conn.recovery.is_empty() && server.conn.recovery.is_empty();
+                if settled && received.0.len() == 64 && received.1.len() == 64 {
+                    break;
+                }
+            }
+
+            let expected = sizes
+                .iter()
+                .enumerate()
+                .map(|(i, &size)| Bytes::from(payload(i, size)))
+                .collect::<Vec<_>>();
+            assert_eq!(received.0, expected);
+            assert_eq!(received.1, expected);
+            for peer in [&client, &server] {
+                // Both counters went past the wrap.
+                assert!(peer.conn.send_sequence < INITIAL_INDEX);
+                assert!(peer.conn.reliable_index < INITIAL_INDEX);
+                assert!(peer.conn.recovery.is_empty());
+                assert_eq!(peer.conn.stats.frames_resent, 0);
+                assert_eq!(peer.conn.stats.nacks_sent, 0);
+            }
+        });
+    }
 }
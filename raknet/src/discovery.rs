@@ -0,0 +1,133 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    ops::RangeInclusive,
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    clock::{default_clock, Clock},
+    packets::*,
+    stream::{bind_unspecified, is_unreachable, receive_timeout},
+    ServerAdvertisement,
+};
+
+pub const DEFAULT_LAN_PORT: u16 = 19132;
+
+/// Options for `scan_lan_with_config`.
+#[derive(Clone)]
+pub struct ScanConfig {
+    pub broadcast_address: IpAddr,
+    pub ports: RangeInclusive<u16>,
+    /// How long to collect responses for.
+    pub duration: Duration,
+    pub guid: i64,
+    pub clock: Arc<dyn Clock>,
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        Self {
+            broadcast_address: IpAddr::V4(Ipv4Addr::BROADCAST),
+            ports: DEFAULT_LAN_PORT..=DEFAULT_LAN_PORT + 1,
+            duration: Duration::from_secs(1),
+            guid: RandomState::new().build_hasher().finish() as i64,
+            clock: default_clock(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DiscoveredServer {
+    pub address: SocketAddr,
+    pub pong: UnconnectedPong,
+}
+
+impl DiscoveredServer {
+    pub fn advertisement(&self) -> std::io::Result<ServerAdvertisement> {
+        self.pong.server_id.parse()
+    }
+}
+
+/// Options for `Listener::start_advertising`.
+#[derive(Debug, Clone)]
+pub struct AdvertiseConfig {
+    pub targets: Vec<SocketAddr>,
+    pub interval: Duration,
+}
+
+impl Default for AdvertiseConfig {
+    fn default() -> Self {
+        Self {
+            targets: vec![SocketAddr::new(
+                IpAddr::V4(Ipv4Addr::BROADCAST),
+                DEFAULT_LAN_PORT,
+            )],
+            interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Broadcasts open-connections pings and collects every server that answers
+/// or advertises itself while the scan runs.
+pub async fn scan_lan() -> std::io::Result<Vec<DiscoveredServer>> {
+    scan_lan_with_config(ScanConfig::default()).await
+}
+
+pub async fn scan_lan_with_config(config: ScanConfig) -> std::io::Result<Vec<DiscoveredServer>> {
+    let socket = bind_unspecified(SocketAddr::new(config.broadcast_address, 0)).await?;
+    socket.set_broadcast(true)?;
+
+    let clock = &config.clock;
+    let start = clock.now();
+    let ping = UnconnectedPing {
+        time: 0,
+        magic: true,
+        client_guid: config.guid,
+    };
+    let ping = encode(ping, 0x2)?;
+    for port in config.ports.clone() {
+        let target = SocketAddr::new(config.broadcast_address, port);
+        match socket.send_to(&ping, target).await {
+            Err(err) if !is_unreachable(&err) => return Err(err),
+            _ => {}
+        }
+    }
+
+    let mut servers = HashMap::new();
+    let mut buffer = [0u8; 4096];
+    let deadline = start + config.duration;
+    loop {
+        let remaining = deadline.saturating_duration_since(clock.now());
+        if remaining.is_zero() {
+            break;
+        }
+        let (size, from) = match receive_timeout(&socket, &mut buffer, clock, remaining).await {
+            Ok(Some(received)) => received,
+            Ok(None) => continue,
+            // A port in the range with nothing behind it.
+            Err(err) if is_unreachable(&err) => continue,
+            Err(err) => return Err(err),
+        };
+        // Advertise System (0x1d) shares the pong layout.
+        if size == 0 || !matches!(buffer[0], 0x1c | 0x1d) {
+            continue;
+        }
+        match decode::<UnconnectedPong>(&buffer[..size]) {
+            Ok(pong) if pong.magic => {
+                servers.insert(
+                    from,
+                    DiscoveredServer {
+                        address: from,
+                        pong,
+                    },
+                );
+            }
+            _ => {}
+        }
+    }
+
+    Ok(servers.into_values().collect())
+}
@@ -0,0 +1,102 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use futures::channel::mpsc;
+
+use crate::{
+    accept::RejectReason,
+    instrument::{debug, warning},
+    metrics::Metrics,
+    StreamInformation,
+};
+
+const EVENT_BUFFER: usize = 64;
+
+#[derive(Debug, Clone)]
+pub enum ListenerEvent {
+    /// Sending or receiving on the listener socket failed. The listener keeps
+    /// running.
+    SocketError(Arc<std::io::Error>),
+    /// A datagram that could not be decoded was dropped.
+    MalformedPacket {
+        address: SocketAddr,
+        id: u8,
+    },
+    HandshakeFailed {
+        address: SocketAddr,
+        reason: HandshakeFailure,
+    },
+    Connected(StreamInformation),
+    Disconnected {
+        address: SocketAddr,
+        reason: DisconnectReason,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeFailure {
+    IncompatibleProtocol {
+        client_protocol: u8,
+    },
+    AlreadyConnected,
+    TimedOut,
+    ClosedByPeer,
+    /// Refused by the accept filter with the given reply.
+    Rejected(RejectReason),
+    /// Dropped by the accept filter without a reply, or because too many
+    /// attempts were already waiting on it.
+    Dropped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisconnectReason {
+    TimedOut,
+    ClosedByPeer,
+    ClosedLocally,
+    /// Closed through `Listener::kick`, with the reason given there.
+    Kicked(String),
+}
+
+/// Fans events out to every receiver returned by `Listener::events`.
+///
+/// Events are dropped for subscribers that fall behind instead of stalling
+/// the listener loop. Every event is also tallied in the listener metrics.
+#[derive(Clone, Default)]
+pub(crate) struct EventBroadcaster {
+    subscribers: Arc<Mutex<Vec<mpsc::Sender<ListenerEvent>>>>,
+    metrics: Arc<Metrics>,
+}
+
+impl EventBroadcaster {
+    pub fn subscribe(&self) -> mpsc::Receiver<ListenerEvent> {
+        let (sender, receiver) = mpsc::channel(EVENT_BUFFER);
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn emit(&self, event: ListenerEvent) {
+        self.metrics.record_event(&event);
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain_mut(|subscriber| match subscriber.try_send(event.clone()) {
+            Ok(()) => true,
+            Err(err) => err.is_full(),
+        });
+    }
+
+    pub fn socket_error(&self, err: std::io::Error) {
+        warning!(error = %err, "socket error");
+        self.emit(ListenerEvent::SocketError(Arc::new(err)));
+    }
+
+    pub fn malformed_packet(&self, address: SocketAddr, buffer: &[u8]) {
+        let id = buffer.first().copied().unwrap_or_default();
+        debug!(%address, id, len = buffer.len(), "dropped malformed packet");
+        self.emit(ListenerEvent::MalformedPacket { address, id });
+    }
+}
@@ -0,0 +1,356 @@
+use std::io::{Cursor, Error, ErrorKind, Write};
+
+use byte_util::{Big, Den, DenWith};
+use bytes::Bytes;
+
+use crate::bytes::U24;
+
+pub const FRAME_SET_ID: u8 = 0x84;
+pub const ACK_ID: u8 = 0xc0;
+pub const NACK_ID: u8 = 0xa0;
+
+/// Largest range a single ACK/NACK record is allowed to expand to.
+const MAX_ACK_RANGE: u32 = 8192;
+
+/// Datagram sequence numbers and the reliable, sequenced and ordered
+/// indices travel as U24 and wrap around at 2^24.
+pub const U24_MASK: u32 = 0xff_ffff;
+
+/// `index + n`, wrapped to 24 bits.
+pub fn u24_add(index: u32, n: u32) -> u32 {
+    index.wrapping_add(n) & U24_MASK
+}
+
+/// How far `index` is ahead of `base`, wrapped to 24 bits.
+pub fn u24_distance(base: u32, index: u32) -> u32 {
+    index.wrapping_sub(base) & U24_MASK
+}
+
+/// Serial number comparison (RFC 1982): whether `index` comes before
+/// `base`, taking the half of the 24-bit space behind `base` as the past.
+pub fn u24_before(index: u32, base: u32) -> bool {
+    u24_distance(base, index) > U24_MASK / 2
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reliability {
+    Unreliable,
+    UnreliableSequenced,
+    Reliable,
+    ReliableOrdered,
+    ReliableSequenced,
+    UnreliableWithAckReceipt,
+    ReliableWithAckReceipt,
+    ReliableOrderedWithAckReceipt,
+}
+
+/// How urgently a packet is sent. Queued frames of a higher priority go
+/// out before those of a lower one, and keep their order within a priority.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Priority {
+    /// Used for the handshake and pings.
+    Immediate,
+    High,
+    #[default]
+    Medium,
+    Low,
+}
+
+impl Priority {
+    /// Every priority, from the most to the least urgent. Indexes
+    /// `ConnectionStats::queue_depth` in this order.
+    pub const ALL: [Priority; 4] = [Self::Immediate, Self::High, Self::Medium, Self::Low];
+}
+
+impl Reliability {
+    fn from_u8(v: u8) -> std::io::Result<Self> {
+        Ok(match v {
+            0 => Self::Unreliable,
+            1 => Self::UnreliableSequenced,
+            2 => Self::Reliable,
+            3 => Self::ReliableOrdered,
+            4 => Self::ReliableSequenced,
+            5 => Self::UnreliableWithAckReceipt,
+            6 => Self::ReliableWithAckReceipt,
+            7 => Self::ReliableOrderedWithAckReceipt,
+            _ => return Err(Error::new(ErrorKind::InvalidData, "invalid reliability")),
+        })
+    }
+
+    fn to_u8(self) -> u8 {
+        self as u8
+    }
+
+    pub fn is_reliable(self) -> bool {
+        matches!(
+            self,
+            Self::Reliable
+                | Self::ReliableOrdered
+                | Self::ReliableSequenced
+                | Self::ReliableWithAckReceipt
+                | Self::ReliableOrderedWithAckReceipt
+        )
+    }
+
+    pub fn is_sequenced(self) -> bool {
+        matches!(self, Self::UnreliableSequenced | Self::ReliableSequenced)
+    }
+
+    pub fn is_ordered(self) -> bool {
+        matches!(
+            self,
+            Self::UnreliableSequenced
+                | Self::ReliableOrdered
+                | Self::ReliableSequenced
+                | Self::ReliableOrderedWithAckReceipt
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Split {
+    pub count: u32,
+    pub id: u16,
+    pub index: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub reliability: Reliability,
+    pub reliable_index: u32,
+    pub sequence_index: u32,
+    pub order_index: u32,
+    pub order_channel: u8,
+    pub split: Option<Split>,
+    pub body: Bytes,
+}
+
+impl Frame {
+    pub fn new(reliability: Reliability, body: Bytes) -> Self {
+        Self {
+            reliability,
+            reliable_index: 0,
+            sequence_index: 0,
+            order_index: 0,
+            order_channel: 0,
+            split: None,
+            body,
+        }
+    }
+
+    /// Decodes a frame from a cursor over `datagram`, slicing the body out of
+    /// it instead of copying.
+    pub fn decode_from(bytes: &mut Cursor<&[u8]>, datagram: &Bytes) -> std::io::Result<Self> {
+        let flags: u8 = Den::decode(bytes)?;
+        let reliability = Reliability::from_u8(flags >> 5)?;
+        let length = (<Big as DenWith<u16>>::decode(bytes)? as usize).div_ceil(8);
+        let mut frame = Frame::new(reliability, Bytes::new());
+        if reliability.is_reliable() {
+            frame.reliable_index = U24::decode(bytes)?;
+        }
+        if reliability.is_sequenced() {
+            frame.sequence_index = U24::decode(bytes)?;
+        }
+        if reliability.is_ordered() {
+            frame.order_index = U24::decode(bytes)?;
+            frame.order_channel = Den::decode(bytes)?;
+        }
+        if flags & 0x10 != 0 {
+            frame.split = Some(Split {
+                count: <Big as DenWith<u32>>::decode(bytes)?,
+                id: <Big as DenWith<u16>>::decode(bytes)?,
+                index: <Big as DenWith<u32>>::decode(bytes)?,
+            });
+        }
+        let remaining = bytes.get_ref().len() - bytes.position() as usize;
+        if length == 0 || length > remaining {
+            return Err(Error::new(ErrorKind::InvalidData, "invalid frame length"));
+        }
+        let start = bytes.position() as usize;
+        frame.body = datagram.slice(start..start + length);
+        bytes.set_position((start + length) as u64);
+        Ok(frame)
+    }
+}
+
+impl Den for Frame {
+    fn decode(bytes: &mut Cursor<&[u8]>) -> std::io::Result<Self> {
+        let datagram = Bytes::copy_from_slice(bytes.get_ref());
+        Self::decode_from(bytes, &datagram)
+    }
+
+    fn encode(&self, bytes: &mut Cursor<Vec<u8>>) -> std::io::Result<()> {
+        let mut flags = self.reliability.to_u8() << 5;
+        if self.split.is_some() {
+            flags |= 0x10;
+        }
+        Den::encode(&flags, bytes)?;
+        <Big as DenWith<u16>>::encode(&((self.body.len() * 8) as u16), bytes)?;
+        if self.reliability.is_reliable() {
+            U24::encode(&self.reliable_index, bytes)?;
+        }
+        if self.reliability.is_sequenced() {
+            U24::encode(&self.sequence_index, bytes)?;
+        }
+        if self.reliability.is_ordered() {
+            U24::encode(&self.order_index, bytes)?;
+            Den::encode(&self.order_channel, bytes)?;
+        }
+        if let Some(split) = self.split {
+            <Big as DenWith<u32>>::encode(&split.count, bytes)?;
+            <Big as DenWith<u16>>::encode(&split.id, bytes)?;
+            <Big as DenWith<u32>>::encode(&split.index, bytes)?;
+        }
+        bytes.write_all(&self.body)
+    }
+
+    fn size(&self) -> usize {
+        let mut size = 3 + self.body.len();
+        if self.reliability.is_reliable() {
+            size += 3;
+        }
+        if self.reliability.is_sequenced() {
+            size += 3;
+        }
+        if self.reliability.is_ordered() {
+            size += 4;
+        }
+        if self.split.is_some() {
+            size += 10;
+        }
+        size
+    }
+}
+
+/// A connected datagram carrying one or more frames. The leading flag byte
+/// is the packet ID and is handled by `encode`/`decode`.
+#[derive(Clone, Debug)]
+pub struct FrameSet {
+    pub sequence: u32,
+    pub frames: Vec<Frame>,
+}
+
+impl FrameSet {
+    /// Decodes a whole datagram, ID included. Frame bodies share `datagram`'s
+    /// buffer.
+    pub fn decode_datagram(datagram: &Bytes) -> std::io::Result<Self> {
+        let mut bytes = Cursor::new(&datagram[..]);
+        bytes.set_position(1);
+        Self::decode_from(&mut bytes, datagram)
+    }
+
+    fn decode_from(bytes: &mut Cursor<&[u8]>, datagram: &Bytes) -> std::io::Result<Self> {
+        let sequence = U24::decode(bytes)?;
+        let mut frames = vec![];
+        while (bytes.position() as usize) < bytes.get_ref().len() {
+            frames.push(Frame::decode_from(bytes, datagram)?);
+        }
+        Ok(Self { sequence, frames })
+    }
+}
+
+impl Den for FrameSet {
+    fn decode(bytes: &mut Cursor<&[u8]>) -> std::io::Result<Self> {
+        let datagram = Bytes::copy_from_slice(bytes.get_ref());
+        Self::decode_from(bytes, &datagram)
+    }
+
+    fn encode(&self, bytes: &mut Cursor<Vec<u8>>) -> std::io::Result<()> {
+        U24::encode(&self.sequence, bytes)?;
+        for frame in &self.frames {
+            frame.encode(bytes)?;
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> usize {
+        3 + self.frames.iter().map(Den::size).sum::<usize>()
+    }
+}
+
+/// Body of an ACK (0xc0) or NACK (0xa0) datagram.
+#[derive(Clone, Debug, Default)]
+pub struct Ack {
+    pub records: Vec<(u32, u32)>,
+}
+
+impl Ack {
+    /// Builds records from sequence numbers, merging consecutive runs.
+    pub fn from_sequences(mut sequences: Vec<u32>) -> Self {
+        sequences.sort_unstable();
+        sequences.dedup();
+        let mut records: Vec<(u32, u32)> = vec![];
+        for sequence in sequences {
+            match records.last_mut() {
+                Some((_, end)) if *end + 1 == sequence => *end = sequence,
+                _ => records.push((sequence, sequence)),
+            }
+        }
+        Self { records }
+    }
+
+    pub fn sequences(&self) -> impl Iterator<Item = u32> + '_ {
+        self.records
+            .iter()
+            .flat_map(|(start, end)| *start..=(*end).min(start.saturating_add(MAX_ACK_RANGE)))
+    }
+}
+
+impl Den for Ack {
+    fn decode(bytes: &mut Cursor<&[u8]>) -> std::io::Result<Self> {
+        let count = <Big as DenWith<u16>>::decode(bytes)?;
+        let mut records = vec![];
+        for _ in 0..count {
+            let single: bool = Den::decode(bytes)?;
+            let start = U24::decode(bytes)?;
+            let end = if single { start } else { U24::decode(bytes)? };
+            if end < start {
+                return Err(Error::new(ErrorKind::InvalidData, "invalid ack range"));
+            }
+            records.push((start, end));
+        }
+        Ok(Self { records })
+    }
+
+    fn encode(&self, bytes: &mut Cursor<Vec<u8>>) -> std::io::Result<()> {
+        <Big as DenWith<u16>>::encode(&(self.records.len() as u16), bytes)?;
+        for (start, end) in &self.records {
+            Den::encode(&(start == end), bytes)?;
+            U24::encode(start, bytes)?;
+            if start != end {
+                U24::encode(end, bytes)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> usize {
+        2 + self
+            .records
+            .iter()
+            .map(|(start, end)| if start == end { 4 } else { 7 })
+            .sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn u24_helpers_wrap() {
+        assert_eq!(u24_add(U24_MASK, 1), 0);
+        assert_eq!(u24_add(0xff_fff0, 0x20), 0x10);
+        assert_eq!(u24_distance(0xff_fff0, 0x10), 0x20);
+        assert_eq!(u24_distance(7, 7), 0);
+
+        // Across the wrap, the lower number is the later one.
+        assert!(u24_before(0xff_fff0, 0x10));
+        assert!(!u24_before(0x10, 0xff_fff0));
+        assert!(!u24_before(7, 7));
+        // Half the space behind `base` counts as the past, the rest as the
+        // future.
+        assert!(u24_before(u24_add(7, U24_MASK / 2 + 1), 7));
+        assert!(!u24_before(u24_add(7, U24_MASK / 2), 7));
+    }
+}
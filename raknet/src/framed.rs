@@ -0,0 +1,138 @@
+use std::{fmt, io::Cursor, marker::PhantomData};
+
+use byte_util::Den;
+use bytes::Bytes;
+
+use crate::{RakStream, RakStreamReceiver, RakStreamSender};
+
+/// A payload that did not decode as the packet type of a `Framed` stream.
+#[derive(Debug)]
+pub struct DecodeError {
+    /// The payload as it was received, starting with its packet ID.
+    pub payload: Bytes,
+    pub error: std::io::Error,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.payload.first() {
+            Some(id) => write!(f, "failed to decode packet {id:#04x}: {}", self.error),
+            None => write!(f, "failed to decode empty packet: {}", self.error),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// A `RakStream` that sends and receives packets of type `P`.
+///
+/// `P` is usually a packet enum deriving `Den`, whose encoding starts with
+/// the packet ID:
+///
+/// ```ignore
+/// #[derive(Den)]
+/// enum Packet {
+///     #[den(id = 0xfe)]
+///     Text(Text),
+/// }
+/// ```
+pub struct Framed<P> {
+    stream: RakStream,
+    packet: PhantomData<fn() -> P>,
+}
+
+impl<P: Den> Framed<P> {
+    pub fn new(stream: RakStream) -> Self {
+        Self {
+            stream,
+            packet: PhantomData,
+        }
+    }
+
+    /// Sends `packet`, failing with `NotConnected` once the connection is
+    /// closed.
+    pub async fn send(&mut self, packet: P) -> std::io::Result<()> {
+        self.stream.try_send(encode(&packet)?).await
+    }
+
+    /// Receives the next packet, or `None` once the connection is closed.
+    pub async fn receive(&mut self) -> Option<Result<P, DecodeError>> {
+        self.stream.receive_bytes().await.map(decode)
+    }
+
+    pub fn split(self) -> (FramedSender<P>, FramedReceiver<P>) {
+        let (sender, receiver) = self.stream.split();
+        (
+            FramedSender {
+                sender,
+                packet: PhantomData,
+            },
+            FramedReceiver {
+                receiver,
+                packet: PhantomData,
+            },
+        )
+    }
+
+    pub fn into_inner(self) -> RakStream {
+        self.stream
+    }
+
+    pub fn disconnect(self) {
+        self.stream.disconnect();
+    }
+}
+
+pub struct FramedSender<P> {
+    sender: RakStreamSender,
+    packet: PhantomData<fn() -> P>,
+}
+
+impl<P: Den> FramedSender<P> {
+    /// Sends `packet`, failing with `NotConnected` once the connection is
+    /// closed.
+    pub async fn send(&mut self, packet: P) -> std::io::Result<()> {
+        self.sender.try_send(encode(&packet)?).await
+    }
+
+    pub fn disconnect(self) {
+        self.sender.disconnect();
+    }
+}
+
+impl<P> Clone for FramedSender<P> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            packet: PhantomData,
+        }
+    }
+}
+
+pub struct FramedReceiver<P> {
+    receiver: RakStreamReceiver,
+    packet: PhantomData<fn() -> P>,
+}
+
+impl<P: Den> FramedReceiver<P> {
+    pub async fn receive(&mut self) -> Option<Result<P, DecodeError>> {
+        self.receiver.receive_bytes().await.map(decode)
+    }
+}
+
+fn encode<P: Den>(packet: &P) -> std::io::Result<Vec<u8>> {
+    let mut cursor = Cursor::new(Vec::with_capacity(packet.size()));
+    packet.encode(&mut cursor)?;
+    Ok(cursor.into_inner())
+}
+
+fn decode<P: Den>(payload: Bytes) -> Result<P, DecodeError> {
+    match P::decode(&mut Cursor::new(&payload[..])) {
+        Ok(packet) => Ok(packet),
+        Err(error) => Err(DecodeError { payload, error }),
+    }
+}
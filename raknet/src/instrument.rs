@@ -0,0 +1,68 @@
+//! Thin wrappers around `tracing` so the rest of the crate does not need
+//! `cfg` attributes. Without the `tracing` feature every macro expands to
+//! nothing and `Span` is a zero-sized placeholder.
+
+use std::net::SocketAddr;
+
+#[cfg(feature = "tracing")]
+macro_rules! trace {
+    ($($arg:tt)*) => { tracing::trace!($($arg)*) };
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! debug {
+    ($($arg:tt)*) => { tracing::debug!($($arg)*) };
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! warning {
+    ($($arg:tt)*) => { tracing::warn!($($arg)*) };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! trace {
+    ($($arg:tt)*) => {};
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! debug {
+    ($($arg:tt)*) => {};
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! warning {
+    ($($arg:tt)*) => {};
+}
+
+pub(crate) use {debug, trace, warning};
+
+#[cfg(feature = "tracing")]
+pub(crate) type Span = tracing::Span;
+
+#[cfg(not(feature = "tracing"))]
+#[derive(Clone)]
+pub(crate) struct Span;
+
+#[cfg(not(feature = "tracing"))]
+impl Span {
+    pub fn record<V>(&self, _field: &str, _value: V) -> &Self {
+        self
+    }
+}
+
+/// The span every event of a connection is attached to. `client_guid` is
+/// recorded once the `ConnectionRequest` carrying it has been seen.
+#[cfg(feature = "tracing")]
+pub(crate) fn connection_span(address: SocketAddr, mtu: usize) -> Span {
+    tracing::debug_span!(
+        "raknet_connection",
+        %address,
+        mtu,
+        client_guid = tracing::field::Empty,
+    )
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn connection_span(_address: SocketAddr, _mtu: usize) -> Span {
+    Span
+}
@@ -1,11 +1,58 @@
+pub mod accept;
+mod admin;
+pub mod advertisement;
+#[cfg(target_os = "linux")]
+mod batch;
+mod buffer;
+pub mod byte_stream;
 mod bytes;
+pub mod capture;
+pub mod clock;
+mod congestion;
 mod conn;
+pub mod discovery;
+pub mod event;
+mod frame;
+pub mod framed;
+mod instrument;
 pub mod listener;
 pub mod loop_task;
+pub mod metrics;
+pub mod offline;
+mod pacer;
 mod packets;
+pub mod ping;
+pub mod proxy;
+mod proxy_protocol;
+pub mod query;
+pub mod remote_clock;
+mod socket;
+pub mod socks;
+pub mod stats;
 pub mod stream;
+mod timer;
 
+pub use ::bytes::Bytes;
+pub use accept::*;
+pub use advertisement::*;
+pub use byte_stream::*;
+pub use bytes::RakString;
+pub use capture::*;
+pub use clock::*;
+pub use discovery::*;
+pub use event::*;
+pub use frame::Priority;
+pub use framed::*;
 pub use listener::*;
+pub use metrics::*;
+pub use offline::*;
+pub use packets::UnconnectedPong;
+pub use ping::*;
+pub use proxy::*;
+pub use query::*;
+pub use remote_clock::RemoteTimeOffset;
+pub use socks::Socks5Config;
+pub use stats::*;
 pub use stream::*;
 
 const RAKNET_PROTOCOL_VERSION: u8 = 0xA;
@@ -1,25 +1,116 @@
-use std::{net::SocketAddr, pin::Pin, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use async_std::net::{ToSocketAddrs, UdpSocket};
 use byte_util::Den;
+use bytes::Bytes;
 use futures::{
     channel::{mpsc, oneshot},
+    future::{self, Either},
     lock::Mutex,
     stream::FuturesUnordered,
     Future, FutureExt, StreamExt,
 };
 
 use crate::{
-    conn::Conn, loop_task::LoopTask, packets::*, RakStream, StreamInformation,
-    RAKNET_PROTOCOL_VERSION,
+    accept::{AcceptDecision, AcceptFilter, ConnectionAttempt, RejectReason},
+    admin::{AdminCommand, ConnectionRegistry},
+    buffer::ReceiveBuffer,
+    capture::{Capture, CaptureHook},
+    clock::{default_clock, Clock},
+    conn::{Conn, ToConnMsg, MAX_MTU, MIN_MTU, UDP_HEADER_SIZE},
+    discovery::AdvertiseConfig,
+    event::{DisconnectReason, EventBroadcaster, HandshakeFailure, ListenerEvent},
+    frame::Priority,
+    instrument::{debug, trace},
+    loop_task::LoopTask,
+    metrics::{ListenerMetrics, Metrics},
+    offline::{OfflineHook, OfflinePacket},
+    pacer::{BandwidthLimits, Pacer},
+    packets::*,
+    query::*,
+    socket::DatagramSocket,
+    stream::ConnState,
+    timer::TimerWheel,
+    RakStream, StreamInformation, RAKNET_PROTOCOL_VERSION,
 };
 
+pub(crate) const TICK_INTERVAL: Duration = Duration::from_millis(10);
+/// Connected streams kept per shard while `Listener::accept` is not keeping
+/// up. Beyond this, new clients are turned away as if the server were full.
+const MAX_UNACCEPTED: usize = 64;
+/// Pong provider calls running at once per shard. Pings are cheap to spoof,
+/// so beyond this they are answered with the static `server_id`.
+const MAX_PENDING_PONGS: usize = 64;
+/// Accept filter calls running at once per shard. Attempts beyond this are
+/// dropped without a reply, as if the filter had dropped them.
+const MAX_PENDING_DECISIONS: usize = 64;
+
+/// Computes the `server_id` sent in an `UnconnectedPong` from the
+/// requester's address and the ping time it sent.
+pub type PongProvider =
+    Arc<dyn Fn(SocketAddr, i64) -> Pin<Box<dyn Future<Output = String> + Send>> + Send + Sync>;
+
+/// Options fixed at bind time.
+#[derive(Clone)]
+pub struct ListenerConfig {
+    pub clock: Arc<dyn Clock>,
+    /// Number of sockets bound to the same port with `SO_REUSEPORT`, each
+    /// with its own loop and connection table. The kernel spreads clients
+    /// across them by address. More than one shard is only supported on
+    /// Linux. Use `LoopTask::into_shards` to run them on separate tasks.
+    pub shards: usize,
+    /// Reads HAProxy PROXY protocol v2 headers, for listeners behind a UDP
+    /// load balancer. The client address in the header of the first
+    /// datagram of each balancer flow is used for the whole flow, and
+    /// replies are sent back through the balancer. Only enable this if the
+    /// port is unreachable except through the balancer, as anyone can send
+    /// a header claiming any address.
+    pub proxy_protocol: bool,
+    /// Caps what each connection sends, in bytes per second. Datagrams
+    /// over the cap wait in the connection's queue and go out paced across
+    /// the following ticks instead of in one burst.
+    pub connection_bandwidth: Option<u64>,
+    /// Caps what all connections send together, in bytes per second,
+    /// across every shard. Connections held back by the cap share it
+    /// evenly. Offline replies are not counted.
+    pub total_bandwidth: Option<u64>,
+}
+
+impl Default for ListenerConfig {
+    fn default() -> Self {
+        Self {
+            clock: default_clock(),
+            shards: 1,
+            proxy_protocol: false,
+            connection_bandwidth: None,
+            total_bandwidth: None,
+        }
+    }
+}
+
 pub struct Listener {
     guid: i64,
     server_id: Arc<Mutex<String>>,
+    pong_provider: Arc<Mutex<Option<PongProvider>>>,
+    query: Arc<Mutex<Option<QueryProviders>>>,
+    accept_filter: Arc<Mutex<Option<AcceptFilter>>>,
+    advertise: Arc<Mutex<Option<AdvertiseConfig>>>,
+    capture: CaptureHook,
+    offline: OfflineHook,
+    registry: ConnectionRegistry,
     raw_socket: Arc<UdpSocket>,
-    destroy_sender: oneshot::Sender<Destroy>,
+    destroy_senders: Vec<oneshot::Sender<Destroy>>,
     new_stream_receiver: mpsc::Receiver<(RakStream, StreamInformation)>,
+    events: EventBroadcaster,
 }
 
 impl Listener {
@@ -28,43 +119,128 @@ impl Listener {
         guid: i64,
         server_id: &str,
     ) -> std::io::Result<(Self, LoopTask)> {
-        let raw_socket = Arc::new(UdpSocket::bind(addrs).await?);
-        let (destroy_sender, destroy_receiver) = oneshot::channel();
+        Self::bind_with_config(addrs, guid, server_id, ListenerConfig::default()).await
+    }
+
+    pub async fn bind_with_config<A: ToSocketAddrs>(
+        addrs: A,
+        guid: i64,
+        server_id: &str,
+        config: ListenerConfig,
+    ) -> std::io::Result<(Self, LoopTask)> {
+        let sockets = bind_shards(addrs, config.shards).await?;
+        let raw_socket = sockets[0].clone();
         let (new_stream_sender, new_stream_receiver) = mpsc::channel(8);
-        let socket = raw_socket.clone();
         let server_id = Arc::new(Mutex::new(server_id.to_owned()));
-        let server_id_cloned = server_id.clone();
-        let server_loop_task = LoopTask {
-            task: listener_loop(
-                guid,
-                server_id_cloned,
-                socket,
-                destroy_receiver,
-                new_stream_sender,
-            )
-            .boxed(),
+        let pong_provider = Arc::new(Mutex::new(None));
+        let query = Arc::new(Mutex::new(None));
+        let query_tokens = QueryTokens::new(config.clock.now());
+        let accept_filter = Arc::new(Mutex::new(None));
+        let advertise = Arc::new(Mutex::new(None));
+        let events = EventBroadcaster::default();
+        let capture = CaptureHook::default();
+        let offline = OfflineHook::default();
+        let registry = ConnectionRegistry::default();
+        let limits = BandwidthLimits {
+            connection: config.connection_bandwidth,
+            shared: config
+                .total_bandwidth
+                .map(|rate| Pacer::shared(rate, config.clock.now())),
         };
 
+        let mut destroy_senders = vec![];
+        let mut loops = vec![];
+        for (shard, socket) in sockets.into_iter().enumerate() {
+            let (destroy_sender, destroy_receiver) = oneshot::channel();
+            destroy_senders.push(destroy_sender);
+            let socket = match config.proxy_protocol {
+                true => DatagramSocket::new(socket).with_proxy_protocol(),
+                false => DatagramSocket::new(socket),
+            };
+            let context = ListenerContext {
+                guid,
+                server_id: server_id.clone(),
+                pong_provider: pong_provider.clone(),
+                pending_pongs: AtomicUsize::new(0),
+                query: query.clone(),
+                query_tokens: query_tokens.clone(),
+                accept_filter: accept_filter.clone(),
+                // Only one shard advertises, or clients would see the
+                // server once per shard.
+                advertise: match shard {
+                    0 => advertise.clone(),
+                    _ => Arc::default(),
+                },
+                capture: capture.clone(),
+                offline: offline.clone(),
+                registry: registry.clone(),
+                socket: Arc::new(socket),
+                clock: config.clock.clone(),
+                limits: limits.clone(),
+                events: events.clone(),
+            };
+            loops.push(listener_loop(context, destroy_receiver, new_stream_sender.clone()).boxed());
+        }
+        let server_loop_task = LoopTask::join(loops);
+
         Ok((
             Self {
                 guid,
                 server_id,
+                pong_provider,
+                query,
+                accept_filter,
+                advertise,
+                capture,
+                offline,
+                registry,
                 raw_socket,
-                destroy_sender,
+                destroy_senders,
                 new_stream_receiver,
+                events,
             },
             server_loop_task,
         ))
     }
 
     pub fn destroy(self) {
-        _ = self.destroy_sender.send(Destroy);
+        for destroy_sender in self.destroy_senders {
+            _ = destroy_sender.send(Destroy);
+        }
     }
 
     pub async fn accept(&mut self) -> Option<(RakStream, StreamInformation)> {
         self.new_stream_receiver.next().await
     }
 
+    /// Takes the channel `accept` reads from, for a caller that accepts
+    /// without holding on to the `Listener` mutably. `accept` returns `None`
+    /// from then on.
+    pub(crate) fn take_incoming(&mut self) -> mpsc::Receiver<(RakStream, StreamInformation)> {
+        let (_, closed) = mpsc::channel(0);
+        std::mem::replace(&mut self.new_stream_receiver, closed)
+    }
+
+    /// Subscribes to socket errors, malformed packets, handshake failures,
+    /// connects and disconnects. Events are dropped if the receiver falls
+    /// behind.
+    pub fn events(&self) -> mpsc::Receiver<ListenerEvent> {
+        self.events.subscribe()
+    }
+
+    /// Hands datagrams that are not RakNet traffic, from peers without a
+    /// connection, to the returned receiver instead of reporting them as
+    /// malformed. Each comes with a way to reply from the listener socket.
+    /// A new call replaces the previous receiver, and packets are dropped
+    /// if it falls behind.
+    pub fn offline_packets(&self) -> mpsc::Receiver<OfflinePacket> {
+        self.offline.subscribe()
+    }
+
+    pub fn metrics(&self) -> ListenerMetrics {
+        self.events.metrics().snapshot()
+    }
+
     pub fn raw_socket(&self) -> Arc<UdpSocket> {
         self.raw_socket.clone()
     }
@@ -73,90 +249,738 @@ impl Listener {
         self.raw_socket.local_addr()
     }
 
-    pub async fn set_server_id(&self, new_server_id: &str) {
-        *self.server_id.lock().await = new_server_id.to_owned()
+    /// Accepts a raw string or a `ServerAdvertisement`.
+    pub async fn set_server_id(&self, new_server_id: impl Into<String>) {
+        *self.server_id.lock().await = new_server_id.into()
     }
 
     pub async fn server_id(&self) -> String {
         self.server_id.lock().await.clone()
     }
 
+    /// Answers pings with the `server_id` returned by `provider` instead of
+    /// the one set by `set_server_id`.
+    pub async fn set_pong_provider<F, Fut>(&self, provider: F)
+    where
+        F: Fn(SocketAddr, i64) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = String> + Send + 'static,
+    {
+        let provider: PongProvider = Arc::new(move |addr, time| provider(addr, time).boxed());
+        *self.pong_provider.lock().await = Some(provider);
+    }
+
+    pub async fn clear_pong_provider(&self) {
+        *self.pong_provider.lock().await = None;
+    }
+
+    /// Answers GameSpy4 (UT3) queries on the listener port, as Bedrock
+    /// Dedicated Server does. Basic stats report what `info` returns, full
+    /// stats also list the players returned by `players`.
+    pub async fn set_query_providers<F, FFut, G, GFut>(&self, info: F, players: G)
+    where
+        F: Fn() -> FFut + Send + Sync + 'static,
+        FFut: Future<Output = QueryInfo> + Send + 'static,
+        G: Fn() -> GFut + Send + Sync + 'static,
+        GFut: Future<Output = Vec<String>> + Send + 'static,
+    {
+        let providers = QueryProviders {
+            info: Arc::new(move || info().boxed()),
+            players: Arc::new(move || players().boxed()),
+        };
+        *self.query.lock().await = Some(providers);
+    }
+
+    /// Stops answering queries. They are then handled like any other
+    /// unknown datagram.
+    pub async fn clear_query_providers(&self) {
+        *self.query.lock().await = None;
+    }
+
+    /// Runs `filter` on every OpenConnectionRequest2 before the listener
+    /// sets up any state for the connection, for allowlists or bans. The
+    /// request is only answered once the filter decides, and retries of an
+    /// accepted request skip it.
+    pub async fn set_accept_filter<F, Fut>(&self, filter: F)
+    where
+        F: Fn(ConnectionAttempt) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = AcceptDecision> + Send + 'static,
+    {
+        let filter: AcceptFilter = Arc::new(move |attempt| filter(attempt).boxed());
+        *self.accept_filter.lock().await = Some(filter);
+    }
+
+    pub async fn clear_accept_filter(&self) {
+        *self.accept_filter.lock().await = None;
+    }
+
+    /// Periodically sends Advertise System packets carrying the current
+    /// `server_id` so the server shows up in clients' LAN tab.
+    pub async fn start_advertising(&self, config: AdvertiseConfig) -> std::io::Result<()> {
+        self.raw_socket.set_broadcast(true)?;
+        *self.advertise.lock().await = Some(config);
+        Ok(())
+    }
+
+    pub async fn stop_advertising(&self) {
+        *self.advertise.lock().await = None;
+    }
+
+    /// Writes every datagram sent or received from now on to `capture`.
+    pub fn start_capture(&self, capture: Capture) -> std::io::Result<()> {
+        self.capture.set(Some(capture), self.local_addr()?);
+        Ok(())
+    }
+
+    pub fn stop_capture(&self) {
+        self.capture.clear();
+    }
+
     pub fn guid(&self) -> i64 {
         self.guid
     }
+
+    /// Lists the connections that completed the handshake and have not
+    /// closed yet, oldest first. This includes those whose stream is still
+    /// waiting to be returned by `accept`.
+    pub fn connections(&self) -> Vec<StreamInformation> {
+        self.registry.list()
+    }
+
+    pub fn connection(&self, id: u64) -> Option<StreamInformation> {
+        self.registry.get(id)
+    }
+
+    pub fn connection_by_address(&self, address: SocketAddr) -> Option<StreamInformation> {
+        self.registry
+            .find(|information| information.address == address)
+    }
+
+    pub fn connection_by_guid(&self, guid: i64) -> Option<StreamInformation> {
+        self.registry.find(|information| information.guid == guid)
+    }
+
+    /// Disconnects a connection listed by `connections`, which stops
+    /// listing it right away. RakNet has no way to tell the client why, so
+    /// `reason` is only reported in the `Disconnected` event. Returns `false`
+    /// if there is no such connection.
+    pub fn kick(&self, id: u64, reason: impl Into<String>) -> bool {
+        if !self.registry.remove(id) {
+            return false;
+        }
+        let reason = reason.into();
+        self.registry.send(|| AdminCommand::Kick {
+            id,
+            reason: reason.clone(),
+        });
+        true
+    }
+
+    pub fn kick_by_address(&self, address: SocketAddr, reason: impl Into<String>) -> bool {
+        match self.connection_by_address(address) {
+            Some(information) => self.kick(information.id, reason),
+            None => false,
+        }
+    }
+
+    /// Sends `payload` to every connection listed by `connections` that
+    /// `filter` accepts, as if written to each stream. Returns the number of
+    /// connections it was sent to.
+    pub fn broadcast(
+        &self,
+        payload: &[u8],
+        mut filter: impl FnMut(&StreamInformation) -> bool,
+    ) -> usize {
+        let ids = self
+            .registry
+            .list()
+            .into_iter()
+            .filter(|information| filter(information))
+            .map(|information| information.id)
+            .collect::<Vec<_>>();
+        if !ids.is_empty() {
+            self.registry.send(|| AdminCommand::Broadcast {
+                ids: ids.clone(),
+                payload: payload.to_vec(),
+            });
+        }
+        ids.len()
+    }
 }
 
 struct Destroy;
 
 enum TaskResultWapper {
     Destroy,
-    UdpReceived(std::io::Result<(usize, SocketAddr)>, Box<[u8; 4096]>),
+    UdpReceived(std::io::Result<Vec<(Bytes, SocketAddr)>>, ReceiveBuffer),
+    ConnMsg(
+        SocketAddr,
+        u64,
+        Option<ToConnMsg>,
+        mpsc::Receiver<ToConnMsg>,
+        oneshot::Receiver<()>,
+    ),
+    PongReady(SocketAddr, UnconnectedPong),
+    QueryReady(SocketAddr, Vec<u8>),
+    AcceptDecided(ConnectionAttempt, AcceptDecision),
+    Admin(Option<AdminCommand>, mpsc::UnboundedReceiver<AdminCommand>),
+    Tick,
 }
 
 type TaskManager = FuturesUnordered<Pin<Box<dyn Future<Output = TaskResultWapper> + Send>>>;
 
-pub struct ConnectionManager {}
-
-async fn listener_loop(
+struct ListenerContext {
     guid: i64,
     server_id: Arc<Mutex<String>>,
-    socket: Arc<UdpSocket>,
+    pong_provider: Arc<Mutex<Option<PongProvider>>>,
+    pending_pongs: AtomicUsize,
+    query: Arc<Mutex<Option<QueryProviders>>>,
+    query_tokens: QueryTokens,
+    accept_filter: Arc<Mutex<Option<AcceptFilter>>>,
+    advertise: Arc<Mutex<Option<AdvertiseConfig>>>,
+    capture: CaptureHook,
+    offline: OfflineHook,
+    registry: ConnectionRegistry,
+    socket: Arc<DatagramSocket>,
+    clock: Arc<dyn Clock>,
+    limits: BandwidthLimits,
+    events: EventBroadcaster,
+}
+
+impl ListenerContext {
+    /// Sends Advertise System packets if advertising is enabled and the
+    /// interval has passed since `last_advertised`.
+    async fn advertise(&self, start: Instant, last_advertised: &mut Option<Instant>) {
+        let Some(config) = self.advertise.lock().await.clone() else {
+            return;
+        };
+        let now = self.clock.now();
+        if matches!(last_advertised, Some(last) if now < *last + config.interval) {
+            return;
+        }
+        *last_advertised = Some(now);
+
+        let server_id = self.server_id.lock().await.clone();
+        for target in config.targets {
+            // Advertise System shares the pong layout.
+            let advertise_system = UnconnectedPong {
+                time: now.duration_since(start).as_millis() as i64,
+                server_guid: self.guid,
+                magic: true,
+                server_id: server_id.clone(),
+            };
+            self.send_packet(advertise_system, 0x1d, target).await;
+        }
+    }
+
+    /// Answers a GameSpy4 query. Returns `false` if no query providers are
+    /// set.
+    async fn answer_query(&self, tasks: &mut TaskManager, addr: SocketAddr, buffer: &[u8]) -> bool {
+        let Some(providers) = self.query.lock().await.clone() else {
+            return false;
+        };
+        let now = self.clock.now();
+        match parse_request(buffer, addr, &self.query_tokens, now) {
+            Some(QueryRequest::Handshake { session }) => {
+                let token = self.query_tokens.issue(addr, now);
+                self.send_raw(&handshake_response(session, token), addr)
+                    .await;
+            }
+            Some(QueryRequest::Stat { session, full }) => {
+                // Like the pong provider, the providers run alongside the
+                // loop.
+                let info = (providers.info)();
+                let players = full.then(|| (providers.players)());
+                tasks.push(
+                    async move {
+                        let info = info.await;
+                        let response = match players {
+                            Some(players) => full_stat_response(session, &info, &players.await),
+                            None => basic_stat_response(session, &info),
+                        };
+                        TaskResultWapper::QueryReady(addr, response)
+                    }
+                    .boxed(),
+                );
+            }
+            None => {
+                trace!(%addr, "dropped invalid query");
+            }
+        }
+        true
+    }
+
+    /// Refuses a connection attempt with the reply for `reason`.
+    async fn reject(&self, addr: SocketAddr, reason: RejectReason) {
+        debug!(%addr, ?reason, "rejected connection attempt");
+        if reason == RejectReason::Banned {
+            Metrics::add(&self.events.metrics().bans, 1);
+        }
+        match reason {
+            RejectReason::IncompatibleProtocol => {
+                let incompatibleprotocolversion = IncompatibleProtocolVersion {
+                    server_protocol: RAKNET_PROTOCOL_VERSION,
+                    magic: true,
+                    server_guid: self.guid,
+                };
+                self.send_packet(incompatibleprotocolversion, reason.packet_id(), addr)
+                    .await;
+            }
+            _ => {
+                // The other refusals share the AlreadyConnected layout.
+                let refusal = AlreadyConnected {
+                    magic: true,
+                    server_guid: self.guid,
+                };
+                self.send_packet(refusal, reason.packet_id(), addr).await;
+            }
+        }
+        self.events.emit(ListenerEvent::HandshakeFailed {
+            address: addr,
+            reason: HandshakeFailure::Rejected(reason),
+        });
+    }
+
+    async fn send_packet<P: Den>(&self, packet: P, id: u8, addr: SocketAddr) {
+        match encode(packet, id) {
+            Ok(buffer) => self.send_raw(&buffer, addr).await,
+            Err(err) => self.events.socket_error(err),
+        }
+    }
+
+    async fn send_raw(&self, buffer: &[u8], addr: SocketAddr) {
+        match self.socket.send_to(buffer, addr).await {
+            Ok(size) => {
+                self.capture.sent(addr, buffer);
+                Metrics::add(&self.events.metrics().bytes_sent, size as u64);
+            }
+            Err(err) => self.events.socket_error(err),
+        }
+    }
+}
+
+struct Connection {
+    id: u64,
+    conn: Conn,
+    pending_stream: Option<(RakStream, StreamInformation)>,
+    /// The deadline this connection is registered with in the timer wheel.
+    scheduled: Option<Instant>,
+    /// Dropped with the connection, which ends its message task.
+    _forget: oneshot::Sender<()>,
+}
+
+pub struct ConnectionManager {
+    connections: HashMap<SocketAddr, Connection>,
+    /// The address of each connection, for admin commands naming it by ID.
+    addresses: HashMap<u64, SocketAddr>,
+    /// Addresses whose connection attempt the accept filter is deciding on.
+    deciding: HashSet<SocketAddr>,
+    registry: ConnectionRegistry,
+    events: EventBroadcaster,
+    timers: TimerWheel<(SocketAddr, u64)>,
+    /// Streams that did not fit in the accept channel, oldest first.
+    unaccepted: VecDeque<(RakStream, StreamInformation)>,
+}
+
+impl ConnectionManager {
+    fn new(registry: ConnectionRegistry, events: EventBroadcaster, start: Instant) -> Self {
+        Self {
+            connections: HashMap::new(),
+            addresses: HashMap::new(),
+            deciding: HashSet::new(),
+            registry,
+            events,
+            timers: TimerWheel::new(start, TICK_INTERVAL),
+            unaccepted: VecDeque::new(),
+        }
+    }
+
+    fn insert(
+        &mut self,
+        addr: SocketAddr,
+        conn: Conn,
+        stream: RakStream,
+        guid: i64,
+        forget: oneshot::Sender<()>,
+    ) -> u64 {
+        let id = self.registry.next_id();
+        self.addresses.insert(id, addr);
+        let information = StreamInformation {
+            id,
+            guid,
+            address: addr,
+        };
+        self.connections.insert(
+            addr,
+            Connection {
+                id,
+                conn,
+                pending_stream: Some((stream, information)),
+                scheduled: None,
+                _forget: forget,
+            },
+        );
+        id
+    }
+
+    /// Hands the stream out once the handshake completes, forgets closed
+    /// connections and registers the next deadline of the others.
+    fn sync(
+        &mut self,
+        addr: SocketAddr,
+        new_stream_sender: &mut mpsc::Sender<(RakStream, StreamInformation)>,
+    ) {
+        if let Some(connection) = self.connections.get_mut(&addr) {
+            if connection.conn.is_connected() {
+                if let Some(stream) = connection.pending_stream.take() {
+                    Metrics::add(&self.events.metrics().active_connections, 1);
+                    self.registry.insert(stream.1.clone());
+                    self.events.emit(ListenerEvent::Connected(stream.1.clone()));
+                    self.unaccepted.push_back(stream);
+                    hand_out(&mut self.unaccepted, new_stream_sender);
+                }
+            }
+            if connection.conn.is_closed() {
+                // Only connections that completed the handshake were counted.
+                if connection.pending_stream.is_none() {
+                    forget_active(&self.events, 1);
+                }
+                self.registry.remove(connection.id);
+                self.addresses.remove(&connection.id);
+                self.connections.remove(&addr);
+                return;
+            }
+            // Only an earlier deadline needs a new entry; a later one is
+            // picked up when the registered entry fires.
+            if let Some(deadline) = connection.conn.next_deadline() {
+                if connection
+                    .scheduled
+                    .is_none_or(|scheduled| deadline < scheduled)
+                {
+                    connection.scheduled = Some(deadline);
+                    self.timers.insert(deadline, (addr, connection.id));
+                }
+            }
+        }
+    }
+
+    /// Disconnects every connection of the shard and forgets them.
+    async fn destroy(&mut self) {
+        let mut connected = 0;
+        for (_, mut connection) in self.connections.drain() {
+            // The loop is going away, so nothing is left to drain the queue.
+            connection
+                .conn
+                .shutdown(DisconnectReason::ClosedLocally)
+                .await;
+            self.registry.remove(connection.id);
+            if connection.pending_stream.is_none() {
+                connected += 1;
+            }
+        }
+        self.addresses.clear();
+        forget_active(&self.events, connected);
+    }
+
+    fn backlog_full(&self) -> bool {
+        self.unaccepted.len() >= MAX_UNACCEPTED
+    }
+
+    /// Updates every connection whose registered deadline has passed.
+    async fn fire_timers(
+        &mut self,
+        now: Instant,
+        new_stream_sender: &mut mpsc::Sender<(RakStream, StreamInformation)>,
+    ) {
+        for (deadline, (addr, id)) in self.timers.advance(now) {
+            let Some(connection) = self.connections.get_mut(&addr) else {
+                continue;
+            };
+            // Entries superseded by an earlier deadline are dropped.
+            if connection.id != id || connection.scheduled != Some(deadline) {
+                continue;
+            }
+            if deadline <= now {
+                connection.scheduled = None;
+                connection.conn.update().await;
+                self.sync(addr, new_stream_sender);
+            } else {
+                // Past the span of the wheel; wait for another round.
+                self.timers.insert(deadline, (addr, id));
+            }
+        }
+    }
+
+    async fn handle_admin(
+        &mut self,
+        command: AdminCommand,
+        new_stream_sender: &mut mpsc::Sender<(RakStream, StreamInformation)>,
+    ) {
+        match command {
+            AdminCommand::Kick { id, reason } => {
+                let Some(addr) = self.address_of(id) else {
+                    return;
+                };
+                debug!(%addr, %reason, "kicking connection");
+                if let Some(connection) = self.connections.get_mut(&addr) {
+                    let reason = DisconnectReason::Kicked(reason);
+                    connection.conn.disconnect_with(reason).await;
+                }
+                self.sync(addr, new_stream_sender);
+            }
+            AdminCommand::Broadcast { ids, payload } => {
+                let addrs = ids
+                    .into_iter()
+                    .filter_map(|id| self.address_of(id))
+                    .collect::<Vec<_>>();
+                for addr in addrs {
+                    if let Some(connection) = self.connections.get_mut(&addr) {
+                        let msg = ToConnMsg::Send(payload.clone(), Priority::default());
+                        connection.conn.handle_msg(Some(msg)).await;
+                    }
+                    self.sync(addr, new_stream_sender);
+                }
+            }
+        }
+    }
+
+    fn address_of(&self, id: u64) -> Option<SocketAddr> {
+        let addr = *self.addresses.get(&id)?;
+        let connection = self.connections.get(&addr)?;
+        (connection.id == id).then_some(addr)
+    }
+}
+
+/// Takes closed connections out of the active connections gauge. Shards
+/// share the gauge, so it moves by deltas.
+fn forget_active(events: &EventBroadcaster, count: u64) {
+    let active = &events.metrics().active_connections;
+    active.fetch_sub(count, Ordering::Relaxed);
+}
+
+/// Moves unaccepted streams into the accept channel while it has room.
+/// Never waits for it, as that would stall every connection of the shard
+/// until the application calls `accept`.
+fn hand_out(
+    unaccepted: &mut VecDeque<(RakStream, StreamInformation)>,
+    new_stream_sender: &mut mpsc::Sender<(RakStream, StreamInformation)>,
+) {
+    while let Some(stream) = unaccepted.pop_front() {
+        if let Err(err) = new_stream_sender.try_send(stream) {
+            // Streams for a dropped `Listener` are dropped with it.
+            if err.is_full() {
+                unaccepted.push_front(err.into_inner());
+            }
+            return;
+        }
+    }
+}
+
+/// Binds one socket, or `shards` sockets sharing a port through
+/// `SO_REUSEPORT`.
+async fn bind_shards<A: ToSocketAddrs>(
+    addrs: A,
+    shards: usize,
+) -> std::io::Result<Vec<Arc<UdpSocket>>> {
+    if shards <= 1 {
+        return Ok(vec![Arc::new(UdpSocket::bind(addrs).await?)]);
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let mut addr = crate::stream::resolve(addrs).await?;
+        let mut sockets = vec![];
+        for _ in 0..shards {
+            let socket = bind_reuse_port(addr)?;
+            // Every shard must share the port the first one was given.
+            addr = socket.local_addr()?;
+            sockets.push(Arc::new(socket));
+        }
+        Ok(sockets)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        _ = addrs;
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "sharded listeners need SO_REUSEPORT load balancing (Linux)",
+        ))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn bind_reuse_port(addr: SocketAddr) -> std::io::Result<UdpSocket> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_port(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    Ok(UdpSocket::from(std::net::UdpSocket::from(socket)))
+}
+
+fn receive_udp(
+    socket: Arc<DatagramSocket>,
+    mut buffer: ReceiveBuffer,
+) -> Pin<Box<dyn Future<Output = TaskResultWapper> + Send>> {
+    async move {
+        let result = socket.recv_many(&mut buffer).await;
+        TaskResultWapper::UdpReceived(result, buffer)
+    }
+    .boxed()
+}
+
+/// Waits for the next message from a connection's stream. Ends early once
+/// the connection is forgotten, dropping `receiver` so that the stream sees
+/// the connection as closed.
+fn receive_conn_msg(
+    addr: SocketAddr,
+    id: u64,
+    mut receiver: mpsc::Receiver<ToConnMsg>,
+    mut forgotten: oneshot::Receiver<()>,
+) -> Pin<Box<dyn Future<Output = TaskResultWapper> + Send>> {
+    async move {
+        let msg = match future::select(receiver.next(), &mut forgotten).await {
+            Either::Left((msg, _)) => msg,
+            Either::Right(_) => None,
+        };
+        TaskResultWapper::ConnMsg(addr, id, msg, receiver, forgotten)
+    }
+    .boxed()
+}
+
+fn receive_admin(
+    mut receiver: mpsc::UnboundedReceiver<AdminCommand>,
+) -> Pin<Box<dyn Future<Output = TaskResultWapper> + Send>> {
+    async move {
+        let command = receiver.next().await;
+        TaskResultWapper::Admin(command, receiver)
+    }
+    .boxed()
+}
+
+fn tick(clock: &Arc<dyn Clock>) -> Pin<Box<dyn Future<Output = TaskResultWapper> + Send>> {
+    let sleep = clock.sleep(TICK_INTERVAL);
+    async move {
+        sleep.await;
+        TaskResultWapper::Tick
+    }
+    .boxed()
+}
+
+async fn listener_loop(
+    context: ListenerContext,
     destroy_receiver: oneshot::Receiver<Destroy>,
     mut new_stream_sender: mpsc::Sender<(RakStream, StreamInformation)>,
 ) {
-    let tasks = Arc::new(Mutex::new(TaskManager::new()));
+    let mut tasks = TaskManager::new();
     let destroy_task = async move {
         _ = destroy_receiver.await;
         TaskResultWapper::Destroy
     }
     .boxed();
-    let socket_clone = socket.clone();
-    let receive_udp_task = async move {
-        let mut buffer = [0u8; 4096];
-        TaskResultWapper::UdpReceived(socket_clone.recv_from(&mut buffer).await, Box::new(buffer))
-    }
-    .boxed();
 
-    tasks.lock().await.push(destroy_task);
-    tasks.lock().await.push(receive_udp_task);
+    tasks.push(destroy_task);
+    tasks.push(receive_udp(context.socket.clone(), ReceiveBuffer::new()));
+    tasks.push(receive_admin(context.registry.commands()));
+    tasks.push(tick(&context.clock));
 
-    let connection_manager = ConnectionManager {};
+    let start = context.clock.now();
+    let mut connection_manager =
+        ConnectionManager::new(context.registry.clone(), context.events.clone(), start);
+    let mut last_advertised = None;
 
-    while let Some(result) = tasks.lock().await.next().await {
+    while let Some(result) = tasks.next().await {
         match result {
             TaskResultWapper::Destroy => {
-                // Do something to end connections
+                connection_manager.destroy().await;
                 break;
             }
-            TaskResultWapper::UdpReceived(res, mut buffer) => {
-                // handle packet
-                if let Err(_) = res {
-                    todo!();
+            TaskResultWapper::UdpReceived(res, buffer) => {
+                match res {
+                    Ok(datagrams) => {
+                        for (datagram, addr) in datagrams {
+                            Metrics::add(
+                                &context.events.metrics().bytes_received,
+                                datagram.len() as u64,
+                            );
+                            context.capture.received(addr, &datagram);
+                            handle_packet(
+                                &mut tasks,
+                                &mut connection_manager,
+                                &context,
+                                addr,
+                                datagram,
+                            )
+                            .await;
+                            connection_manager.sync(addr, &mut new_stream_sender);
+                        }
+                    }
+                    // e.g. ICMP port unreachable from a peer that went away.
+                    Err(err) => context.events.socket_error(err),
                 }
 
-                let (size, addr) = res.unwrap();
-                let tasks_new = tasks.clone();
-                handle_packet(
-                    tasks_new,
-                    &connection_manager,
-                    addr,
-                    &buffer[..size],
-                    &socket.clone(),
-                    guid,
-                    server_id.clone(),
-                    &mut new_stream_sender,
-                )
-                .await;
+                tasks.push(receive_udp(context.socket.clone(), buffer));
+            }
+            TaskResultWapper::ConnMsg(addr, id, msg, receiver, forgotten) => {
+                let Some(connection) = connection_manager.connections.get_mut(&addr) else {
+                    continue;
+                };
+                if connection.id != id {
+                    continue;
+                }
 
-                let socket_clone = socket.clone();
-                let receive_udp_task = async move {
-                    TaskResultWapper::UdpReceived(
-                        socket_clone.recv_from(buffer.as_mut_slice()).await,
-                        buffer,
-                    )
+                let closed = msg.is_none();
+                connection.conn.handle_msg(msg).await;
+                connection_manager.sync(addr, &mut new_stream_sender);
+                if !closed {
+                    tasks.push(receive_conn_msg(addr, id, receiver, forgotten));
                 }
-                .boxed();
-                tasks.lock().await.push(receive_udp_task)
+            }
+            TaskResultWapper::PongReady(addr, pong) => {
+                context.pending_pongs.fetch_sub(1, Ordering::Relaxed);
+                Metrics::add(&context.events.metrics().pings_answered, 1);
+                context.send_packet(pong, 0x1c, addr).await;
+            }
+            TaskResultWapper::QueryReady(addr, response) => {
+                context.send_raw(&response, addr).await;
+            }
+            TaskResultWapper::AcceptDecided(attempt, decision) => {
+                let addr = attempt.address;
+                connection_manager.deciding.remove(&addr);
+                match decision {
+                    AcceptDecision::Accept => {
+                        open_connection(&mut tasks, &mut connection_manager, &context, attempt)
+                            .await;
+                        connection_manager.sync(addr, &mut new_stream_sender);
+                    }
+                    AcceptDecision::Reject(reason) => context.reject(addr, reason).await,
+                    AcceptDecision::Drop => {
+                        debug!(%addr, "dropped connection attempt");
+                        context.events.emit(ListenerEvent::HandshakeFailed {
+                            address: addr,
+                            reason: HandshakeFailure::Dropped,
+                        });
+                    }
+                }
+            }
+            TaskResultWapper::Admin(command, receiver) => {
+                // The `Listener` is gone once the channel closes.
+                if let Some(command) = command {
+                    connection_manager
+                        .handle_admin(command, &mut new_stream_sender)
+                        .await;
+                    tasks.push(receive_admin(receiver));
+                }
+            }
+            TaskResultWapper::Tick => {
+                let now = context.clock.now();
+                hand_out(&mut connection_manager.unaccepted, &mut new_stream_sender);
+                connection_manager
+                    .fire_timers(now, &mut new_stream_sender)
+                    .await;
+                context.advertise(start, &mut last_advertised).await;
+                tasks.push(tick(&context.clock));
             }
         }
     }
@@ -169,49 +993,116 @@ macro_rules! or_return {
             Err(_) => return,
         }
     };
+    ($result:expr, $on_err:expr) => {
+        match $result {
+            Ok(p) => p,
+            Err(_) => {
+                $on_err;
+                return;
+            }
+        }
+    };
 }
 
 async fn handle_packet(
-    tasks: Arc<Mutex<TaskManager>>,
-    connection_manager: &ConnectionManager,
+    tasks: &mut TaskManager,
+    connection_manager: &mut ConnectionManager,
+    context: &ListenerContext,
     addr: SocketAddr,
-    buffer: &[u8],
-    socket: &Arc<UdpSocket>,
-    guid: i64,
-    server_id: Arc<Mutex<String>>,
-    new_stream_sender: &mut mpsc::Sender<(RakStream, StreamInformation)>,
+    datagram: Bytes,
 ) {
+    let buffer = &datagram[..];
     if buffer.is_empty() {
         return;
     }
 
+    let socket = &context.socket;
+    let guid = context.guid;
+    let malformed = || context.events.malformed_packet(addr, buffer);
+
+    // Queries share the port but are not RakNet packets.
+    if buffer.starts_with(&QUERY_MAGIC) && context.answer_query(tasks, addr, buffer).await {
+        return;
+    }
+
+    if let Some(connection) = connection_manager.connections.get_mut(&addr) {
+        if buffer[0] & 0x80 != 0 {
+            connection.conn.handle(datagram.clone()).await;
+            return;
+        }
+    }
+
     match buffer[0] {
         0x1 | 0x2 => {
-            let ping = or_return!(decode::<UnconnectedPing>(buffer));
-            let pong = UnconnectedPong {
+            let ping = or_return!(decode::<UnconnectedPing>(buffer), malformed());
+            if !ping.magic {
+                malformed();
+                return;
+            }
+            trace!(%addr, time = ping.time, "answering unconnected ping");
+            let mut pong = UnconnectedPong {
                 time: ping.time,
                 server_guid: guid,
                 magic: true,
-                server_id: server_id.lock().await.clone(),
+                server_id: String::new(),
             };
-            socket
-                .send_to(&encode(pong, 0x1c).unwrap(), addr)
-                .await
-                .unwrap();
+            let provider = match context.pending_pongs.load(Ordering::Relaxed) {
+                pending if pending < MAX_PENDING_PONGS => {
+                    context.pong_provider.lock().await.clone()
+                }
+                _ => None,
+            };
+            match provider {
+                Some(provider) => {
+                    // The provider may be slow, so it runs alongside the
+                    // loop instead of blocking it.
+                    context.pending_pongs.fetch_add(1, Ordering::Relaxed);
+                    let server_id = provider(addr, ping.time);
+                    tasks.push(
+                        async move {
+                            pong.server_id = server_id.await;
+                            TaskResultWapper::PongReady(addr, pong)
+                        }
+                        .boxed(),
+                    );
+                }
+                None => {
+                    pong.server_id = context.server_id.lock().await.clone();
+                    Metrics::add(&context.events.metrics().pings_answered, 1);
+                    context.send_packet(pong, 0x1c, addr).await;
+                }
+            }
         }
         0x5 => {
-            let openconnectionrequest1 = or_return!(decode::<OpenConnectionRequest1>(buffer));
+            let openconnectionrequest1 =
+                or_return!(decode::<OpenConnectionRequest1>(buffer), malformed());
+            if !openconnectionrequest1.magic {
+                malformed();
+                return;
+            }
 
+            debug!(
+                %addr,
+                protocol = openconnectionrequest1.protocol_version,
+                mtu = buffer.len() + UDP_HEADER_SIZE,
+                "open connection request 1"
+            );
             if openconnectionrequest1.protocol_version != RAKNET_PROTOCOL_VERSION {
+                debug!(%addr, "rejecting incompatible protocol version");
                 let incompatibleprotocolversion = IncompatibleProtocolVersion {
                     server_protocol: RAKNET_PROTOCOL_VERSION,
                     magic: true,
                     server_guid: guid,
                 };
-                socket
-                    .send_to(&encode(incompatibleprotocolversion, 0x19).unwrap(), addr)
-                    .await
-                    .unwrap();
+                context
+                    .send_packet(incompatibleprotocolversion, 0x19, addr)
+                    .await;
+                context.events.emit(ListenerEvent::HandshakeFailed {
+                    address: addr,
+                    reason: HandshakeFailure::IncompatibleProtocol {
+                        client_protocol: openconnectionrequest1.protocol_version,
+                    },
+                });
                 return;
             }
 
@@ -219,44 +1110,146 @@ async fn handle_packet(
                 magic: true,
                 server_guid: guid,
                 use_security: false,
-                mtu: openconnectionrequest1.size() as i16,
+                mtu: (buffer.len() + UDP_HEADER_SIZE).min(MAX_MTU) as i16,
             };
-            socket
-                .send_to(&encode(openconnectionreply1, 0x6).unwrap(), addr)
-                .await
-                .unwrap();
+            context.send_packet(openconnectionreply1, 0x6, addr).await;
         }
         0x7 => {
-            let openconnectionrequest2 = or_return!(decode::<OpenConnectionRequest2>(buffer));
-            let openconnectionreply2 = OpenConnectionReply2 {
+            let openconnectionrequest2 =
+                or_return!(decode::<OpenConnectionRequest2>(buffer), malformed());
+            if !openconnectionrequest2.magic {
+                malformed();
+                return;
+            }
+            let mtu = (openconnectionrequest2.mtu.max(0) as usize).clamp(MIN_MTU, MAX_MTU);
+            debug!(
+                %addr,
+                client_guid = openconnectionrequest2.client_guid,
+                mtu,
+                "open connection request 2"
+            );
+
+            let attempt = ConnectionAttempt {
+                address: addr,
+                guid: openconnectionrequest2.client_guid,
+                mtu,
+            };
+            // Retries of an accepted request skip the filter.
+            let filter = match connection_manager.connections.contains_key(&addr) {
+                true => None,
+                false => context.accept_filter.lock().await.clone(),
+            };
+            match filter {
+                Some(_) if connection_manager.deciding.contains(&addr) => {
+                    // The client retried while the filter is still deciding
+                    // on its first request; the decision answers both.
+                    debug!(%addr, "ignoring retry while the accept filter decides");
+                }
+                Some(_) if connection_manager.deciding.len() >= MAX_PENDING_DECISIONS => {
+                    debug!(%addr, "dropped connection attempt, too many pending decisions");
+                    context.events.emit(ListenerEvent::HandshakeFailed {
+                        address: addr,
+                        reason: HandshakeFailure::Dropped,
+                    });
+                }
+                Some(filter) => {
+                    // Like the pong provider, the filter runs alongside the
+                    // loop.
+                    connection_manager.deciding.insert(addr);
+                    let decision = filter(attempt);
+                    tasks.push(
+                        async move { TaskResultWapper::AcceptDecided(attempt, decision.await) }
+                            .boxed(),
+                    );
+                }
+                None => open_connection(tasks, connection_manager, context, attempt).await,
+            }
+        }
+
+        _ => {
+            let passed = context.offline.pass(
+                addr,
+                datagram.clone(),
+                socket,
+                &context.capture,
+                &context.events,
+            );
+            if !passed {
+                malformed();
+            }
+        }
+    }
+}
+
+/// Answers an OpenConnectionRequest2 that passed the accept filter and sets
+/// up the connection.
+async fn open_connection(
+    tasks: &mut TaskManager,
+    connection_manager: &mut ConnectionManager,
+    context: &ListenerContext,
+    attempt: ConnectionAttempt,
+) {
+    let addr = attempt.address;
+    let guid = context.guid;
+    if let Some(connection) = connection_manager.connections.get(&addr) {
+        if connection.conn.is_connected() {
+            let alreadyconnected = AlreadyConnected {
                 magic: true,
                 server_guid: guid,
-                client_address: addr,
-                mtu: openconnectionrequest2.mtu,
-                encrypion_enabled: false,
             };
-            socket
-                .send_to(&encode(openconnectionreply2, 0x8).unwrap(), addr)
-                .await
-                .unwrap();
-
-            let (to_stream_sender, to_stream_receiver) = mpsc::channel(8);
-            let (to_conn_sender, to_conn_receiver) = mpsc::channel(8);
-
-            let conn =
-                Conn::incoming_connection(socket.clone(), to_stream_sender, to_conn_receiver);
-            /*let stream = RakStream {
-                msg_receiver: to_stream_receiver,
-                msg_sender: to_conn_sender,
-            };
-            let info = StreamInformation {
-                guid,
+            debug!(%addr, "rejecting client that is already connected");
+            context.send_packet(alreadyconnected, 0x12, addr).await;
+            context.events.emit(ListenerEvent::HandshakeFailed {
                 address: addr,
-            };
-
-            new_stream_sender.send((stream, info)).await.unwrap();*/
+                reason: HandshakeFailure::AlreadyConnected,
+            });
+            return;
         }
+    }
+
+    let retry = connection_manager.connections.contains_key(&addr);
+    if !retry && connection_manager.backlog_full() {
+        debug!(%addr, "rejecting client while accept is not keeping up");
+        context.reject(addr, RejectReason::ServerFull).await;
+        return;
+    }
+
+    let openconnectionreply2 = OpenConnectionReply2 {
+        magic: true,
+        server_guid: guid,
+        client_address: addr,
+        mtu: attempt.mtu as i16,
+        encrypion_enabled: false,
+    };
+    context.send_packet(openconnectionreply2, 0x8, addr).await;
 
-        _ => {}
+    if retry {
+        // The client retried because our reply was lost.
+        debug!(%addr, "resent open connection reply 2");
+        return;
     }
+
+    Metrics::add(&context.events.metrics().handshakes_started, 1);
+    let (to_stream_sender, to_stream_receiver) = mpsc::unbounded();
+    let (to_conn_sender, to_conn_receiver) = mpsc::channel(8);
+
+    let conn = Conn::incoming_connection(
+        context.socket.clone(),
+        addr,
+        guid,
+        attempt.mtu,
+        context.clock.clone(),
+        context.events.clone(),
+        context.capture.clone(),
+        to_stream_sender,
+        context.limits.clone(),
+    );
+    let stream = RakStream {
+        msg_receiver: to_stream_receiver,
+        msg_sender: to_conn_sender,
+        state: ConnState::of(&conn),
+    };
+    let (forget, forgotten) = oneshot::channel();
+    let id = connection_manager.insert(addr, conn, stream, attempt.guid, forget);
+    tasks.push(receive_conn_msg(addr, id, to_conn_receiver, forgotten));
 }
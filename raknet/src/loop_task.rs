@@ -1,16 +1,37 @@
 use std::{
     pin::Pin,
-    task::Context,
+    task::{Context, Poll},
     thread::{self, JoinHandle},
 };
 
 use futures::{executor::block_on, Future, FutureExt};
 
+type Task = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// The loop driving a listener or a client connection. Nothing happens until
+/// the caller spawns it or polls it.
+///
+/// A sharded listener runs one loop per shard, all polled from whichever task
+/// polls the `LoopTask`. `into_shards` hands them out separately instead, so
+/// they can be spawned onto different threads.
 pub struct LoopTask {
-    pub task: Pin<Box<dyn Future<Output = ()> + Send>>,
+    tasks: Vec<Task>,
 }
 
 impl LoopTask {
+    pub(crate) fn new(task: Task) -> Self {
+        Self { tasks: vec![task] }
+    }
+
+    pub(crate) fn join(tasks: Vec<Task>) -> Self {
+        Self { tasks }
+    }
+
+    /// One `LoopTask` per shard, each finishing with its own loop.
+    pub fn into_shards(self) -> Vec<LoopTask> {
+        self.tasks.into_iter().map(LoopTask::new).collect()
+    }
+
     pub fn run_in_new_thread(self) -> JoinHandle<()> {
         thread::spawn(|| block_on(self.boxed()))
     }
@@ -19,7 +40,12 @@ impl LoopTask {
 impl Future for LoopTask {
     type Output = ();
 
-    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> std::task::Poll<Self::Output> {
-        self.task.poll_unpin(cx)
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.tasks
+            .retain_mut(|task| task.poll_unpin(cx).is_pending());
+        match self.tasks.is_empty() {
+            true => Poll::Ready(()),
+            false => Poll::Pending,
+        }
     }
 }
@@ -0,0 +1,172 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::event::ListenerEvent;
+
+/// Counters aggregated over every connection of a `Listener`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ListenerMetrics {
+    /// Connections past the handshake and not yet closed.
+    pub active_connections: u64,
+    /// Connections created by an accepted `OpenConnectionRequest2`.
+    pub handshakes_started: u64,
+    pub handshakes_completed: u64,
+    pub handshakes_failed: u64,
+    /// Connection attempts refused because the address is banned.
+    pub bans: u64,
+    pub pings_answered: u64,
+    pub malformed_packets: u64,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+    pub datagrams_sent: u64,
+    /// Datagrams whose reliable frames had to be sent again.
+    pub datagrams_resent: u64,
+}
+
+impl ListenerMetrics {
+    /// `datagrams_resent / datagrams_sent`.
+    pub fn resend_ratio(&self) -> f64 {
+        if self.datagrams_sent == 0 {
+            0.0
+        } else {
+            self.datagrams_resent as f64 / self.datagrams_sent as f64
+        }
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let metrics = [
+            (
+                "raknet_active_connections",
+                "gauge",
+                "Connections past the handshake and not yet closed.",
+                self.active_connections as f64,
+            ),
+            (
+                "raknet_handshakes_started_total",
+                "counter",
+                "Connections created by OpenConnectionRequest2.",
+                self.handshakes_started as f64,
+            ),
+            (
+                "raknet_handshakes_completed_total",
+                "counter",
+                "Handshakes that reached the connected state.",
+                self.handshakes_completed as f64,
+            ),
+            (
+                "raknet_handshakes_failed_total",
+                "counter",
+                "Handshakes that were rejected or timed out.",
+                self.handshakes_failed as f64,
+            ),
+            (
+                "raknet_bans_total",
+                "counter",
+                "Connection attempts refused because the address is banned.",
+                self.bans as f64,
+            ),
+            (
+                "raknet_pings_answered_total",
+                "counter",
+                "Offline pings answered with a pong.",
+                self.pings_answered as f64,
+            ),
+            (
+                "raknet_malformed_packets_total",
+                "counter",
+                "Datagrams dropped because they could not be decoded.",
+                self.malformed_packets as f64,
+            ),
+            (
+                "raknet_received_bytes_total",
+                "counter",
+                "Bytes received on the listener socket.",
+                self.bytes_received as f64,
+            ),
+            (
+                "raknet_sent_bytes_total",
+                "counter",
+                "Bytes sent on the listener socket.",
+                self.bytes_sent as f64,
+            ),
+            (
+                "raknet_sent_datagrams_total",
+                "counter",
+                "Frame set datagrams sent to connections.",
+                self.datagrams_sent as f64,
+            ),
+            (
+                "raknet_resent_datagrams_total",
+                "counter",
+                "Frame set datagrams that had to be resent.",
+                self.datagrams_resent as f64,
+            ),
+            (
+                "raknet_resend_ratio",
+                "gauge",
+                "Resent datagrams per sent datagram.",
+                self.resend_ratio(),
+            ),
+        ];
+
+        let mut output = String::new();
+        for (name, kind, help, value) in metrics {
+            _ = writeln!(output, "# HELP {} {}", name, help);
+            _ = writeln!(output, "# TYPE {} {}", name, kind);
+            _ = writeln!(output, "{} {}", name, value);
+        }
+        output
+    }
+}
+
+/// The live counters behind `ListenerMetrics`, shared by the listener loop and
+/// its connections.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    pub active_connections: AtomicU64,
+    pub handshakes_started: AtomicU64,
+    pub handshakes_completed: AtomicU64,
+    pub handshakes_failed: AtomicU64,
+    pub bans: AtomicU64,
+    pub pings_answered: AtomicU64,
+    pub malformed_packets: AtomicU64,
+    pub bytes_received: AtomicU64,
+    pub bytes_sent: AtomicU64,
+    pub datagrams_sent: AtomicU64,
+    pub datagrams_resent: AtomicU64,
+}
+
+impl Metrics {
+    pub fn add(counter: &AtomicU64, value: u64) {
+        counter.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn record_event(&self, event: &ListenerEvent) {
+        match event {
+            ListenerEvent::MalformedPacket { .. } => Self::add(&self.malformed_packets, 1),
+            ListenerEvent::HandshakeFailed { .. } => Self::add(&self.handshakes_failed, 1),
+            ListenerEvent::Connected(_) => Self::add(&self.handshakes_completed, 1),
+            _ => {}
+        }
+    }
+
+    pub fn snapshot(&self) -> ListenerMetrics {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        ListenerMetrics {
+            active_connections: load(&self.active_connections),
+            handshakes_started: load(&self.handshakes_started),
+            handshakes_completed: load(&self.handshakes_completed),
+            handshakes_failed: load(&self.handshakes_failed),
+            bans: load(&self.bans),
+            pings_answered: load(&self.pings_answered),
+            malformed_packets: load(&self.malformed_packets),
+            bytes_received: load(&self.bytes_received),
+            bytes_sent: load(&self.bytes_sent),
+            datagrams_sent: load(&self.datagrams_sent),
+            datagrams_resent: load(&self.datagrams_resent),
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use bytes::Bytes;
+use futures::channel::mpsc;
+
+use crate::{
+    capture::CaptureHook, event::EventBroadcaster, metrics::Metrics, socket::DatagramSocket,
+};
+
+const OFFLINE_BUFFER: usize = 64;
+
+/// A datagram from a peer without a connection that is not part of the
+/// RakNet handshake, such as a query or health probe sharing the listener's
+/// port.
+pub struct OfflinePacket {
+    pub address: SocketAddr,
+    pub payload: Bytes,
+    socket: Arc<DatagramSocket>,
+    capture: CaptureHook,
+    events: EventBroadcaster,
+}
+
+impl OfflinePacket {
+    /// Sends `payload` to the peer from the socket this packet arrived on.
+    pub async fn reply(&self, payload: &[u8]) -> std::io::Result<()> {
+        let size = self.socket.send_to(payload, self.address).await?;
+        self.capture.sent(self.address, payload);
+        Metrics::add(&self.events.metrics().bytes_sent, size as u64);
+        Ok(())
+    }
+}
+
+/// The receiver of offline packets shared by every shard of a listener.
+#[derive(Clone, Default)]
+pub(crate) struct OfflineHook {
+    sender: Arc<Mutex<Option<mpsc::Sender<OfflinePacket>>>>,
+}
+
+impl OfflineHook {
+    pub fn subscribe(&self) -> mpsc::Receiver<OfflinePacket> {
+        let (sender, receiver) = mpsc::channel(OFFLINE_BUFFER);
+        *self.sender.lock().unwrap() = Some(sender);
+        receiver
+    }
+
+    /// Hands the datagram to the receiver. Returns `false` if there is none,
+    /// so the caller can treat it as malformed instead.
+    pub fn pass(
+        &self,
+        address: SocketAddr,
+        payload: Bytes,
+        socket: &Arc<DatagramSocket>,
+        capture: &CaptureHook,
+        events: &EventBroadcaster,
+    ) -> bool {
+        let mut sender = self.sender.lock().unwrap();
+        let Some(subscriber) = sender.as_mut() else {
+            return false;
+        };
+        let packet = OfflinePacket {
+            address,
+            payload,
+            socket: socket.clone(),
+            capture: capture.clone(),
+            events: events.clone(),
+        };
+        match subscriber.try_send(packet) {
+            Ok(()) => true,
+            // Dropped, like events, rather than stalling the listener loop.
+            Err(err) if err.is_full() => true,
+            Err(_) => {
+                *sender = None;
+                false
+            }
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// How far ahead of the rate a pacer may send. Connections are updated
+/// every tick, so this has to cover a couple of ticks for the full rate to
+/// be reachable.
+const BURST: Duration = Duration::from_millis(20);
+
+/// A token bucket that spreads datagrams out to `rate` bytes per second.
+///
+/// A datagram may be sent whenever the bucket is not in debt, and its size
+/// is then taken out, so datagrams larger than the burst still get through.
+pub(crate) struct Pacer {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+/// A pacer shared by every connection of a listener, with the number of
+/// connections currently waiting on it.
+pub(crate) type SharedPacer = Arc<Mutex<(Pacer, usize)>>;
+
+impl Pacer {
+    pub fn new(rate: u64, now: Instant) -> Self {
+        let rate = rate.max(1) as f64;
+        let burst = rate * BURST.as_secs_f64();
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            updated: now,
+        }
+    }
+
+    pub fn shared(rate: u64, now: Instant) -> SharedPacer {
+        Arc::new(Mutex::new((Self::new(rate, now), 0)))
+    }
+
+    /// Changes the rate from `now` on, keeping the debt.
+    fn set_rate(&mut self, rate: f64, now: Instant) {
+        self.refill(now);
+        self.rate = rate.max(1.0);
+        self.burst = self.rate * BURST.as_secs_f64();
+        self.tokens = self.tokens.min(self.burst);
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.updated = now.max(self.updated);
+    }
+
+    pub fn is_ready(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= 0.0
+    }
+
+    /// Takes `size` bytes out of the bucket, whether or not it is ready.
+    pub fn consume(&mut self, size: usize) {
+        self.tokens -= size as f64;
+    }
+
+    /// When the bucket is next out of debt.
+    pub fn ready_at(&self) -> Instant {
+        match self.tokens >= 0.0 {
+            true => self.updated,
+            false => self.updated + Duration::from_secs_f64(-self.tokens / self.rate),
+        }
+    }
+}
+
+/// A connection's part of a `SharedPacer`.
+///
+/// Whichever connection flushed first would otherwise take all the shared
+/// bandwidth. Instead, every connection the shared pacer holds back counts
+/// as waiting on it, and each is kept to an equal part of the rate by a
+/// bucket of its own. Connections held back by anything else do not count,
+/// so they do not take a part they cannot use.
+pub(crate) struct Share {
+    shared: SharedPacer,
+    bucket: Pacer,
+    waiting: bool,
+}
+
+impl Share {
+    pub fn new(shared: SharedPacer, now: Instant) -> Self {
+        let rate = shared.lock().map_or(1.0, |shared| shared.0.rate);
+        Self {
+            shared,
+            bucket: Pacer::new(rate as u64, now),
+            waiting: false,
+        }
+    }
+
+    pub fn is_ready(&mut self, now: Instant) -> bool {
+        let Ok(mut shared) = self.shared.lock() else {
+            return true;
+        };
+        let (pacer, waiting) = &mut *shared;
+        let rate = pacer.rate / (*waiting).max(1) as f64;
+        self.bucket.set_rate(rate, now);
+        pacer.is_ready(now) && self.bucket.is_ready(now)
+    }
+
+    pub fn consume(&mut self, size: usize) {
+        self.bucket.consume(size);
+        if let Ok(mut shared) = self.shared.lock() {
+            shared.0.consume(size);
+        }
+    }
+
+    pub fn ready_at(&self) -> Instant {
+        let shared = self.shared.lock().map(|shared| shared.0.ready_at());
+        self.bucket
+            .ready_at()
+            .max(shared.unwrap_or(self.bucket.updated))
+    }
+
+    /// Whether the connection has frames that only the shared pacer holds
+    /// back.
+    pub fn set_waiting(&mut self, waiting: bool) {
+        if waiting == self.waiting {
+            return;
+        }
+        self.waiting = waiting;
+        if let Ok(mut shared) = self.shared.lock() {
+            match waiting {
+                true => shared.1 += 1,
+                false => shared.1 -= 1,
+            }
+        }
+    }
+}
+
+impl Drop for Share {
+    fn drop(&mut self) {
+        self.set_waiting(false);
+    }
+}
+
+/// The bandwidth limits a connection sends under.
+#[derive(Clone, Default)]
+pub(crate) struct BandwidthLimits {
+    /// Bytes per second for the connection alone.
+    pub connection: Option<u64>,
+    pub shared: Option<SharedPacer>,
+}
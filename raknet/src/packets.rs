@@ -7,6 +7,7 @@ use crate::bytes::{Magic, RakAddress, RakString};
 
 pub fn decode<P: Den>(buffer: &[u8]) -> std::io::Result<P> {
     let mut cursor = std::io::Cursor::new(buffer);
+    cursor.set_position(1);
     P::decode(&mut cursor)
 }
 
@@ -27,7 +28,7 @@ pub struct UnconnectedPing {
     pub client_guid: i64,
 }
 
-#[derive(Clone, Den)]
+#[derive(Debug, Clone, Den)]
 pub struct UnconnectedPong {
     #[den(with = "Big")]
     pub time: i64,
@@ -42,15 +43,15 @@ pub struct UnconnectedPong {
 #[derive(Clone, Den)]
 pub struct ConnectedPing {
     #[den(with = "Big")]
-    time: i64,
+    pub time: i64,
 }
 
 #[derive(Clone, Den)]
 pub struct ConnectedPong {
     #[den(with = "Big")]
-    ping_time: i64,
+    pub ping_time: i64,
     #[den(with = "Big")]
-    pong_time: i64,
+    pub pong_time: i64,
 }
 
 #[derive(Clone)]
@@ -120,24 +121,31 @@ pub struct OpenConnectionReply2 {
 #[derive(Clone, Den)]
 pub struct ConnectionRequest {
     #[den(with = "Big")]
-    guid: i64,
+    pub guid: i64,
     #[den(with = "Big")]
-    time: i64,
+    pub time: i64,
+    pub use_security: bool,
+}
+
+const SYSTEM_ADDRESS_COUNT: usize = 10;
+
+fn empty_system_address() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 0))
 }
 
 #[derive(Clone)]
 pub struct ConnectionRequestAccepted {
-    client_address: SocketAddr,
-    system_index: i16,
-    request_time: i64,
-    time: i64,
+    pub client_address: SocketAddr,
+    pub system_index: i16,
+    pub request_time: i64,
+    pub time: i64,
 }
 
 impl Den for ConnectionRequestAccepted {
     fn decode(bytes: &mut std::io::Cursor<&[u8]>) -> std::io::Result<Self> {
         let client_address = <RakAddress as DenWith<SocketAddr>>::decode(bytes)?;
         let system_index = <Big as DenWith<i16>>::decode(bytes)?;
-        bytes.set_position(bytes.get_ref().len() as u64 - 16);
+        bytes.set_position(bytes.get_ref().len().saturating_sub(16) as u64);
         let request_time = <Big as DenWith<i64>>::decode(bytes)?;
         let time = <Big as DenWith<i64>>::decode(bytes)?;
         Ok(Self {
@@ -151,27 +159,71 @@ impl Den for ConnectionRequestAccepted {
     fn encode(&self, bytes: &mut std::io::Cursor<Vec<u8>>) -> std::io::Result<()> {
         <RakAddress as DenWith<SocketAddr>>::encode(&self.client_address, bytes)?;
         <Big as DenWith<i16>>::encode(&self.system_index, bytes)?;
-        bytes.write_all(&[6u8; 10])?;
+        for _ in 0..SYSTEM_ADDRESS_COUNT {
+            <RakAddress as DenWith<SocketAddr>>::encode(&empty_system_address(), bytes)?;
+        }
         <Big as DenWith<i64>>::encode(&self.request_time, bytes)?;
         <Big as DenWith<i64>>::encode(&self.time, bytes)
     }
 
     fn size(&self) -> usize {
-        <RakAddress as DenWith<SocketAddr>>::size(&self.client_address) + 28
+        <RakAddress as DenWith<SocketAddr>>::size(&self.client_address)
+            + 2
+            + SYSTEM_ADDRESS_COUNT
+                * <RakAddress as DenWith<SocketAddr>>::size(&empty_system_address())
+            + 16
     }
 }
 
-#[derive(Clone, Den)]
+#[derive(Clone)]
 pub struct NewIncomingConnection {
-    #[den(with = "RakAddress")]
-    server_address: SocketAddr,
-    #[den(with = "RakAddress")]
-    internal_address: SocketAddr,
+    pub server_address: SocketAddr,
+    pub ping_time: i64,
+    pub pong_time: i64,
+}
+
+impl Den for NewIncomingConnection {
+    fn decode(bytes: &mut std::io::Cursor<&[u8]>) -> std::io::Result<Self> {
+        let server_address = <RakAddress as DenWith<SocketAddr>>::decode(bytes)?;
+        bytes.set_position(bytes.get_ref().len().saturating_sub(16) as u64);
+        let ping_time = <Big as DenWith<i64>>::decode(bytes)?;
+        let pong_time = <Big as DenWith<i64>>::decode(bytes)?;
+        Ok(Self {
+            server_address,
+            ping_time,
+            pong_time,
+        })
+    }
+
+    fn encode(&self, bytes: &mut std::io::Cursor<Vec<u8>>) -> std::io::Result<()> {
+        <RakAddress as DenWith<SocketAddr>>::encode(&self.server_address, bytes)?;
+        for _ in 0..SYSTEM_ADDRESS_COUNT {
+            <RakAddress as DenWith<SocketAddr>>::encode(&empty_system_address(), bytes)?;
+        }
+        <Big as DenWith<i64>>::encode(&self.ping_time, bytes)?;
+        <Big as DenWith<i64>>::encode(&self.pong_time, bytes)
+    }
+
+    fn size(&self) -> usize {
+        <RakAddress as DenWith<SocketAddr>>::size(&self.server_address)
+            + SYSTEM_ADDRESS_COUNT
+                * <RakAddress as DenWith<SocketAddr>>::size(&empty_system_address())
+            + 16
+    }
+}
+
+#[derive(Clone, Den)]
+pub struct AlreadyConnected {
+    #[den(with = "Magic")]
+    pub magic: bool,
+    #[den(with = "Big")]
+    pub server_guid: i64,
 }
 
 #[derive(Clone, Den)]
 pub struct IncompatibleProtocolVersion {
     pub server_protocol: u8,
+    #[den(with = "Magic")]
     pub magic: bool,
     #[den(with = "Big")]
     pub server_guid: i64,
@@ -0,0 +1,116 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io::{Error, ErrorKind},
+    sync::Arc,
+    time::Duration,
+};
+
+use async_std::net::ToSocketAddrs;
+
+use crate::{
+    clock::{default_clock, Clock},
+    packets::*,
+    stream::{bind_unspecified, is_unreachable, receive_timeout, resolve},
+    ServerAdvertisement,
+};
+
+/// Options for `ping_with_config`.
+#[derive(Clone)]
+pub struct PingConfig {
+    pub attempts: usize,
+    /// How long to wait for a pong before sending the next ping.
+    pub attempt_timeout: Duration,
+    pub guid: i64,
+    pub clock: Arc<dyn Clock>,
+}
+
+impl Default for PingConfig {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            attempt_timeout: Duration::from_secs(1),
+            guid: RandomState::new().build_hasher().finish() as i64,
+            clock: default_clock(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PingResponse {
+    pub pong: UnconnectedPong,
+    /// Round-trip time of the ping that was answered.
+    pub latency: Duration,
+}
+
+impl PingResponse {
+    pub fn advertisement(&self) -> std::io::Result<ServerAdvertisement> {
+        self.pong.server_id.parse()
+    }
+}
+
+/// Sends `UnconnectedPing` to a server and waits for its `UnconnectedPong`.
+pub async fn ping<A: ToSocketAddrs>(addrs: A) -> std::io::Result<PingResponse> {
+    ping_with_config(addrs, PingConfig::default()).await
+}
+
+pub async fn ping_with_config<A: ToSocketAddrs>(
+    addrs: A,
+    config: PingConfig,
+) -> std::io::Result<PingResponse> {
+    let addr = resolve(addrs).await?;
+    let socket = bind_unspecified(addr).await?;
+    let clock = &config.clock;
+    let start = clock.now();
+    let mut sent = vec![];
+    let mut buffer = [0u8; 4096];
+    let mut error = Error::new(ErrorKind::TimedOut, "server did not respond");
+
+    for _ in 0..config.attempts {
+        let sent_at = clock.now();
+        let time = sent_at.duration_since(start).as_millis() as i64;
+        let ping = UnconnectedPing {
+            time,
+            magic: true,
+            client_guid: config.guid,
+        };
+        socket.send_to(&encode(ping, 0x1)?, addr).await?;
+        sent.push((time, sent_at));
+
+        let deadline = sent_at + config.attempt_timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(clock.now());
+            if remaining.is_zero() {
+                break;
+            }
+            let (size, from) = match receive_timeout(&socket, &mut buffer, clock, remaining).await {
+                Ok(Some(received)) => received,
+                Ok(None) => continue,
+                // Nothing listens on the port right now. The next attempt
+                // may find the server up, but only goes out once this one
+                // has timed out, as some platforms report the refusal
+                // right away.
+                Err(err) if is_unreachable(&err) => {
+                    error = err;
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            if from != addr || size == 0 || buffer[0] != 0x1c {
+                continue;
+            }
+            let Ok(pong) = decode::<UnconnectedPong>(&buffer[..size]) else {
+                continue;
+            };
+            // A late pong may answer an earlier attempt.
+            if let Some((_, sent_at)) = sent.iter().rev().find(|(time, _)| *time == pong.time) {
+                return Ok(PingResponse {
+                    latency: clock.now().saturating_duration_since(*sent_at),
+                    pong,
+                });
+            }
+        }
+    }
+
+    Err(error)
+}
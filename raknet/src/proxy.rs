@@ -0,0 +1,290 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_std::net::ToSocketAddrs;
+use futures::{
+    channel::mpsc, future, lock::Mutex, select, stream::FuturesUnordered, Future, FutureExt,
+    StreamExt,
+};
+
+use crate::{
+    clock::Clock,
+    instrument::debug,
+    loop_task::LoopTask,
+    ping::{ping_with_config, PingConfig},
+    stream::resolve,
+    ConnectConfig, Listener, ListenerConfig, RakStream, RakStreamReceiver, RakStreamSender,
+    StreamInformation,
+};
+
+/// How long an upstream pong answers pings to the proxy. Clients on a
+/// server list ping about once a second each, which should not turn into
+/// as many pings upstream.
+const PONG_TTL: Duration = Duration::from_secs(2);
+
+/// Which way a payload travels through a `Proxy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    ClientToServer,
+    ServerToClient,
+}
+
+/// Sees every payload a `Proxy` relays and returns what to forward in its
+/// place, or `None` to drop it.
+pub type PacketHook = Arc<
+    dyn Fn(
+            ProxySession,
+            Direction,
+            Vec<u8>,
+        ) -> Pin<Box<dyn Future<Output = Option<Vec<u8>>> + Send>>
+        + Send
+        + Sync,
+>;
+
+/// Options for `Proxy::bind_with_config`.
+#[derive(Clone, Default)]
+pub struct ProxyConfig {
+    pub listener: ListenerConfig,
+    /// Used for every upstream connection, with the GUID replaced by the
+    /// client's so the server sees the same GUID.
+    pub connect: ConnectConfig,
+}
+
+/// A client relayed by a `Proxy` and its connection to the upstream server.
+#[derive(Clone)]
+pub struct ProxySession {
+    info: StreamInformation,
+    client: RakStreamSender,
+    server: RakStreamSender,
+}
+
+impl ProxySession {
+    /// The client, as accepted by the proxy's listener.
+    pub fn info(&self) -> &StreamInformation {
+        &self.info
+    }
+
+    /// Sends `payload` on to wherever `direction` leads, without passing it
+    /// through the packet hook.
+    pub async fn inject(&self, direction: Direction, payload: Vec<u8>) {
+        let mut sender = match direction {
+            Direction::ClientToServer => self.server.clone(),
+            Direction::ServerToClient => self.client.clone(),
+        };
+        sender.send(payload).await;
+    }
+
+    /// Disconnects both the client and the upstream connection.
+    pub fn disconnect(&self) {
+        self.client.clone().disconnect();
+        self.server.clone().disconnect();
+    }
+}
+
+/// Accepts clients with a `Listener` and relays each to an upstream server
+/// over a `RakStream` of its own, passing every payload through the packet
+/// hook on the way.
+pub struct Proxy {
+    listener: Listener,
+    incoming: Mutex<mpsc::Receiver<(RakStream, StreamInformation)>>,
+    upstream: SocketAddr,
+    connect: ConnectConfig,
+    hook: Arc<Mutex<Option<PacketHook>>>,
+}
+
+impl Proxy {
+    pub async fn bind<A: ToSocketAddrs, U: ToSocketAddrs>(
+        addrs: A,
+        upstream: U,
+    ) -> std::io::Result<(Self, LoopTask)> {
+        Self::bind_with_config(addrs, upstream, ProxyConfig::default()).await
+    }
+
+    /// Pings are answered with the `server_id` of the upstream server, so
+    /// the proxy shows up like the server it relays to. The upstream pong is
+    /// reused for a couple of seconds.
+    pub async fn bind_with_config<A: ToSocketAddrs, U: ToSocketAddrs>(
+        addrs: A,
+        upstream: U,
+        config: ProxyConfig,
+    ) -> std::io::Result<(Self, LoopTask)> {
+        let upstream = resolve(upstream).await?;
+        let guid = RandomState::new().build_hasher().finish() as i64;
+        let (mut listener, loop_task) =
+            Listener::bind_with_config(addrs, guid, "", config.listener).await?;
+
+        let clock = config.connect.clock.clone();
+        let cache = Arc::new(Mutex::new(None));
+        listener
+            .set_pong_provider(move |_, _| {
+                upstream_server_id(upstream, clock.clone(), cache.clone())
+            })
+            .await;
+
+        Ok((
+            Self {
+                incoming: Mutex::new(listener.take_incoming()),
+                listener,
+                upstream,
+                connect: config.connect,
+                hook: Arc::default(),
+            },
+            loop_task,
+        ))
+    }
+
+    /// The listener clients connect to, for its events, metrics and
+    /// connection administration.
+    pub fn listener(&self) -> &Listener {
+        &self.listener
+    }
+
+    pub fn upstream(&self) -> SocketAddr {
+        self.upstream
+    }
+
+    /// Passes every payload relayed from now on through `hook`.
+    pub async fn set_packet_hook<F, Fut>(&self, hook: F)
+    where
+        F: Fn(ProxySession, Direction, Vec<u8>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<Vec<u8>>> + Send + 'static,
+    {
+        let hook: PacketHook =
+            Arc::new(move |session, direction, payload| hook(session, direction, payload).boxed());
+        *self.hook.lock().await = Some(hook);
+    }
+
+    pub async fn clear_packet_hook(&self) {
+        *self.hook.lock().await = None;
+    }
+
+    /// Accepts clients and relays them, all from the task that runs this.
+    /// Only returns once the listener stops accepting and every session has
+    /// ended. Only one call runs at a time; others wait for it to return.
+    pub async fn run(&self) {
+        let mut incoming = self.incoming.lock().await;
+        let mut sessions = FuturesUnordered::new();
+        loop {
+            select! {
+                accepted = incoming.next() => {
+                    let Some((client, info)) = accepted else {
+                        break;
+                    };
+                    let mut connect = self.connect.clone();
+                    connect.guid = info.guid;
+                    let hook = self.hook.clone();
+                    sessions.push(relay(client, info, self.upstream, connect, hook));
+                }
+                _ = sessions.select_next_some() => {}
+            }
+        }
+        while sessions.next().await.is_some() {}
+    }
+
+    pub fn destroy(self) {
+        self.listener.destroy();
+    }
+}
+
+/// The upstream server's `server_id`, pinging it only once `PONG_TTL` has
+/// passed since the last time. Pings that arrive meanwhile wait for that
+/// one instead of pinging too. When the server does not answer, the last
+/// `server_id` it sent stays in use.
+async fn upstream_server_id(
+    upstream: SocketAddr,
+    clock: Arc<dyn Clock>,
+    cache: Arc<Mutex<Option<(Instant, String)>>>,
+) -> String {
+    let mut cache = cache.lock().await;
+    let now = clock.now();
+    if let Some((fetched, server_id)) = &*cache {
+        if now.saturating_duration_since(*fetched) < PONG_TTL {
+            return server_id.clone();
+        }
+    }
+
+    let config = PingConfig {
+        attempts: 1,
+        clock: clock.clone(),
+        ..Default::default()
+    };
+    let server_id = match ping_with_config(upstream, config).await {
+        Ok(response) => response.pong.server_id,
+        Err(_) => cache
+            .take()
+            .map(|(_, server_id)| server_id)
+            .unwrap_or_default(),
+    };
+    *cache = Some((now, server_id.clone()));
+    server_id
+}
+
+/// Relays one client until either side disconnects, then disconnects the
+/// other. Also drives the upstream connection until it has closed.
+async fn relay(
+    client: RakStream,
+    info: StreamInformation,
+    upstream: SocketAddr,
+    connect: ConnectConfig,
+    hook: Arc<Mutex<Option<PacketHook>>>,
+) {
+    let (server, server_loop) = match RakStream::connect_with_config(upstream, connect).await {
+        Ok(connected) => connected,
+        Err(_) => {
+            debug!(address = %info.address, "upstream connection failed");
+            client.disconnect();
+            return;
+        }
+    };
+
+    let (client_sender, client_receiver) = client.split();
+    let (server_sender, server_receiver) = server.split();
+    let session = ProxySession {
+        info,
+        client: client_sender,
+        server: server_sender,
+    };
+    let to_server = pump(
+        session.clone(),
+        client_receiver,
+        Direction::ClientToServer,
+        hook.clone(),
+    );
+    let to_client = pump(
+        session.clone(),
+        server_receiver,
+        Direction::ServerToClient,
+        hook,
+    );
+    let relaying = async {
+        future::select(to_server.boxed(), to_client.boxed()).await;
+        session.disconnect();
+    };
+    // The upstream loop keeps running after the relay ends, to send the
+    // disconnect notification.
+    future::join(relaying, server_loop).await;
+}
+
+async fn pump(
+    session: ProxySession,
+    mut receiver: RakStreamReceiver,
+    direction: Direction,
+    hook: Arc<Mutex<Option<PacketHook>>>,
+) {
+    while let Some(payload) = receiver.receive().await {
+        let hook = hook.lock().await.clone();
+        let payload = match hook {
+            Some(hook) => hook(session.clone(), direction, payload).await,
+            None => Some(payload),
+        };
+        if let Some(payload) = payload {
+            session.inject(direction, payload).await;
+        }
+    }
+}
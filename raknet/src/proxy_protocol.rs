@@ -0,0 +1,103 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Mutex,
+};
+
+use bytes::Bytes;
+
+use crate::instrument::debug;
+
+/// Every PROXY protocol v2 header starts with these bytes.
+const SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+const HEADER_SIZE: usize = 16;
+
+/// Client addresses learned from PROXY protocol v2 headers.
+///
+/// A load balancer gives each client flow its own source port and prefixes
+/// the first datagram of the flow with a header naming the client. Later
+/// datagrams from that port are attributed to the client, and datagrams for
+/// the client are sent back through the port. Both maps hold one entry per
+/// balancer port, as a new header for a port replaces its client.
+#[derive(Default)]
+pub(crate) struct ProxyFlows {
+    flows: Mutex<Flows>,
+}
+
+#[derive(Default)]
+struct Flows {
+    clients: HashMap<SocketAddr, SocketAddr>,
+    peers: HashMap<SocketAddr, SocketAddr>,
+}
+
+impl ProxyFlows {
+    /// Strips the header off a datagram from `peer` and returns it with the
+    /// client address. Returns `None` for datagrams with an invalid header or
+    /// nothing after it.
+    pub fn inbound(&self, mut datagram: Bytes, peer: SocketAddr) -> Option<(Bytes, SocketAddr)> {
+        let mut flows = self.flows.lock().unwrap();
+        if datagram.starts_with(&SIGNATURE) {
+            let header = parse_header(&datagram);
+            if header.is_none() {
+                debug!(%peer, "dropped datagram with invalid PROXY header");
+            }
+            let (size, client) = header?;
+            if let Some(client) = client {
+                if let Some(previous) = flows.clients.insert(peer, client) {
+                    flows.peers.remove(&previous);
+                }
+                flows.peers.insert(client, peer);
+            }
+            datagram = datagram.slice(size..);
+        }
+        if datagram.is_empty() {
+            return None;
+        }
+        let address = flows.clients.get(&peer).copied().unwrap_or(peer);
+        Some((datagram, address))
+    }
+
+    /// The address datagrams for `address` are sent to.
+    pub fn outbound(&self, address: SocketAddr) -> SocketAddr {
+        let flows = self.flows.lock().unwrap();
+        flows.peers.get(&address).copied().unwrap_or(address)
+    }
+}
+
+/// Returns the size of the header and, for proxied flows, the client
+/// address. Health checks by the balancer itself (LOCAL) carry no address.
+fn parse_header(buffer: &[u8]) -> Option<(usize, Option<SocketAddr>)> {
+    let header = buffer.get(..HEADER_SIZE)?;
+    let (version, command) = (header[12] >> 4, header[12] & 0x0f);
+    if version != 2 || command > 1 {
+        return None;
+    }
+    let len = u16::from_be_bytes([header[14], header[15]]) as usize;
+    let addresses = buffer.get(HEADER_SIZE..HEADER_SIZE + len)?;
+    let size = HEADER_SIZE + len;
+    if command == 0 {
+        return Some((size, None));
+    }
+
+    let port = |bytes: &[u8]| u16::from_be_bytes([bytes[0], bytes[1]]);
+    // Only the family is checked; the transport is whatever the balancer
+    // received on.
+    let client = match header[13] >> 4 {
+        // Unspecified or Unix socket addresses say nothing about the client.
+        0 | 3 => None,
+        1 => {
+            let addresses = addresses.get(..12)?;
+            let ip: [u8; 4] = addresses[..4].try_into().ok()?;
+            let ip = IpAddr::V4(Ipv4Addr::from(ip));
+            Some(SocketAddr::new(ip, port(&addresses[8..])))
+        }
+        2 => {
+            let addresses = addresses.get(..36)?;
+            let ip: [u8; 16] = addresses[..16].try_into().ok()?;
+            let ip = IpAddr::V6(Ipv6Addr::from(ip));
+            Some(SocketAddr::new(ip, port(&addresses[32..])))
+        }
+        _ => return None,
+    };
+    Some((size, client))
+}
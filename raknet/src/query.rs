@@ -0,0 +1,206 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hash, Hasher},
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::Future;
+
+/// Query packets start with these two bytes instead of a RakNet ID.
+pub(crate) const QUERY_MAGIC: [u8; 2] = [0xfe, 0xfd];
+const HANDSHAKE: u8 = 0x09;
+const STAT: u8 = 0x00;
+/// A challenge token is accepted for one to two of these periods.
+const TOKEN_PERIOD: Duration = Duration::from_secs(30);
+const SPLITNUM: &[u8] = b"splitnum\0\x80\0";
+const PLAYER_SECTION: &[u8] = b"\x01player_\0\0";
+
+/// The server information reported to GameSpy4 (UT3) queries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryInfo {
+    pub motd: String,
+    pub game_type: String,
+    pub game_id: String,
+    pub version: String,
+    pub server_engine: String,
+    pub plugins: String,
+    pub map: String,
+    pub online_players: u32,
+    pub max_players: u32,
+    pub whitelist: bool,
+    pub host_ip: String,
+    pub host_port: u16,
+}
+
+impl Default for QueryInfo {
+    fn default() -> Self {
+        Self {
+            motd: String::new(),
+            game_type: "SMP".to_owned(),
+            game_id: "MINECRAFTPE".to_owned(),
+            version: String::new(),
+            server_engine: String::new(),
+            plugins: String::new(),
+            map: String::new(),
+            online_players: 0,
+            max_players: 0,
+            whitelist: false,
+            host_ip: String::new(),
+            host_port: 19132,
+        }
+    }
+}
+
+/// Computes the `QueryInfo` for each stat query.
+pub type QueryInfoProvider =
+    Arc<dyn Fn() -> Pin<Box<dyn Future<Output = QueryInfo> + Send>> + Send + Sync>;
+/// Lists the names of online players for each full stat query.
+pub type PlayerListProvider =
+    Arc<dyn Fn() -> Pin<Box<dyn Future<Output = Vec<String>> + Send>> + Send + Sync>;
+
+#[derive(Clone)]
+pub(crate) struct QueryProviders {
+    pub info: QueryInfoProvider,
+    pub players: PlayerListProvider,
+}
+
+pub(crate) enum QueryRequest {
+    Handshake { session: [u8; 4] },
+    Stat { session: [u8; 4], full: bool },
+}
+
+/// Parses a query packet, checking the challenge token of stat requests.
+/// Invalid packets are `None`.
+pub(crate) fn parse_request(
+    buffer: &[u8],
+    addr: SocketAddr,
+    tokens: &QueryTokens,
+    now: Instant,
+) -> Option<QueryRequest> {
+    let rest = buffer.strip_prefix(&QUERY_MAGIC)?;
+    let (&kind, rest) = rest.split_first()?;
+    let session = rest.get(..4)?.try_into().ok()?;
+    match kind {
+        HANDSHAKE => Some(QueryRequest::Handshake { session }),
+        STAT => {
+            let token = i32::from_be_bytes(rest.get(4..8)?.try_into().ok()?);
+            if !tokens.verify(addr, token, now) {
+                return None;
+            }
+            // Full stat requests are padded with four more bytes.
+            let full = rest.len() >= 12;
+            Some(QueryRequest::Stat { session, full })
+        }
+        _ => None,
+    }
+}
+
+pub(crate) fn handshake_response(session: [u8; 4], token: i32) -> Vec<u8> {
+    let mut response = vec![HANDSHAKE];
+    response.extend(session);
+    response.extend(token.to_string().as_bytes());
+    response.push(0);
+    response
+}
+
+pub(crate) fn basic_stat_response(session: [u8; 4], info: &QueryInfo) -> Vec<u8> {
+    let mut response = vec![STAT];
+    response.extend(session);
+    for value in [
+        &info.motd,
+        &info.game_type,
+        &info.map,
+        &info.online_players.to_string(),
+        &info.max_players.to_string(),
+    ] {
+        push_string(&mut response, value);
+    }
+    response.extend(info.host_port.to_le_bytes());
+    push_string(&mut response, &info.host_ip);
+    response
+}
+
+pub(crate) fn full_stat_response(
+    session: [u8; 4],
+    info: &QueryInfo,
+    players: &[String],
+) -> Vec<u8> {
+    let mut response = vec![STAT];
+    response.extend(session);
+    response.extend(SPLITNUM);
+    let whitelist = if info.whitelist { "on" } else { "off" };
+    for (key, value) in [
+        ("hostname", info.motd.as_str()),
+        ("gametype", &info.game_type),
+        ("game_id", &info.game_id),
+        ("version", &info.version),
+        ("server_engine", &info.server_engine),
+        ("plugins", &info.plugins),
+        ("map", &info.map),
+        ("numplayers", &info.online_players.to_string()),
+        ("maxplayers", &info.max_players.to_string()),
+        ("whitelist", whitelist),
+        ("hostip", &info.host_ip),
+        ("hostport", &info.host_port.to_string()),
+    ] {
+        push_string(&mut response, key);
+        push_string(&mut response, value);
+    }
+    response.push(0);
+    response.extend(PLAYER_SECTION);
+    for player in players {
+        push_string(&mut response, player);
+    }
+    response.push(0);
+    response
+}
+
+/// Strings are null terminated, so any nulls inside them are dropped.
+fn push_string(buffer: &mut Vec<u8>, value: &str) {
+    buffer.extend(value.bytes().filter(|byte| *byte != 0));
+    buffer.push(0);
+}
+
+/// Issues challenge tokens without keeping per-client state: a token is a
+/// keyed hash of the client address and the current period.
+#[derive(Clone)]
+pub(crate) struct QueryTokens {
+    key: RandomState,
+    start: Instant,
+}
+
+impl QueryTokens {
+    pub fn new(start: Instant) -> Self {
+        Self {
+            key: RandomState::new(),
+            start,
+        }
+    }
+
+    pub fn issue(&self, addr: SocketAddr, now: Instant) -> i32 {
+        self.token(addr, self.period(now))
+    }
+
+    /// Tokens from the previous period stay valid so that one issued just
+    /// before a rollover still works.
+    pub fn verify(&self, addr: SocketAddr, token: i32, now: Instant) -> bool {
+        let period = self.period(now);
+        token == self.token(addr, period) || (period > 0 && token == self.token(addr, period - 1))
+    }
+
+    fn period(&self, now: Instant) -> u64 {
+        let elapsed = now.saturating_duration_since(self.start);
+        elapsed.as_secs() / TOKEN_PERIOD.as_secs()
+    }
+
+    fn token(&self, addr: SocketAddr, period: u64) -> i32 {
+        let mut hasher = self.key.build_hasher();
+        addr.hash(&mut hasher);
+        period.hash(&mut hasher);
+        // Clients parse the token as a signed 32-bit number.
+        hasher.finish() as i32 & i32::MAX
+    }
+}
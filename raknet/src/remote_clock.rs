@@ -0,0 +1,260 @@
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::clock::Clock;
+
+/// Samples kept for the estimate. At one ping every five seconds this spans
+/// a few minutes.
+const WINDOW: usize = 32;
+/// Drift is only estimated once the samples span this many milliseconds;
+/// over shorter spans jitter swamps it.
+const MIN_DRIFT_SPAN: f64 = 30_000.0;
+/// Real clocks drift by tens of parts per million, so anything beyond this
+/// is noise.
+const MAX_DRIFT_PPM: f64 = 500.0;
+
+/// How the peer's clock relates to ours, estimated from the timestamps of
+/// the handshake and of every `ConnectedPing`/`ConnectedPong` exchange.
+///
+/// Times are RakNet timestamps, in milliseconds since each side set up the
+/// connection, as returned by `RakStream::local_time`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RemoteTimeOffset {
+    /// Remote time minus local time now, in milliseconds.
+    pub offset_ms: f64,
+    /// How much faster the remote clock runs than ours, in parts per
+    /// million. Zero until the samples span enough time to tell.
+    pub drift_ppm: f64,
+    /// Number of round trips the estimate is based on.
+    pub samples: usize,
+}
+
+struct Sample {
+    /// Local time halfway through the round trip.
+    local: f64,
+    /// Remote time minus `local`.
+    offset: f64,
+    rtt: f64,
+}
+
+/// A line fitted through the samples: `offset(t) = offset + slope * (t -
+/// local)`.
+#[derive(Clone, Copy)]
+struct Fit {
+    local: f64,
+    offset: f64,
+    slope: f64,
+}
+
+/// Fits the offset to the peer's clock over a window of samples, shared
+/// between a connection and its `RakStream`.
+pub(crate) struct RemoteClock {
+    clock: Arc<dyn Clock>,
+    created: Instant,
+    samples: VecDeque<Sample>,
+    fit: Option<Fit>,
+}
+
+impl RemoteClock {
+    pub fn new(clock: Arc<dyn Clock>, created: Instant) -> Self {
+        Self {
+            clock,
+            created,
+            samples: VecDeque::with_capacity(WINDOW),
+            fit: None,
+        }
+    }
+
+    /// Milliseconds since the connection was created, as sent in pings.
+    pub fn local_time(&self) -> i64 {
+        let now = self.clock.now();
+        now.saturating_duration_since(self.created).as_millis() as i64
+    }
+
+    /// Adds a round trip that left at local `ping_time`, was stamped
+    /// `pong_time` by the peer and came back at local `now`.
+    pub fn add_sample(&mut self, ping_time: i64, pong_time: i64, now: i64) {
+        if ping_time > now {
+            return;
+        }
+        let local = (ping_time + now) as f64 / 2.0;
+        if self.samples.len() == WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample {
+            local,
+            offset: pong_time as f64 - local,
+            rtt: (now - ping_time) as f64,
+        });
+        self.fit = Some(self.refit());
+    }
+
+    pub fn estimate(&self) -> Option<RemoteTimeOffset> {
+        let fit = self.fit?;
+        let now = self.local_time() as f64;
+        Some(RemoteTimeOffset {
+            offset_ms: fit.offset + fit.slope * (now - fit.local),
+            drift_ppm: fit.slope * 1_000_000.0,
+            samples: self.samples.len(),
+        })
+    }
+
+    /// The local instant at which the peer's clock read `remote_time`.
+    pub fn to_local(&self, remote_time: i64) -> Option<Instant> {
+        let fit = self.fit?;
+        // remote = t + offset + slope * (t - local), solved for t.
+        let local = (remote_time as f64 - fit.offset + fit.slope * fit.local) / (1.0 + fit.slope);
+        let delta = Duration::from_secs_f64(local.abs() / 1000.0);
+        match local >= 0.0 {
+            true => self.created.checked_add(delta),
+            false => self.created.checked_sub(delta),
+        }
+    }
+
+    /// Weighted least squares. Samples with a round trip close to the
+    /// fastest seen are weighted most, as their midpoint is least skewed by
+    /// queueing on one leg.
+    fn refit(&self) -> Fit {
+        let min_rtt = self
+            .samples
+            .iter()
+            .map(|sample| sample.rtt)
+            .fold(f64::INFINITY, f64::min);
+        let weight = |sample: &Sample| 1.0 / (1.0 + sample.rtt - min_rtt).powi(2);
+
+        let total = self.samples.iter().map(weight).sum::<f64>();
+        let mean = |value: fn(&Sample) -> f64| {
+            self.samples
+                .iter()
+                .map(|sample| weight(sample) * value(sample))
+                .sum::<f64>()
+                / total
+        };
+        let local = mean(|sample| sample.local);
+        let offset = mean(|sample| sample.offset);
+
+        let first = self.samples.front().map_or(0.0, |sample| sample.local);
+        let last = self.samples.back().map_or(0.0, |sample| sample.local);
+        let mut slope = 0.0;
+        if last - first >= MIN_DRIFT_SPAN {
+            let (mut covariance, mut variance) = (0.0, 0.0);
+            for sample in &self.samples {
+                let dx = sample.local - local;
+                covariance += weight(sample) * dx * (sample.offset - offset);
+                variance += weight(sample) * dx * dx;
+            }
+            if variance > 0.0 {
+                let max = MAX_DRIFT_PPM / 1_000_000.0;
+                slope = (covariance / variance).clamp(-max, max);
+            }
+        }
+
+        Fit {
+            local,
+            offset,
+            slope,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    /// A peer whose clock reads `offset_ms + t * (1 + drift_ppm / 10^6)` at our
+    /// time `t`, ten milliseconds away each way.
+    struct Peer {
+        clock: ManualClock,
+        remote: RemoteClock,
+        offset_ms: f64,
+        drift_ppm: f64,
+    }
+
+    impl Peer {
+        fn new(offset_ms: f64, drift_ppm: f64) -> Self {
+            let clock = ManualClock::new();
+            let remote = RemoteClock::new(Arc::new(clock.clone()), clock.now());
+            Self {
+                clock,
+                remote,
+                offset_ms,
+                drift_ppm,
+            }
+        }
+
+        /// A ping whose way out was held up by `queued_ms`, then `interval_ms`
+        /// until the next one.
+        fn round_trip(&mut self, queued_ms: u64, interval_ms: u64) {
+            let ping = self.remote.local_time();
+            self.clock.advance(Duration::from_millis(10 + queued_ms));
+            let stamped = self.remote.local_time() as f64;
+            let pong = self.offset_ms + stamped * (1.0 + self.drift_ppm / 1_000_000.0);
+            self.clock.advance(Duration::from_millis(10));
+            let now = self.remote.local_time();
+            self.remote.add_sample(ping, pong.round() as i64, now);
+            self.clock.advance(Duration::from_millis(interval_ms));
+        }
+
+        fn true_offset(&self) -> f64 {
+            self.offset_ms + self.remote.local_time() as f64 * self.drift_ppm / 1_000_000.0
+        }
+    }
+
+    #[test]
+    fn fit_favours_the_fastest_round_trips() {
+        let mut peer = Peer::new(-250.0, 0.0);
+        // Queueing on the way out skews a sample by half the delay, so an
+        // unweighted mean of these would be off by about 50 milliseconds.
+        for queued_ms in [0, 300, 2, 150, 1, 0, 200, 3, 0, 250] {
+            peer.round_trip(queued_ms, 1000);
+        }
+
+        let estimate = peer.remote.estimate().unwrap();
+        assert_eq!(estimate.samples, 10);
+        assert!((estimate.offset_ms + 250.0).abs() < 3.0, "{:?}", estimate);
+        // Ten seconds of samples are too few to tell drift from jitter.
+        assert_eq!(estimate.drift_ppm, 0.0);
+    }
+
+    #[test]
+    fn fit_estimates_drift() {
+        let mut peer = Peer::new(1000.0, 200.0);
+        for i in 0..20 {
+            peer.round_trip(i % 3, 5000);
+        }
+
+        let estimate = peer.remote.estimate().unwrap();
+        assert!((estimate.drift_ppm - 200.0).abs() < 10.0, "{:?}", estimate);
+        let error = estimate.offset_ms - peer.true_offset();
+        assert!(error.abs() < 3.0, "{:?}", estimate);
+
+        // Converting back follows the drift as well.
+        let remote_now = peer.true_offset() + peer.remote.local_time() as f64;
+        let converted = peer.remote.to_local(remote_now.round() as i64).unwrap();
+        let now = peer.clock.now();
+        let error = match converted > now {
+            true => converted - now,
+            false => now - converted,
+        };
+        assert!(error < Duration::from_millis(3), "{:?}", error);
+    }
+
+    #[test]
+    fn drift_is_clamped() {
+        let mut peer = Peer::new(0.0, 5000.0);
+        for _ in 0..20 {
+            peer.round_trip(0, 5000);
+        }
+        assert_eq!(peer.remote.estimate().unwrap().drift_ppm, 500.0);
+
+        let mut peer = Peer::new(0.0, -5000.0);
+        for _ in 0..20 {
+            peer.round_trip(0, 5000);
+        }
+        assert_eq!(peer.remote.estimate().unwrap().drift_ppm, -500.0);
+    }
+}
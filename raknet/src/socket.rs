@@ -0,0 +1,149 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use async_std::net::UdpSocket;
+use bytes::Bytes;
+
+#[cfg(target_os = "linux")]
+use crate::{
+    batch::{BatchSocket, BATCH_SIZE},
+    buffer::MAX_DATAGRAM_SIZE,
+};
+use crate::{buffer::ReceiveBuffer, proxy_protocol::ProxyFlows, socks::SocksRelay};
+
+/// The socket the loops and connections read and write through.
+///
+/// On Linux, datagrams are received and sent in batches with `recvmmsg` and
+/// `sendmmsg`. Elsewhere, or if the batched socket cannot be set up, every
+/// datagram is its own syscall.
+///
+/// With PROXY protocol enabled, the addresses going in and out are those of
+/// the clients behind the load balancer. With a SOCKS5 relay, every
+/// datagram goes through the relay and the addresses are those of the peers
+/// beyond it.
+pub(crate) struct DatagramSocket {
+    socket: Arc<UdpSocket>,
+    #[cfg(target_os = "linux")]
+    batch: Option<BatchSocket>,
+    proxy: Option<ProxyFlows>,
+    socks: Option<SocksRelay>,
+}
+
+impl DatagramSocket {
+    pub fn new(socket: Arc<UdpSocket>) -> Self {
+        Self {
+            #[cfg(target_os = "linux")]
+            batch: BatchSocket::new(&socket).ok(),
+            socket,
+            proxy: None,
+            socks: None,
+        }
+    }
+
+    /// Reads PROXY protocol v2 headers off received datagrams.
+    pub fn with_proxy_protocol(mut self) -> Self {
+        self.proxy = Some(ProxyFlows::default());
+        self
+    }
+
+    /// Sends and receives every datagram through `relay`.
+    pub fn with_socks(mut self, relay: SocksRelay) -> Self {
+        self.socks = Some(relay);
+        self
+    }
+
+    pub async fn send_to(&self, buffer: &[u8], addr: SocketAddr) -> std::io::Result<usize> {
+        if let Some(socks) = &self.socks {
+            let datagram = socks.wrap(buffer, addr);
+            self.socket.send_to(&datagram, socks.relay()).await?;
+            return Ok(buffer.len());
+        }
+        let addr = self.route(addr);
+        self.socket.send_to(buffer, addr).await
+    }
+
+    /// Sends every datagram to `addr`. Returns how many were sent, and the
+    /// error that stopped the rest of the batch.
+    pub async fn send_many(
+        &self,
+        datagrams: &[Vec<u8>],
+        addr: SocketAddr,
+    ) -> (usize, std::io::Result<()>) {
+        if let Some(socks) = &self.socks {
+            let datagrams = datagrams
+                .iter()
+                .map(|datagram| socks.wrap(datagram, addr))
+                .collect::<Vec<_>>();
+            return self.send_batch(&datagrams, socks.relay()).await;
+        }
+        self.send_batch(datagrams, self.route(addr)).await
+    }
+
+    async fn send_batch(
+        &self,
+        datagrams: &[Vec<u8>],
+        addr: SocketAddr,
+    ) -> (usize, std::io::Result<()>) {
+        #[cfg(target_os = "linux")]
+        if let Some(batch) = &self.batch {
+            return batch.send(datagrams, addr).await;
+        }
+
+        for (sent, datagram) in datagrams.iter().enumerate() {
+            if let Err(err) = self.socket.send_to(datagram, addr).await {
+                return (sent, Err(err));
+            }
+        }
+        (datagrams.len(), Ok(()))
+    }
+
+    /// Waits for at least one datagram and returns every datagram that is
+    /// already queued, up to a batch.
+    pub async fn recv_many(
+        &self,
+        buffer: &mut ReceiveBuffer,
+    ) -> std::io::Result<Vec<(Bytes, SocketAddr)>> {
+        let datagrams = self.recv_batch(buffer).await?;
+        Ok(match (&self.proxy, &self.socks) {
+            (Some(proxy), _) => datagrams
+                .into_iter()
+                .filter_map(|(datagram, peer)| proxy.inbound(datagram, peer))
+                .collect(),
+            (_, Some(socks)) => datagrams
+                .into_iter()
+                .filter_map(|(datagram, from)| socks.unwrap(datagram, from))
+                .collect(),
+            (None, None) => datagrams,
+        })
+    }
+
+    async fn recv_batch(
+        &self,
+        buffer: &mut ReceiveBuffer,
+    ) -> std::io::Result<Vec<(Bytes, SocketAddr)>> {
+        #[cfg(target_os = "linux")]
+        if let Some(batch) = &self.batch {
+            let slots = buffer.prepare_slots(BATCH_SIZE);
+            let received = batch.recv(slots, MAX_DATAGRAM_SIZE).await?;
+            return Ok(received
+                .into_iter()
+                .filter_map(|(size, addr)| {
+                    // Every slot is taken so the next one lines up.
+                    // SAFETY: `recvmmsg` wrote `size` bytes into each slot it
+                    // reports, in order.
+                    let datagram = unsafe { buffer.take_slot(size) };
+                    Some((datagram, addr?))
+                })
+                .collect());
+        }
+
+        let (size, addr) = self.socket.recv_from(buffer.prepare()).await?;
+        Ok(vec![(buffer.take(size), addr)])
+    }
+
+    fn route(&self, addr: SocketAddr) -> SocketAddr {
+        match &self.proxy {
+            Some(proxy) => proxy.outbound(addr),
+            None => addr,
+        }
+    }
+}
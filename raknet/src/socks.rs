@@ -0,0 +1,198 @@
+use std::{
+    io::{Error, ErrorKind},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use async_std::net::TcpStream;
+use bytes::Bytes;
+use futures::{AsyncReadExt, AsyncWriteExt};
+
+const VERSION: u8 = 0x05;
+const NO_AUTHENTICATION: u8 = 0x00;
+const USERNAME_PASSWORD: u8 = 0x02;
+const USERNAME_PASSWORD_VERSION: u8 = 0x01;
+const UDP_ASSOCIATE: u8 = 0x03;
+const SUCCEEDED: u8 = 0x00;
+const IPV4: u8 = 0x01;
+const IPV6: u8 = 0x04;
+
+/// A SOCKS5 proxy to send a client's datagrams through, using UDP
+/// ASSOCIATE.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Socks5Config {
+    /// The TCP address of the proxy.
+    pub proxy: SocketAddr,
+    /// A username and password, for proxies that require them.
+    pub credentials: Option<(String, String)>,
+}
+
+/// A UDP association with a SOCKS5 proxy. The proxy relays datagrams for as
+/// long as the TCP connection it was negotiated on stays open.
+pub(crate) struct SocksRelay {
+    relay: SocketAddr,
+    _control: TcpStream,
+}
+
+impl SocksRelay {
+    pub async fn associate(config: &Socks5Config) -> std::io::Result<Self> {
+        let mut control = TcpStream::connect(config.proxy).await?;
+        let method = match config.credentials {
+            Some(_) => USERNAME_PASSWORD,
+            None => NO_AUTHENTICATION,
+        };
+        control.write_all(&[VERSION, 1, method]).await?;
+        let mut reply = [0u8; 2];
+        control.read_exact(&mut reply).await?;
+        if reply[0] != VERSION {
+            return Err(invalid_reply());
+        }
+        if reply[1] != method {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "SOCKS5 proxy refused the authentication method",
+            ));
+        }
+        if let Some((username, password)) = &config.credentials {
+            authenticate(&mut control, username, password).await?;
+        }
+
+        // The address the client sends from is unknown behind NAT, so it is
+        // left unspecified.
+        let mut request = vec![VERSION, UDP_ASSOCIATE, 0];
+        encode_address(&mut request, (Ipv4Addr::UNSPECIFIED, 0).into());
+        control.write_all(&request).await?;
+        let mut reply = [0u8; 4];
+        control.read_exact(&mut reply).await?;
+        if reply[0] != VERSION {
+            return Err(invalid_reply());
+        }
+        if reply[1] != SUCCEEDED {
+            return Err(Error::new(
+                ErrorKind::ConnectionRefused,
+                format!("SOCKS5 proxy refused UDP ASSOCIATE with code {}", reply[1]),
+            ));
+        }
+        let mut address = vec![reply[3]];
+        address.resize(1 + address_size(reply[3]).ok_or_else(invalid_reply)?, 0);
+        control.read_exact(&mut address[1..]).await?;
+        let (relay, _) = decode_address(&address).ok_or_else(invalid_reply)?;
+        // An unspecified address stands for the proxy's own.
+        let relay = match relay.ip().is_unspecified() {
+            true => SocketAddr::new(config.proxy.ip(), relay.port()),
+            false => relay,
+        };
+
+        Ok(Self {
+            relay,
+            _control: control,
+        })
+    }
+
+    /// Where every wrapped datagram is sent.
+    pub fn relay(&self) -> SocketAddr {
+        self.relay
+    }
+
+    /// Prefixes `payload` with the SOCKS UDP header for `target`, which is
+    /// `header_size(target)` bytes long.
+    pub fn wrap(&self, payload: &[u8], target: SocketAddr) -> Vec<u8> {
+        // Two reserved bytes and the fragment number; fragments are not
+        // used.
+        let mut datagram = vec![0, 0, 0];
+        encode_address(&mut datagram, target);
+        datagram.extend_from_slice(payload);
+        datagram
+    }
+
+    /// Strips the SOCKS UDP header off a datagram from the relay. Returns
+    /// the payload and the address it came from, or `None` for datagrams
+    /// from anywhere else, fragments and invalid headers.
+    pub fn unwrap(&self, datagram: Bytes, from: SocketAddr) -> Option<(Bytes, SocketAddr)> {
+        if from != self.relay || datagram.get(2) != Some(&0) {
+            return None;
+        }
+        let (source, size) = decode_address(datagram.get(3..)?)?;
+        Some((datagram.slice(3 + size..), source))
+    }
+}
+
+/// Size of the SOCKS UDP header in front of a datagram for `target`: the
+/// reserved bytes, the fragment number and the address.
+pub(crate) fn header_size(target: SocketAddr) -> usize {
+    match target {
+        SocketAddr::V4(_) => 10,
+        SocketAddr::V6(_) => 22,
+    }
+}
+
+/// Username and password authentication (RFC 1929).
+async fn authenticate(
+    control: &mut TcpStream,
+    username: &str,
+    password: &str,
+) -> std::io::Result<()> {
+    let mut request = vec![USERNAME_PASSWORD_VERSION];
+    for field in [username, password] {
+        let len = u8::try_from(field.len()).map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "SOCKS5 credentials are limited to 255 bytes",
+            )
+        })?;
+        request.push(len);
+        request.extend_from_slice(field.as_bytes());
+    }
+    control.write_all(&request).await?;
+    let mut reply = [0u8; 2];
+    control.read_exact(&mut reply).await?;
+    if reply[1] != SUCCEEDED {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "SOCKS5 proxy rejected the credentials",
+        ));
+    }
+    Ok(())
+}
+
+fn encode_address(buffer: &mut Vec<u8>, addr: SocketAddr) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            buffer.push(IPV4);
+            buffer.extend(ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buffer.push(IPV6);
+            buffer.extend(ip.octets());
+        }
+    }
+    buffer.extend(addr.port().to_be_bytes());
+}
+
+/// The size of an address of type `kind` after the type byte, port
+/// included. Domain names are not supported; proxies answer with IPs.
+fn address_size(kind: u8) -> Option<usize> {
+    match kind {
+        IPV4 => Some(4 + 2),
+        IPV6 => Some(16 + 2),
+        _ => None,
+    }
+}
+
+/// Decodes an address starting with its type byte and returns it with the
+/// number of bytes it took.
+fn decode_address(buffer: &[u8]) -> Option<(SocketAddr, usize)> {
+    let (&kind, rest) = buffer.split_first()?;
+    let size = address_size(kind)?;
+    let rest = rest.get(..size)?;
+    let (ip, port) = rest.split_at(size - 2);
+    let ip = match kind {
+        IPV4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(ip).ok()?)),
+        _ => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(ip).ok()?)),
+    };
+    let port = u16::from_be_bytes([port[0], port[1]]);
+    Some((SocketAddr::new(ip, port), 1 + size))
+}
+
+fn invalid_reply() -> Error {
+    Error::new(ErrorKind::InvalidData, "invalid SOCKS5 reply")
+}
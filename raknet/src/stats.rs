@@ -0,0 +1,38 @@
+use std::time::Duration;
+
+/// A snapshot of a connection's reliability layer, refreshed whenever the
+/// connection sends datagrams or ticks.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConnectionStats {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub datagrams_sent: u64,
+    pub datagrams_received: u64,
+    /// Sent datagrams that carried frames, leaving out ACKs and NACKs.
+    pub frame_sets_sent: u64,
+    /// Reliable frames queued again after a NACK or a resend timeout.
+    pub frames_resent: u64,
+    /// Frames a disconnect gave up on, still held back by the bandwidth
+    /// limits when the connection closed.
+    pub frames_dropped: u64,
+    /// Datagrams the peer reported missing or that were never acknowledged.
+    pub datagrams_lost: u64,
+    /// Sequence numbers we reported missing.
+    pub nacks_sent: u64,
+    /// Sequence numbers the peer reported missing.
+    pub nacks_received: u64,
+    /// Smoothed round trip time, `None` until the first ACK arrives.
+    pub rtt: Option<Duration>,
+    pub rtt_variance: Duration,
+    /// Reliable datagrams sent but not yet acknowledged.
+    pub in_flight: usize,
+    /// `datagrams_lost / frame_sets_sent`.
+    pub packet_loss: f64,
+    /// Frames waiting to be packed into a datagram, per priority in the
+    /// order of `Priority::ALL`.
+    pub queue_depth: [usize; 4],
+    /// Bytes the congestion window lets be unacknowledged at once.
+    pub congestion_window: usize,
+    /// Time since the handshake completed, zero while connecting.
+    pub connected_for: Duration,
+}
@@ -1,26 +1,232 @@
-use std::net::SocketAddr;
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io::{Error, ErrorKind},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
 
 use async_std::net::{ToSocketAddrs, UdpSocket};
-use futures::{channel::mpsc, SinkExt, StreamExt};
+use bytes::Bytes;
+use futures::{
+    channel::mpsc,
+    future::{self, Either},
+    pin_mut,
+    stream::FuturesUnordered,
+    Future, FutureExt, Sink, SinkExt, Stream, StreamExt,
+};
 
 use crate::{
-    conn::{ToConnMsg, ToStreamMsg},
+    buffer::ReceiveBuffer,
+    capture::{Capture, CaptureHook},
+    clock::{default_clock, Clock},
+    conn::{Conn, ToConnMsg, ToStreamMsg, MAX_MTU, MIN_MTU, UDP_HEADER_SIZE},
+    event::EventBroadcaster,
+    frame::Priority,
+    listener::TICK_INTERVAL,
     loop_task::LoopTask,
+    pacer::BandwidthLimits,
+    packets::*,
+    remote_clock::{RemoteClock, RemoteTimeOffset},
+    socket::DatagramSocket,
+    socks::{self, Socks5Config, SocksRelay},
+    stats::ConnectionStats,
+    RAKNET_PROTOCOL_VERSION,
 };
 
+const MTU_SIZES: [usize; 3] = [1492, 1200, 576];
+const OFFLINE_ATTEMPTS_PER_MTU: usize = 4;
+const OFFLINE_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Options for `RakStream::connect_with_config`.
+#[derive(Clone)]
+pub struct ConnectConfig {
+    pub guid: i64,
+    pub clock: Arc<dyn Clock>,
+    /// Captures the connection from the first handshake packet on.
+    pub capture: Option<Capture>,
+    /// Sends every datagram through a SOCKS5 proxy with UDP ASSOCIATE.
+    pub socks5: Option<Socks5Config>,
+    /// Caps what the connection sends, in bytes per second, pacing the
+    /// rest out over the following ticks.
+    pub bandwidth: Option<u64>,
+}
+
+impl Default for ConnectConfig {
+    fn default() -> Self {
+        Self {
+            guid: RandomState::new().build_hasher().finish() as i64,
+            clock: default_clock(),
+            capture: None,
+            socks5: None,
+            bandwidth: None,
+        }
+    }
+}
+
 pub struct RakStream {
-    pub(crate) msg_receiver: mpsc::Receiver<ToStreamMsg>,
+    pub(crate) msg_receiver: mpsc::UnboundedReceiver<ToStreamMsg>,
     pub(crate) msg_sender: mpsc::Sender<ToConnMsg>,
+    pub(crate) state: ConnState,
+}
+
+/// What a stream, and both halves of a split one, read of the connection's
+/// state.
+#[derive(Clone)]
+pub(crate) struct ConnState {
+    stats: Arc<Mutex<ConnectionStats>>,
+    remote_clock: Arc<Mutex<RemoteClock>>,
+    closed: Arc<AtomicBool>,
+}
+
+impl ConnState {
+    pub fn of(conn: &Conn) -> Self {
+        Self {
+            stats: conn.stats(),
+            remote_clock: conn.remote_clock(),
+            closed: conn.closed_flag(),
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    fn stats(&self) -> ConnectionStats {
+        self.stats
+            .lock()
+            .map(|stats| stats.clone())
+            .unwrap_or_default()
+    }
+
+    fn local_time(&self) -> i64 {
+        self.remote_clock
+            .lock()
+            .map(|remote_clock| remote_clock.local_time())
+            .unwrap_or_default()
+    }
+
+    fn remote_time_offset(&self) -> Option<RemoteTimeOffset> {
+        self.remote_clock.lock().ok()?.estimate()
+    }
+
+    fn remote_to_local(&self, remote_time: i64) -> Option<Instant> {
+        self.remote_clock.lock().ok()?.to_local(remote_time)
+    }
 }
 
 impl RakStream {
     pub async fn connect<A: ToSocketAddrs>(addrs: A) -> std::io::Result<(Self, LoopTask)> {
-        let socket = UdpSocket::bind(addrs).await?;
+        Self::connect_with_config(addrs, ConnectConfig::default()).await
+    }
+
+    pub async fn connect_with_config<A: ToSocketAddrs>(
+        addrs: A,
+        config: ConnectConfig,
+    ) -> std::io::Result<(Self, LoopTask)> {
+        let addr = resolve(addrs).await?;
+        let relay = match &config.socks5 {
+            Some(socks5) => Some(SocksRelay::associate(socks5).await?),
+            None => None,
+        };
+        // Through a proxy, every datagram goes to its relay, behind a SOCKS
+        // UDP header that leaves less room for the datagram itself.
+        let remote = relay.as_ref().map_or(addr, SocksRelay::relay);
+        let overhead = relay.as_ref().map_or(0, |_| socks::header_size(addr));
+        let socket = Arc::new(bind_unspecified(remote).await?);
+        let capture = CaptureHook::default();
+        capture.set(config.capture.clone(), socket.local_addr()?);
+        let datagram_socket = match relay {
+            Some(relay) => DatagramSocket::new(socket).with_socks(relay),
+            None => DatagramSocket::new(socket),
+        };
+        let datagram_socket = Arc::new(datagram_socket);
+        let mut buffer = ReceiveBuffer::new();
+        let mtu = open_connection(
+            &datagram_socket,
+            &mut buffer,
+            addr,
+            &config,
+            &capture,
+            overhead,
+        )
+        .await?;
+
+        let (to_stream_sender, to_stream_receiver) = mpsc::unbounded();
+        let (to_conn_sender, to_conn_receiver) = mpsc::channel(8);
+        let mut conn = Conn::outgoing_connection(
+            datagram_socket.clone(),
+            addr,
+            config.guid,
+            mtu,
+            config.clock.clone(),
+            EventBroadcaster::default(),
+            capture.clone(),
+            to_stream_sender,
+            BandwidthLimits {
+                connection: config.bandwidth,
+                shared: None,
+            },
+        );
 
-        todo!();
+        conn.request_connection().await;
+        while !conn.is_connected() {
+            if conn.is_closed() {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    "connection handshake timed out",
+                ));
+            }
+            let received = or_timeout(
+                datagram_socket.recv_many(&mut buffer),
+                &config.clock,
+                TICK_INTERVAL,
+            )
+            .await
+            .transpose()?;
+            for (datagram, from) in received.into_iter().flatten() {
+                capture.received(from, &datagram);
+                if from == addr {
+                    conn.handle(datagram).await;
+                }
+            }
+            conn.update().await;
+        }
+
+        let stream = RakStream {
+            msg_receiver: to_stream_receiver,
+            msg_sender: to_conn_sender,
+            state: ConnState::of(&conn),
+        };
+        let loop_task = LoopTask::new(
+            client_loop(
+                datagram_socket,
+                addr,
+                conn,
+                to_conn_receiver,
+                config.clock,
+                capture,
+                buffer,
+            )
+            .boxed(),
+        );
+        Ok((stream, loop_task))
     }
 
     pub async fn receive(&mut self) -> Option<Vec<u8>> {
+        self.receive_bytes().await.map(Vec::from)
+    }
+
+    /// Like `receive`, but hands out the payload without copying it out of
+    /// the receive buffer. Holding on to it keeps that buffer alive, so
+    /// long-lived payloads are better copied.
+    pub async fn receive_bytes(&mut self) -> Option<Bytes> {
         self.msg_receiver.next().await.map(|msg| {
             let ToStreamMsg::Packet(packet) = msg;
             packet
@@ -28,51 +234,462 @@ impl RakStream {
     }
 
     pub async fn send(&mut self, bytes: Vec<u8>) {
-        self.msg_sender.send(ToConnMsg::Send(bytes)).await.unwrap();
+        self.send_with_priority(bytes, Priority::default()).await;
+    }
+
+    /// Like `send`, queueing the packet ahead of those with a lower
+    /// priority. As in RakNet, every packet shares one ordering channel, so
+    /// the peer still delivers them in the order they were sent; priority
+    /// decides which queued packet goes on the wire first.
+    pub async fn send_with_priority(&mut self, bytes: Vec<u8>, priority: Priority) {
+        _ = self.msg_sender.send(ToConnMsg::Send(bytes, priority)).await;
+    }
+
+    /// Like `send`, failing with `NotConnected` once the connection is
+    /// closed.
+    pub(crate) async fn try_send(&mut self, bytes: Vec<u8>) -> Result<(), Error> {
+        if self.state.is_closed() {
+            return Err(not_connected());
+        }
+        let msg = ToConnMsg::Send(bytes, Priority::default());
+        self.msg_sender.send(msg).await.map_err(closed)
+    }
+
+    pub fn stats(&self) -> ConnectionStats {
+        self.state.stats()
+    }
+
+    /// The RakNet time this side stamps on pings, in milliseconds since the
+    /// connection was set up. The peer's `remote_to_local` turns it into an
+    /// `Instant` of its own, which makes it a timestamp to send along with
+    /// events that need ordering or lag compensation.
+    pub fn local_time(&self) -> i64 {
+        self.state.local_time()
+    }
+
+    /// The offset and drift of the peer's RakNet time relative to
+    /// `local_time`. `None` until the handshake has produced a sample.
+    pub fn remote_time_offset(&self) -> Option<RemoteTimeOffset> {
+        self.state.remote_time_offset()
+    }
+
+    /// The local instant at which the peer's `local_time` was
+    /// `remote_time`, or `None` without an estimate yet.
+    pub fn remote_to_local(&self, remote_time: i64) -> Option<Instant> {
+        self.state.remote_to_local(remote_time)
     }
 
     pub fn split(self) -> (RakStreamSender, RakStreamReceiver) {
         (
             RakStreamSender {
                 msg_sender: self.msg_sender,
+                state: self.state.clone(),
             },
             RakStreamReceiver {
                 msg_receiver: self.msg_receiver,
+                state: self.state,
             },
         )
     }
 
-    pub fn disconnect(self) {}
+    pub fn disconnect(mut self) {
+        _ = self.msg_sender.try_send(ToConnMsg::Disconnect);
+    }
 }
 
+/// The sending half of a `RakStream`.
+///
+/// Every packet is sent reliable and ordered. As a `Sink`, it reports a
+/// closed connection as `NotConnected`, which the inherent `send` ignores.
 #[derive(Clone)]
 pub struct RakStreamSender {
     msg_sender: mpsc::Sender<ToConnMsg>,
+    state: ConnState,
 }
 
 impl RakStreamSender {
     pub async fn send(&mut self, bytes: Vec<u8>) {
-        self.msg_sender.send(ToConnMsg::Send(bytes)).await.unwrap();
+        self.send_with_priority(bytes, Priority::default()).await;
+    }
+
+    pub async fn send_with_priority(&mut self, bytes: Vec<u8>, priority: Priority) {
+        _ = self.msg_sender.send(ToConnMsg::Send(bytes, priority)).await;
+    }
+
+    /// Like `send`, failing with `NotConnected` once the connection is
+    /// closed.
+    pub(crate) async fn try_send(&mut self, bytes: Vec<u8>) -> Result<(), Error> {
+        if self.state.is_closed() {
+            return Err(not_connected());
+        }
+        let msg = ToConnMsg::Send(bytes, Priority::default());
+        self.msg_sender.send(msg).await.map_err(closed)
+    }
+
+    /// See `RakStream::stats`.
+    pub fn stats(&self) -> ConnectionStats {
+        self.state.stats()
+    }
+
+    /// See `RakStream::local_time`.
+    pub fn local_time(&self) -> i64 {
+        self.state.local_time()
+    }
+
+    /// See `RakStream::remote_time_offset`.
+    pub fn remote_time_offset(&self) -> Option<RemoteTimeOffset> {
+        self.state.remote_time_offset()
+    }
+
+    /// See `RakStream::remote_to_local`.
+    pub fn remote_to_local(&self, remote_time: i64) -> Option<Instant> {
+        self.state.remote_to_local(remote_time)
+    }
+
+    pub fn disconnect(mut self) {
+        _ = self.msg_sender.try_send(ToConnMsg::Disconnect);
+    }
+}
+
+impl Sink<Vec<u8>> for RakStreamSender {
+    type Error = Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.msg_sender.poll_ready(cx).map_err(closed)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, bytes: Vec<u8>) -> Result<(), Error> {
+        self.msg_sender
+            .start_send(ToConnMsg::Send(bytes, Priority::default()))
+            .map_err(closed)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.msg_sender.poll_flush_unpin(cx).map_err(closed)
+    }
+
+    /// Drops this sender. As with dropping it, the connection is closed
+    /// once no sender is left.
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.msg_sender.poll_close_unpin(cx).map_err(closed)
     }
+}
+
+fn closed(_: mpsc::SendError) -> Error {
+    not_connected()
+}
 
-    pub fn disconnect(self) {}
+fn not_connected() -> Error {
+    Error::new(ErrorKind::NotConnected, "connection closed")
 }
 
+/// The receiving half of a `RakStream`. As a `Stream`, it ends once the
+/// connection is closed.
 pub struct RakStreamReceiver {
-    msg_receiver: mpsc::Receiver<ToStreamMsg>,
+    msg_receiver: mpsc::UnboundedReceiver<ToStreamMsg>,
+    state: ConnState,
 }
 
 impl RakStreamReceiver {
     pub async fn receive(&mut self) -> Option<Vec<u8>> {
-        self.msg_receiver.next().await.map(|msg| {
-            let ToStreamMsg::Packet(packet) = msg;
-            packet
+        self.receive_bytes().await.map(Vec::from)
+    }
+
+    pub async fn receive_bytes(&mut self) -> Option<Bytes> {
+        future::poll_fn(|cx| self.poll_receive_bytes(cx)).await
+    }
+
+    /// See `RakStream::stats`.
+    pub fn stats(&self) -> ConnectionStats {
+        self.state.stats()
+    }
+
+    /// See `RakStream::local_time`.
+    pub fn local_time(&self) -> i64 {
+        self.state.local_time()
+    }
+
+    /// See `RakStream::remote_time_offset`.
+    pub fn remote_time_offset(&self) -> Option<RemoteTimeOffset> {
+        self.state.remote_time_offset()
+    }
+
+    /// See `RakStream::remote_to_local`.
+    pub fn remote_to_local(&self, remote_time: i64) -> Option<Instant> {
+        self.state.remote_to_local(remote_time)
+    }
+
+    pub(crate) fn poll_receive_bytes(&mut self, cx: &mut Context<'_>) -> Poll<Option<Bytes>> {
+        self.msg_receiver.poll_next_unpin(cx).map(|msg| {
+            msg.map(|msg| {
+                let ToStreamMsg::Packet(packet) = msg;
+                packet
+            })
         })
     }
 }
 
+impl Stream for RakStreamReceiver {
+    type Item = Vec<u8>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Vec<u8>>> {
+        self.poll_receive_bytes(cx)
+            .map(|packet| packet.map(Vec::from))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct StreamInformation {
+    /// Identifies the connection to `Listener` methods such as `kick`. IDs
+    /// are not reused within a listener.
+    pub id: u64,
     pub guid: i64,
     pub address: SocketAddr,
 }
+
+pub(crate) async fn resolve<A: ToSocketAddrs>(addrs: A) -> std::io::Result<SocketAddr> {
+    addrs
+        .to_socket_addrs()
+        .await?
+        .next()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "no address to connect to"))
+}
+
+/// Binds an ephemeral port of the same address family as `remote`.
+pub(crate) async fn bind_unspecified(remote: SocketAddr) -> std::io::Result<UdpSocket> {
+    let local_addr: SocketAddr = if remote.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    UdpSocket::bind(local_addr).await
+}
+
+/// Waits for `future` until `timeout` elapses on `clock`.
+async fn or_timeout<T>(
+    future: impl Future<Output = T>,
+    clock: &Arc<dyn Clock>,
+    timeout: Duration,
+) -> Option<T> {
+    pin_mut!(future);
+    match future::select(future, clock.sleep(timeout)).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(_) => None,
+    }
+}
+
+/// Waits for a datagram until `timeout` elapses on `clock`.
+pub(crate) async fn receive_timeout(
+    socket: &UdpSocket,
+    buffer: &mut [u8],
+    clock: &Arc<dyn Clock>,
+    timeout: Duration,
+) -> std::io::Result<Option<(usize, SocketAddr)>> {
+    or_timeout(socket.recv_from(buffer), clock, timeout)
+        .await
+        .transpose()
+}
+
+/// Whether `err` is an ICMP error for an earlier datagram, such as port
+/// unreachable, which some platforms report on the next receive from an
+/// unconnected socket.
+pub(crate) fn is_unreachable(err: &Error) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::ConnectionRefused | ErrorKind::ConnectionReset
+    )
+}
+
+/// Sends `request` until a datagram from `addr` whose ID is one of `replies`
+/// arrives, or every attempt has timed out.
+async fn request_offline(
+    socket: &DatagramSocket,
+    buffer: &mut ReceiveBuffer,
+    addr: SocketAddr,
+    clock: &Arc<dyn Clock>,
+    capture: &CaptureHook,
+    request: &[u8],
+    replies: &[u8],
+) -> std::io::Result<Option<Vec<u8>>> {
+    for _ in 0..OFFLINE_ATTEMPTS_PER_MTU {
+        socket.send_to(request, addr).await?;
+        capture.sent(addr, request);
+        let deadline = clock.now() + OFFLINE_RETRY_INTERVAL;
+        loop {
+            let remaining = deadline.saturating_duration_since(clock.now());
+            if remaining.is_zero() {
+                break;
+            }
+            let received = or_timeout(socket.recv_many(buffer), clock, remaining)
+                .await
+                .transpose()?;
+            for (datagram, from) in received.into_iter().flatten() {
+                capture.received(from, &datagram);
+                if from == addr && datagram.first().is_some_and(|id| replies.contains(id)) {
+                    return Ok(Some(datagram.to_vec()));
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Runs the offline handshake and returns the MTU to use. `overhead` is
+/// what a proxy adds to every datagram, taken off the MTU both sides use.
+async fn open_connection(
+    socket: &DatagramSocket,
+    buffer: &mut ReceiveBuffer,
+    addr: SocketAddr,
+    config: &ConnectConfig,
+    capture: &CaptureHook,
+    overhead: usize,
+) -> std::io::Result<usize> {
+    let mut reply1 = None;
+    let mut max_mtu = MAX_MTU;
+    for mtu in MTU_SIZES {
+        // The server answers with the size it received, so a smaller
+        // request also negotiates the smaller MTU.
+        max_mtu = mtu - overhead;
+        let openconnectionrequest1 = OpenConnectionRequest1 {
+            magic: true,
+            protocol_version: RAKNET_PROTOCOL_VERSION,
+            zero_padding: max_mtu - UDP_HEADER_SIZE - 18,
+        };
+        let request = encode(openconnectionrequest1, 0x5)?;
+        reply1 = request_offline(
+            socket,
+            buffer,
+            addr,
+            &config.clock,
+            capture,
+            &request,
+            &[0x6, 0x19],
+        )
+        .await?;
+        if reply1.is_some() {
+            break;
+        }
+    }
+
+    let reply1 = reply1.ok_or_else(|| Error::new(ErrorKind::TimedOut, "server did not respond"))?;
+    if reply1[0] == 0x19 {
+        return Err(Error::new(
+            ErrorKind::ConnectionRefused,
+            "incompatible protocol version",
+        ));
+    }
+    let openconnectionreply1 = decode::<OpenConnectionReply1>(&reply1)?;
+
+    let openconnectionrequest2 = OpenConnectionRequest2 {
+        magic: true,
+        server_address: addr,
+        mtu: openconnectionreply1.mtu,
+        client_guid: config.guid,
+    };
+    let request = encode(openconnectionrequest2, 0x7)?;
+    let reply2 = request_offline(
+        socket,
+        buffer,
+        addr,
+        &config.clock,
+        capture,
+        &request,
+        &[0x8, 0x12, 0x14, 0x17, 0x19, 0x1a],
+    )
+    .await?
+    .ok_or_else(|| Error::new(ErrorKind::TimedOut, "server did not respond"))?;
+    let refusal = match reply2[0] {
+        0x12 => Some((ErrorKind::AlreadyExists, "already connected")),
+        0x14 => Some((ErrorKind::ConnectionRefused, "server is full")),
+        0x17 => Some((ErrorKind::PermissionDenied, "banned from the server")),
+        0x19 => Some((
+            ErrorKind::ConnectionRefused,
+            "incompatible protocol version",
+        )),
+        0x1a => Some((ErrorKind::ConnectionRefused, "connected too recently")),
+        _ => None,
+    };
+    if let Some((kind, message)) = refusal {
+        return Err(Error::new(kind, message));
+    }
+    let openconnectionreply2 = decode::<OpenConnectionReply2>(&reply2)?;
+    let mtu = (openconnectionreply2.mtu.max(0) as usize).clamp(MIN_MTU, MAX_MTU);
+    // The server never goes below `MIN_MTU`, but what this side sends still
+    // has to fit once the proxy's header is added.
+    Ok(mtu.min(max_mtu))
+}
+
+enum ClientTaskResult {
+    UdpReceived(std::io::Result<Vec<(Bytes, SocketAddr)>>, ReceiveBuffer),
+    ConnMsg(Option<ToConnMsg>, mpsc::Receiver<ToConnMsg>),
+    Tick,
+}
+
+type ClientTaskManager = FuturesUnordered<Pin<Box<dyn Future<Output = ClientTaskResult> + Send>>>;
+
+async fn client_loop(
+    socket: Arc<DatagramSocket>,
+    addr: SocketAddr,
+    mut conn: Conn,
+    msg_receiver: mpsc::Receiver<ToConnMsg>,
+    clock: Arc<dyn Clock>,
+    capture: CaptureHook,
+    buffer: ReceiveBuffer,
+) {
+    let receive_udp = |socket: Arc<DatagramSocket>, mut buffer: ReceiveBuffer| {
+        async move {
+            let result = socket.recv_many(&mut buffer).await;
+            ClientTaskResult::UdpReceived(result, buffer)
+        }
+        .boxed()
+    };
+    let receive_msg = |mut receiver: mpsc::Receiver<ToConnMsg>| {
+        async move {
+            let msg = receiver.next().await;
+            ClientTaskResult::ConnMsg(msg, receiver)
+        }
+        .boxed()
+    };
+    let tick = |clock: &Arc<dyn Clock>| {
+        let sleep = clock.sleep(TICK_INTERVAL);
+        async move {
+            sleep.await;
+            ClientTaskResult::Tick
+        }
+        .boxed()
+    };
+
+    let mut tasks = ClientTaskManager::new();
+    tasks.push(receive_udp(socket.clone(), buffer));
+    tasks.push(receive_msg(msg_receiver));
+    tasks.push(tick(&clock));
+
+    while let Some(result) = tasks.next().await {
+        match result {
+            ClientTaskResult::UdpReceived(res, buffer) => {
+                for (datagram, from) in res.into_iter().flatten() {
+                    capture.received(from, &datagram);
+                    if from == addr {
+                        conn.handle(datagram).await;
+                    }
+                }
+                tasks.push(receive_udp(socket.clone(), buffer));
+            }
+            ClientTaskResult::ConnMsg(msg, receiver) => {
+                let closed = msg.is_none();
+                conn.handle_msg(msg).await;
+                if !closed {
+                    tasks.push(receive_msg(receiver));
+                }
+            }
+            ClientTaskResult::Tick => {
+                conn.update().await;
+                tasks.push(tick(&clock));
+            }
+        }
+
+        if conn.is_closed() {
+            break;
+        }
+    }
+}
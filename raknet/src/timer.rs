@@ -0,0 +1,195 @@
+use std::time::{Duration, Instant};
+
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+const LEVELS: usize = 4;
+/// Ticks ahead the wheel can hold a deadline. About 43 minutes at 10ms.
+const MAX_DELAY: u64 = 1 << (SLOT_BITS * (LEVELS as u32 - 1));
+
+/// A hierarchical timer wheel.
+///
+/// Level 0 holds deadlines in the next 64 ticks, one slot per tick. Each
+/// higher level covers 64 times the span of the one below, and its slots are
+/// cascaded down as the wheel reaches them. Inserting is O(1), and advancing
+/// by a tick only touches the slots that come due.
+///
+/// Deadlines further away than the wheel spans fire early, at the end of its
+/// range, so whoever handles an entry should check its own deadline.
+pub(crate) struct TimerWheel<T> {
+    start: Instant,
+    resolution: Duration,
+    elapsed: u64,
+    levels: Vec<Vec<Vec<Entry<T>>>>,
+}
+
+struct Entry<T> {
+    tick: u64,
+    deadline: Instant,
+    value: T,
+}
+
+impl<T> TimerWheel<T> {
+    pub fn new(start: Instant, resolution: Duration) -> Self {
+        Self {
+            start,
+            resolution,
+            elapsed: 0,
+            levels: (0..LEVELS)
+                .map(|_| (0..SLOTS).map(|_| vec![]).collect())
+                .collect(),
+        }
+    }
+
+    /// Schedules `value` to be returned by the first `advance` past
+    /// `deadline`. Deadlines already passed fire on the next tick.
+    pub fn insert(&mut self, deadline: Instant, value: T) {
+        let offset = deadline.saturating_duration_since(self.start);
+        let tick = offset.as_nanos().div_ceil(self.resolution.as_nanos()) as u64;
+        let tick = tick.clamp(self.elapsed + 1, self.elapsed + MAX_DELAY - 1);
+        self.place(Entry {
+            tick,
+            deadline,
+            value,
+        });
+    }
+
+    /// Moves the wheel up to `now` and returns every entry that came due,
+    /// with the deadline it was inserted with.
+    pub fn advance(&mut self, now: Instant) -> Vec<(Instant, T)> {
+        let target = (now.saturating_duration_since(self.start).as_nanos()
+            / self.resolution.as_nanos()) as u64;
+        let mut due = vec![];
+        while self.elapsed < target {
+            self.elapsed += 1;
+            for level in (1..LEVELS).rev() {
+                let shift = SLOT_BITS * level as u32;
+                if self.elapsed & ((1 << shift) - 1) == 0 {
+                    let slot = (self.elapsed >> shift) as usize & (SLOTS - 1);
+                    for entry in std::mem::take(&mut self.levels[level][slot]) {
+                        self.place(entry);
+                    }
+                }
+            }
+            let slot = self.elapsed as usize & (SLOTS - 1);
+            due.extend(
+                self.levels[0][slot]
+                    .drain(..)
+                    .map(|entry| (entry.deadline, entry.value)),
+            );
+        }
+        due
+    }
+
+    fn place(&mut self, entry: Entry<T>) {
+        // The highest group of bits in which the deadline differs from the
+        // current tick decides the level.
+        let differing = (entry.tick ^ self.elapsed) | (SLOTS as u64 - 1);
+        let level = ((63 - differing.leading_zeros()) / SLOT_BITS) as usize;
+        let level = level.min(LEVELS - 1);
+        let slot = (entry.tick >> (SLOT_BITS * level as u32)) as usize & (SLOTS - 1);
+        self.levels[level][slot].push(entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, ManualClock};
+
+    const RESOLUTION: Duration = Duration::from_millis(10);
+
+    struct Wheel {
+        clock: ManualClock,
+        start: Instant,
+        wheel: TimerWheel<u64>,
+    }
+
+    impl Wheel {
+        fn new() -> Self {
+            let clock = ManualClock::new();
+            let start = clock.now();
+            Self {
+                wheel: TimerWheel::new(start, RESOLUTION),
+                clock,
+                start,
+            }
+        }
+
+        fn at(&self, tick: u64) -> Instant {
+            self.start + RESOLUTION * tick as u32
+        }
+
+        /// Moves the clock to `tick` and returns the values that came due.
+        fn advance_to(&mut self, tick: u64) -> Vec<u64> {
+            self.clock.advance(self.at(tick) - self.clock.now());
+            let due = self.wheel.advance(self.clock.now());
+            due.into_iter().map(|(_, value)| value).collect()
+        }
+    }
+
+    #[test]
+    fn entries_cascade_at_each_level_boundary() {
+        let level_spans = (1..LEVELS as u32).map(|level| 1u64 << (SLOT_BITS * level));
+        let offsets = level_spans
+            .flat_map(|span| [span - 1, span, span + 1])
+            .filter(|&offset| offset < MAX_DELAY)
+            .chain([1, 2, MAX_DELAY - 1])
+            .collect::<Vec<_>>();
+
+        // From an aligned tick and from ticks that are not, so entries land
+        // in slots other than the first of each level.
+        for base in [0, 100, 4000, 70_000] {
+            let mut wheel = Wheel::new();
+            assert!(wheel.advance_to(base).is_empty());
+            let mut ticks = offsets
+                .iter()
+                .map(|offset| base + offset)
+                .collect::<Vec<_>>();
+            ticks.sort_unstable();
+            ticks.dedup();
+            for &tick in &ticks {
+                let deadline = wheel.at(tick);
+                wheel.wheel.insert(deadline, tick);
+            }
+
+            for &tick in &ticks {
+                assert!(wheel.advance_to(tick - 1).is_empty(), "{} early", tick);
+                assert_eq!(wheel.advance_to(tick), [tick]);
+            }
+        }
+    }
+
+    #[test]
+    fn deadlines_between_ticks_round_up() {
+        let mut wheel = Wheel::new();
+        let deadline = wheel.at(64) + RESOLUTION / 2;
+        wheel.wheel.insert(deadline, 1);
+        assert!(wheel.advance_to(64).is_empty());
+        assert_eq!(wheel.advance_to(65), [1]);
+    }
+
+    #[test]
+    fn passed_deadlines_fire_on_the_next_tick() {
+        let mut wheel = Wheel::new();
+        wheel.advance_to(5000);
+        let deadline = wheel.at(10);
+        wheel.wheel.insert(deadline, 1);
+        assert_eq!(wheel.advance_to(5001), [1]);
+    }
+
+    #[test]
+    fn deadlines_past_the_top_level_fire_at_its_end() {
+        let mut wheel = Wheel::new();
+        wheel.advance_to(300);
+        let deadline = wheel.at(300 + MAX_DELAY * 3);
+        wheel.wheel.insert(deadline, 1);
+
+        let last = 300 + MAX_DELAY - 1;
+        assert!(wheel.advance_to(last - 1).is_empty());
+        let due = wheel.wheel.advance(wheel.at(last));
+        // The entry keeps its real deadline, so the caller can tell it fired
+        // early and insert it again.
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0], (deadline, 1));
+    }
+}
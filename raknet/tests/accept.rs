@@ -0,0 +1,139 @@
+mod common;
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_std::{future::timeout, net::UdpSocket, task};
+use futures::{future, StreamExt};
+use raknet::*;
+
+use common::*;
+
+fn config(guid: i64) -> ConnectConfig {
+    ConnectConfig {
+        guid,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn accept_filter_decides_on_connections() {
+    task::block_on(async {
+        let (mut listener, addr) = bind().await;
+        let mut events = listener.events();
+        let attempts = Arc::new(Mutex::new(vec![]));
+        let seen = attempts.clone();
+        listener
+            .set_accept_filter(move |attempt: ConnectionAttempt| {
+                seen.lock().unwrap().push(attempt);
+                async move {
+                    match attempt.guid {
+                        1 => AcceptDecision::Reject(RejectReason::Banned),
+                        2 => AcceptDecision::Drop,
+                        _ => AcceptDecision::Accept,
+                    }
+                }
+            })
+            .await;
+
+        let err = RakStream::connect_with_config(addr, config(1))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+        match timeout(WAIT, events.next()).await.unwrap().unwrap() {
+            ListenerEvent::HandshakeFailed { reason, .. } => {
+                assert_eq!(reason, HandshakeFailure::Rejected(RejectReason::Banned));
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        let dropped = timeout(
+            Duration::from_millis(300),
+            RakStream::connect_with_config(addr, config(2)),
+        );
+        assert!(dropped.await.is_err());
+        match timeout(WAIT, events.next()).await.unwrap().unwrap() {
+            ListenerEvent::HandshakeFailed { reason, .. } => {
+                assert_eq!(reason, HandshakeFailure::Dropped);
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+        let metrics = listener.metrics();
+        assert_eq!(metrics.handshakes_started, 0);
+        assert_eq!(metrics.bans, 1);
+        assert!(metrics.to_prometheus().contains("raknet_bans_total 1\n"));
+
+        let _client = connect_with_config(addr, config(3)).await;
+        let (_stream, info) = accept(&mut listener).await;
+        assert_eq!(info.guid, 3);
+        let attempt = *attempts.lock().unwrap().last().unwrap();
+        assert_eq!(attempt.address, info.address);
+        assert!(attempt.mtu >= 576);
+    });
+}
+
+#[test]
+fn retries_wait_for_the_pending_decision() {
+    task::block_on(async {
+        let (mut listener, addr) = bind().await;
+        let calls = Arc::new(Mutex::new(0));
+        let counted = calls.clone();
+        listener
+            .set_accept_filter(move |_| {
+                *counted.lock().unwrap() += 1;
+                async {
+                    // Long enough for the client to retry its request.
+                    task::sleep(Duration::from_millis(1200)).await;
+                    AcceptDecision::Accept
+                }
+            })
+            .await;
+
+        let _client = connect(addr).await;
+        accept(&mut listener).await;
+        assert_eq!(*calls.lock().unwrap(), 1);
+    });
+}
+
+#[test]
+fn pending_decisions_are_capped() {
+    task::block_on(async {
+        let (listener, addr) = bind().await;
+        let mut events = listener.events();
+        let calls = Arc::new(Mutex::new(0));
+        let counted = calls.clone();
+        listener
+            .set_accept_filter(move |_| {
+                *counted.lock().unwrap() += 1;
+                future::pending()
+            })
+            .await;
+
+        let mut request2 = vec![0x07];
+        request2.extend(MAGIC);
+        request2.push(4);
+        request2.extend([0x80, 0xff, 0xff, 0xfe]);
+        request2.extend(addr.port().to_be_bytes());
+        request2.extend(1400i16.to_be_bytes());
+        request2.extend(42i64.to_be_bytes());
+        let mut sockets = vec![];
+        for _ in 0..65 {
+            let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            socket.send_to(&request2, addr).await.unwrap();
+            sockets.push(socket);
+        }
+
+        // Only the attempt past the cap is turned away.
+        match timeout(WAIT, events.next()).await.unwrap().unwrap() {
+            ListenerEvent::HandshakeFailed { address, reason } => {
+                assert_eq!(address, sockets[64].local_addr().unwrap());
+                assert_eq!(reason, HandshakeFailure::Dropped);
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+        assert_eq!(*calls.lock().unwrap(), 64);
+    });
+}
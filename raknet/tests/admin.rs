@@ -0,0 +1,89 @@
+mod common;
+
+use async_std::{future::timeout, task};
+use futures::StreamExt;
+use raknet::*;
+
+use common::*;
+
+#[test]
+fn listener_administers_connections() {
+    task::block_on(async {
+        let (mut listener, addr) = bind().await;
+        let mut events = listener.events();
+
+        let mut first = connect(addr).await;
+        let (_first_server, first_info) = accept(&mut listener).await;
+        let mut second = connect(addr).await;
+        let (_second_server, second_info) = accept(&mut listener).await;
+
+        let ids = listener
+            .connections()
+            .iter()
+            .map(|information| information.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [first_info.id, second_info.id]);
+        let found = listener.connection(second_info.id).unwrap();
+        assert_eq!(found.guid, second_info.guid);
+        let found = listener.connection_by_guid(second_info.guid).unwrap();
+        assert_eq!(found.address, second_info.address);
+        let found = listener.connection_by_address(first_info.address).unwrap();
+        assert_eq!(found.id, first_info.id);
+
+        assert_eq!(listener.broadcast(&[0xfe, 1], |_| true), 2);
+        assert_eq!(
+            timeout(WAIT, first.receive()).await.unwrap(),
+            Some(vec![0xfe, 1])
+        );
+        assert_eq!(
+            timeout(WAIT, second.receive()).await.unwrap(),
+            Some(vec![0xfe, 1])
+        );
+        let sent = listener.broadcast(&[0xfe, 2], |information| information.id == second_info.id);
+        assert_eq!(sent, 1);
+        assert_eq!(
+            timeout(WAIT, second.receive()).await.unwrap(),
+            Some(vec![0xfe, 2])
+        );
+
+        assert!(listener.kick(first_info.id, "banned"));
+        assert_eq!(timeout(WAIT, first.receive()).await.unwrap(), None);
+        loop {
+            match timeout(WAIT, events.next()).await.unwrap().unwrap() {
+                ListenerEvent::Disconnected { address, reason } => {
+                    assert_eq!(address, first_info.address);
+                    assert_eq!(reason, DisconnectReason::Kicked("banned".to_owned()));
+                    break;
+                }
+                ListenerEvent::Connected(_) => {}
+                event => panic!("unexpected event {:?}", event),
+            }
+        }
+        assert!(listener.connection(first_info.id).is_none());
+        assert!(!listener.kick(first_info.id, "banned"));
+        // The other connection is unaffected.
+        assert!(listener.kick_by_address(second_info.address, "shutting down"));
+        assert_eq!(timeout(WAIT, second.receive()).await.unwrap(), None);
+        assert!(listener.connections().is_empty());
+    });
+}
+
+#[test]
+fn connections_are_listed_before_accept() {
+    task::block_on(async {
+        let (mut listener, addr) = bind().await;
+        let mut events = listener.events();
+
+        let _client = connect(addr).await;
+        let connected = match timeout(WAIT, events.next()).await.unwrap().unwrap() {
+            ListenerEvent::Connected(information) => information,
+            event => panic!("unexpected event {:?}", event),
+        };
+        let listed = listener.connections();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, connected.id);
+
+        let (_server, info) = accept(&mut listener).await;
+        assert_eq!(info.id, connected.id);
+    });
+}
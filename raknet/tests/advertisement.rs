@@ -0,0 +1,43 @@
+use raknet::*;
+
+const BDS: &str = "MCPE;Dedicated Server;560;1.19.50;2;10;13253860892328930865;Bedrock level;Survival;1;19132;19133;";
+
+#[test]
+fn parse_and_format() {
+    let advertisement = BDS.parse::<ServerAdvertisement>().unwrap();
+    assert_eq!(advertisement.motd, "Dedicated Server");
+    assert_eq!(advertisement.protocol, 560);
+    assert_eq!(advertisement.online_players, 2);
+    assert_eq!(advertisement.max_players, 10);
+    assert_eq!(advertisement.server_guid as u64, 13253860892328930865);
+    assert_eq!(advertisement.game_mode_numeric, 1);
+    assert_eq!(advertisement.ipv4_port, Some(19132));
+    assert_eq!(advertisement.ipv6_port, Some(19133));
+    assert_eq!(advertisement.to_string(), BDS);
+}
+
+#[test]
+fn parse_minimal() {
+    let advertisement = "MCPE;motd;1;1.0;0;1"
+        .parse::<ServerAdvertisement>()
+        .unwrap();
+    assert_eq!(advertisement.level_name, "");
+    assert_eq!(advertisement.ipv4_port, None);
+
+    assert!("MCPE;motd;x;1.0;0;1"
+        .parse::<ServerAdvertisement>()
+        .is_err());
+    assert!("MCPE;motd".parse::<ServerAdvertisement>().is_err());
+    assert!("".parse::<ServerAdvertisement>().is_err());
+}
+
+#[test]
+fn format_drops_separators() {
+    let advertisement = ServerAdvertisement {
+        motd: "a;b".to_owned(),
+        ..Default::default()
+    };
+    let formatted = advertisement.to_string();
+    assert_eq!(formatted, "MCPE;ab;0;;0;0;0;;Survival;1;");
+    assert_eq!(formatted.parse::<ServerAdvertisement>().unwrap().motd, "ab");
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use std::io::ErrorKind;
+
+use async_std::{future::timeout, task};
+use raknet::*;
+
+use common::*;
+
+#[test]
+fn unaccepted_streams_do_not_stall_the_listener() {
+    task::block_on(async {
+        let (mut listener, addr) = bind().await;
+
+        // Far more clients than the accept channel holds, without calling
+        // `accept`. Each handshake needs the loop to keep running.
+        let mut clients = vec![];
+        for _ in 0..32 {
+            let (client, client_loop) = timeout(WAIT, RakStream::connect(addr))
+                .await
+                .unwrap()
+                .unwrap();
+            task::spawn(client_loop);
+            clients.push(client);
+        }
+
+        for client in &mut clients {
+            let (mut server, _) = accept(&mut listener).await;
+            server.send(vec![0xfe, 1]).await;
+            assert_eq!(
+                timeout(WAIT, client.receive()).await.unwrap(),
+                Some(vec![0xfe, 1])
+            );
+        }
+    });
+}
+
+#[test]
+fn full_backlog_turns_clients_away() {
+    task::block_on(async {
+        let (_listener, addr) = bind().await;
+
+        let mut clients = vec![];
+        let err = loop {
+            match RakStream::connect(addr).await {
+                Ok((client, client_loop)) => {
+                    task::spawn(client_loop);
+                    clients.push(client);
+                    assert!(clients.len() <= 128, "backlog is unbounded");
+                }
+                Err(err) => break err,
+            }
+        };
+        assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+        assert!(clients.len() >= 64);
+    });
+}
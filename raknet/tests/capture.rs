@@ -0,0 +1,102 @@
+mod common;
+
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use async_std::task;
+use raknet::*;
+
+use common::*;
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+struct Packet {
+    flags: u32,
+    source_port: u16,
+    destination_port: u16,
+    payload: Vec<u8>,
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+/// Parses the enhanced packet blocks of an IPv4 capture.
+fn packets(capture: &[u8]) -> Vec<Packet> {
+    assert_eq!(u32_at(capture, 0), 0x0A0D0D0A);
+    assert_eq!(u32_at(capture, 8), 0x1A2B3C4D);
+
+    let mut packets = vec![];
+    let mut at = 0;
+    while at < capture.len() {
+        let block_type = u32_at(capture, at);
+        let length = u32_at(capture, at + 4) as usize;
+        assert_eq!(u32_at(capture, at + length - 4) as usize, length);
+        if block_type == 6 {
+            let captured = u32_at(capture, at + 20) as usize;
+            let ip = &capture[at + 28..at + 28 + captured];
+            assert_eq!(ip[0], 0x45);
+            assert_eq!(ip[9], 17);
+            let udp = &ip[20..];
+            let options = at + 28 + captured.next_multiple_of(4);
+            packets.push(Packet {
+                flags: u32_at(capture, options + 4),
+                source_port: u16::from_be_bytes([udp[0], udp[1]]),
+                destination_port: u16::from_be_bytes([udp[2], udp[3]]),
+                payload: udp[8..].to_vec(),
+            });
+        }
+        at += length;
+    }
+    packets
+}
+
+#[test]
+fn captures_handshake() {
+    task::block_on(async {
+        let (mut listener, addr) = bind().await;
+        let listener_buffer = SharedBuffer::default();
+        listener
+            .start_capture(Capture::new(listener_buffer.clone()).unwrap())
+            .unwrap();
+
+        let client_buffer = SharedBuffer::default();
+        let config = ConnectConfig {
+            capture: Some(Capture::new(client_buffer.clone()).unwrap()),
+            ..Default::default()
+        };
+        let _client = connect_with_config(addr, config).await;
+        accept(&mut listener).await;
+        listener.stop_capture();
+
+        let client_packets = packets(&client_buffer.0.lock().unwrap());
+        let first = &client_packets[0];
+        assert_eq!(first.flags, 2);
+        assert_eq!(first.destination_port, addr.port());
+        assert_eq!(first.payload[0], 0x05);
+        assert!(client_packets
+            .iter()
+            .any(|packet| packet.flags == 1 && packet.payload[0] == 0x06));
+
+        let listener_packets = packets(&listener_buffer.0.lock().unwrap());
+        assert_eq!(listener_packets[0].flags, 1);
+        assert_eq!(listener_packets[0].payload[0], 0x05);
+        assert!(listener_packets
+            .iter()
+            .any(|packet| packet.source_port == addr.port() && packet.payload[0] == 0x08));
+    });
+}
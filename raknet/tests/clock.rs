@@ -0,0 +1,176 @@
+mod common;
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use async_std::{future::timeout, net::UdpSocket, task};
+use futures::FutureExt;
+use raknet::*;
+
+use common::*;
+
+async fn bind_listener(clock: &ManualClock) -> (Listener, SocketAddr) {
+    bind_with_config(ListenerConfig {
+        clock: Arc::new(clock.clone()),
+        ..Default::default()
+    })
+    .await
+}
+
+/// Receives the next frame set, skipping ACKs and NACKs.
+async fn receive_frame_set(socket: &UdpSocket) -> Vec<u8> {
+    loop {
+        let datagram = receive(socket).await;
+        if datagram[0] & 0xf0 == 0x80 {
+            return datagram;
+        }
+    }
+}
+
+#[test]
+fn manual_sleep_completes_after_advance() {
+    let clock = ManualClock::new();
+    let mut sleep = clock.sleep(Duration::from_millis(100));
+
+    assert!((&mut sleep).now_or_never().is_none());
+    clock.advance(Duration::from_millis(99));
+    assert!((&mut sleep).now_or_never().is_none());
+    clock.advance(Duration::from_millis(1));
+    assert!(sleep.now_or_never().is_some());
+}
+
+#[test]
+fn idle_connection_times_out() {
+    task::block_on(async {
+        let server_clock = ManualClock::new();
+        let (mut listener, addr) = bind_listener(&server_clock).await;
+
+        let config = ConnectConfig {
+            clock: Arc::new(ManualClock::new()),
+            ..Default::default()
+        };
+        let (_client, client_loop) = RakStream::connect_with_config(addr, config).await.unwrap();
+        let client_loop = task::spawn(client_loop);
+        let (mut stream, _) = accept(&mut listener).await;
+
+        // Silence the client so that the server stops hearing from it.
+        client_loop.cancel().await;
+
+        server_clock.advance(Duration::from_secs(9));
+        assert!(stream.receive().now_or_never().is_none());
+
+        server_clock.advance(Duration::from_secs(2));
+        assert_eq!(timeout(WAIT, stream.receive()).await.unwrap(), None);
+    });
+}
+
+/// Runs the handshake up to ConnectionRequestAccepted from a raw socket and
+/// returns the socket with the accepting frame set.
+async fn connect_raw(addr: SocketAddr) -> (UdpSocket, Vec<u8>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    let mut request1 = vec![0x05];
+    request1.extend(MAGIC);
+    request1.push(0x0a);
+    request1.resize(1400, 0);
+    socket.send_to(&request1, addr).await.unwrap();
+    assert_eq!(receive(&socket).await[0], 0x06);
+
+    let mut request2 = vec![0x07];
+    request2.extend(MAGIC);
+    request2.push(4);
+    match addr.ip() {
+        IpAddr::V4(ip) => request2.extend(ip.octets().map(|octet| 0xff - octet)),
+        IpAddr::V6(_) => unreachable!(),
+    }
+    request2.extend(addr.port().to_be_bytes());
+    request2.extend(1400i16.to_be_bytes());
+    request2.extend(42i64.to_be_bytes());
+    socket.send_to(&request2, addr).await.unwrap();
+    assert_eq!(receive(&socket).await[0], 0x08);
+
+    let mut connection_request = vec![0x09];
+    connection_request.extend(42i64.to_be_bytes());
+    connection_request.extend(0i64.to_be_bytes());
+    connection_request.push(0);
+    let mut frame_set = vec![0x84, 0, 0, 0, 0x00];
+    frame_set.extend(((connection_request.len() * 8) as u16).to_be_bytes());
+    frame_set.extend(connection_request);
+    socket.send_to(&frame_set, addr).await.unwrap();
+
+    let accepted = receive_frame_set(&socket).await;
+    (socket, accepted)
+}
+
+#[test]
+fn unacknowledged_datagram_is_resent() {
+    task::block_on(async {
+        let clock = ManualClock::new();
+        let (_listener, addr) = bind_listener(&clock).await;
+        let (socket, accepted) = connect_raw(addr).await;
+
+        // Nothing is resent before the retransmission timeout expires.
+        clock.advance(Duration::from_millis(500));
+        assert!(
+            timeout(Duration::from_millis(200), receive_frame_set(&socket))
+                .await
+                .is_err()
+        );
+
+        clock.advance(Duration::from_millis(600));
+        let resent = receive_frame_set(&socket).await;
+        assert_ne!(accepted[1..4], resent[1..4]);
+        assert_eq!(accepted[4..], resent[4..]);
+    });
+}
+
+#[test]
+fn idle_connection_is_kept_alive() {
+    task::block_on(async {
+        let clock = ManualClock::new();
+        let (_listener, addr) = bind_listener(&clock).await;
+        let (socket, accepted) = connect_raw(addr).await;
+
+        let mut ack = vec![0xc0, 0, 1, 1];
+        ack.extend(&accepted[1..4]);
+        socket.send_to(&ack, addr).await.unwrap();
+
+        let mut new_incoming_connection = vec![0x13, 4];
+        match addr.ip() {
+            IpAddr::V4(ip) => new_incoming_connection.extend(ip.octets().map(|octet| 0xff - octet)),
+            IpAddr::V6(_) => unreachable!(),
+        }
+        new_incoming_connection.extend(addr.port().to_be_bytes());
+        new_incoming_connection.extend([0; 16]);
+        let mut frame_set = vec![0x84, 1, 0, 0, 0x40];
+        frame_set.extend(((new_incoming_connection.len() * 8) as u16).to_be_bytes());
+        frame_set.extend([0, 0, 0]);
+        frame_set.extend(new_incoming_connection);
+        socket.send_to(&frame_set, addr).await.unwrap();
+
+        // Step until the server has seen both and acknowledged the frame set.
+        loop {
+            clock.advance(Duration::from_millis(10));
+            let received = timeout(Duration::from_millis(100), receive(&socket)).await;
+            if received.is_ok_and(|datagram| datagram[0] == 0xc0) {
+                break;
+            }
+        }
+
+        clock.advance(Duration::from_secs(4));
+        assert!(
+            timeout(Duration::from_millis(200), receive_frame_set(&socket))
+                .await
+                .is_err()
+        );
+
+        clock.advance(Duration::from_millis(1100));
+        let ping = receive_frame_set(&socket).await;
+        // An unreliable ConnectedPing.
+        assert_eq!(ping[4], 0x00);
+        assert_eq!(ping[7], 0x00);
+    });
+}
@@ -0,0 +1,61 @@
+//! Fixtures shared by the integration tests. Each test crate only uses some
+//! of them.
+#![allow(dead_code)]
+
+use std::{net::SocketAddr, time::Duration};
+
+use async_std::{future::timeout, net::UdpSocket, task};
+use raknet::*;
+
+/// How long a test waits for something that should happen right away.
+pub const WAIT: Duration = Duration::from_secs(5);
+/// The offline message ID every offline packet carries.
+pub const MAGIC: [u8; 16] = [
+    0x00, 0xff, 0xff, 0x00, 0xfe, 0xfe, 0xfe, 0xfe, 0xfd, 0xfd, 0xfd, 0xfd, 0x12, 0x34, 0x56, 0x78,
+];
+
+/// Binds a single shard listener to a free local port and runs its loop.
+pub async fn bind() -> (Listener, SocketAddr) {
+    bind_with_config(ListenerConfig::default()).await
+}
+
+pub async fn bind_with_config(config: ListenerConfig) -> (Listener, SocketAddr) {
+    let (listener, loop_task) = Listener::bind_with_config("127.0.0.1:0", 1, "test", config)
+        .await
+        .unwrap();
+    task::spawn(loop_task);
+    let addr = listener.local_addr().unwrap();
+    (listener, addr)
+}
+
+/// Connects to `addr` and runs the client loop.
+pub async fn connect(addr: SocketAddr) -> RakStream {
+    connect_with_config(addr, ConnectConfig::default()).await
+}
+
+pub async fn connect_with_config(addr: SocketAddr, config: ConnectConfig) -> RakStream {
+    let (client, client_loop) = RakStream::connect_with_config(addr, config).await.unwrap();
+    task::spawn(client_loop);
+    client
+}
+
+pub async fn accept(listener: &mut Listener) -> (RakStream, StreamInformation) {
+    timeout(WAIT, listener.accept()).await.unwrap().unwrap()
+}
+
+/// A 1000 byte game packet filled with `i`.
+pub fn payload(i: usize) -> Vec<u8> {
+    let mut payload = vec![i as u8; 1000];
+    payload[0] = 0xfe;
+    payload
+}
+
+/// Receives the next datagram on a raw socket.
+pub async fn receive(socket: &UdpSocket) -> Vec<u8> {
+    let mut buffer = [0u8; 2048];
+    let (size, _) = timeout(WAIT, socket.recv_from(&mut buffer))
+        .await
+        .unwrap()
+        .unwrap();
+    buffer[..size].to_vec()
+}
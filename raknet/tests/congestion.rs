@@ -0,0 +1,30 @@
+mod common;
+
+use async_std::{future::timeout, task};
+
+use common::*;
+
+#[test]
+fn congestion_window_opens_during_a_transfer() {
+    task::block_on(async {
+        let (mut listener, addr) = bind().await;
+        let mut client = connect(addr).await;
+        let (mut server, _) = accept(&mut listener).await;
+
+        let initial = server.stats().congestion_window;
+        assert!(initial > 0);
+
+        // Far more than the initial window; the rest goes out as ACKs
+        // come back.
+        for i in 0..500 {
+            server.send(payload(i)).await;
+        }
+        for i in 0..500 {
+            let received = timeout(WAIT, client.receive()).await.unwrap();
+            assert_eq!(received, Some(payload(i)));
+        }
+
+        let stats = server.stats();
+        assert!(stats.congestion_window > initial, "{stats:?}");
+    });
+}
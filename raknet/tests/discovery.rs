@@ -0,0 +1,103 @@
+mod common;
+
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    time::Duration,
+};
+
+use async_std::{future::timeout, net::UdpSocket, task};
+use raknet::*;
+
+use common::*;
+
+#[test]
+fn scan_finds_listener() {
+    task::block_on(async {
+        let (listener, loop_task) = Listener::bind("127.0.0.1:0", 3, "lan").await.unwrap();
+        task::spawn(loop_task);
+        let port = listener.local_addr().unwrap().port();
+
+        let config = ScanConfig {
+            broadcast_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            ports: port..=port,
+            duration: Duration::from_millis(500),
+            ..Default::default()
+        };
+        let servers = scan_lan_with_config(config).await.unwrap();
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].address, listener.local_addr().unwrap());
+        assert_eq!(servers[0].pong.server_guid, 3);
+        assert_eq!(servers[0].pong.server_id, "lan");
+    });
+}
+
+#[test]
+fn listener_advertises() {
+    task::block_on(async {
+        let (listener, loop_task) = Listener::bind("127.0.0.1:0", 4, "advertised")
+            .await
+            .unwrap();
+        task::spawn(loop_task);
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        listener
+            .start_advertising(AdvertiseConfig {
+                targets: vec![socket.local_addr().unwrap()],
+                interval: Duration::from_millis(100),
+            })
+            .await
+            .unwrap();
+
+        let mut buffer = [0u8; 2048];
+        for _ in 0..2 {
+            let (size, from) = timeout(WAIT, socket.recv_from(&mut buffer))
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(from, listener.local_addr().unwrap());
+            assert_eq!(buffer[0], 0x1d);
+            assert_eq!(buffer[9..17], 4i64.to_be_bytes());
+            assert_eq!(&buffer[35..size], b"advertised");
+        }
+
+        listener.stop_advertising().await;
+        task::sleep(Duration::from_millis(50)).await;
+        while timeout(Duration::from_millis(10), socket.recv_from(&mut buffer))
+            .await
+            .is_ok()
+        {}
+        assert!(
+            timeout(Duration::from_millis(300), socket.recv_from(&mut buffer))
+                .await
+                .is_err()
+        );
+    });
+}
+
+#[test]
+fn scan_skips_closed_ports() {
+    task::block_on(async {
+        let (listener, loop_task) = Listener::bind("127.0.0.1:0", 5, "lan").await.unwrap();
+        task::spawn(loop_task);
+        let port = listener.local_addr().unwrap().port();
+        // A neighbouring port with nothing bound to it.
+        let mut closed = None;
+        for candidate in [port.wrapping_add(1), port.wrapping_sub(1)] {
+            if UdpSocket::bind(("127.0.0.1", candidate)).await.is_ok() {
+                closed = Some(candidate);
+                break;
+            }
+        }
+        let closed = closed.unwrap();
+
+        let config = ScanConfig {
+            broadcast_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            ports: port.min(closed)..=port.max(closed),
+            duration: Duration::from_millis(500),
+            ..Default::default()
+        };
+        let servers = scan_lan_with_config(config).await.unwrap();
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].address, listener.local_addr().unwrap());
+    });
+}
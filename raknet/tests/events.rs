@@ -0,0 +1,120 @@
+mod common;
+
+use std::time::Duration;
+
+use async_std::{future::timeout, net::UdpSocket, task};
+use futures::StreamExt;
+use raknet::*;
+
+use common::*;
+
+#[test]
+fn listener_reports_events() {
+    task::block_on(async {
+        let (mut listener, addr) = bind().await;
+        let mut events = listener.events();
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.send_to(&[0x05, 0x00], addr).await.unwrap();
+        match timeout(WAIT, events.next()).await.unwrap().unwrap() {
+            ListenerEvent::MalformedPacket { address, id } => {
+                assert_eq!(address, socket.local_addr().unwrap());
+                assert_eq!(id, 0x05);
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        let client = connect(addr).await;
+        let (_stream, info) = accept(&mut listener).await;
+        match timeout(WAIT, events.next()).await.unwrap().unwrap() {
+            ListenerEvent::Connected(connected) => assert_eq!(connected.address, info.address),
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        client.disconnect();
+        match timeout(WAIT, events.next()).await.unwrap().unwrap() {
+            ListenerEvent::Disconnected { address, reason } => {
+                assert_eq!(address, info.address);
+                assert_eq!(reason, DisconnectReason::ClosedByPeer);
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+    });
+}
+
+#[test]
+fn listener_counts_metrics() {
+    task::block_on(async {
+        let (mut listener, addr) = bind().await;
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.send_to(&[0x05, 0x00], addr).await.unwrap();
+        raknet::ping(addr).await.unwrap();
+
+        let client = connect(addr).await;
+        let (mut stream, _) = accept(&mut listener).await;
+
+        let metrics = listener.metrics();
+        assert_eq!(metrics.active_connections, 1);
+        assert_eq!(metrics.handshakes_started, 1);
+        assert_eq!(metrics.handshakes_completed, 1);
+        assert_eq!(metrics.pings_answered, 1);
+        assert_eq!(metrics.malformed_packets, 1);
+        assert!(metrics.bytes_received > 0);
+        assert!(metrics.bytes_sent > 0);
+
+        client.disconnect();
+        assert_eq!(timeout(WAIT, stream.receive()).await.unwrap(), None);
+        // The connection is forgotten right after its stream is closed.
+        timeout(WAIT, async {
+            while listener.metrics().active_connections != 0 {
+                task::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        let text = listener.metrics().to_prometheus();
+        assert!(
+            text.contains("# TYPE raknet_active_connections gauge\nraknet_active_connections 0\n")
+        );
+        assert!(text.contains("raknet_handshakes_completed_total 1\n"));
+    });
+}
+
+#[test]
+fn handshaking_connections_are_not_active() {
+    task::block_on(async {
+        let (listener, addr) = bind().await;
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        // The offline handshake creates the connection, but the client never
+        // sends ConnectionRequest.
+        let mut request1 = vec![0x05];
+        request1.extend(MAGIC);
+        request1.push(0x0a);
+        request1.resize(1400, 0);
+        socket.send_to(&request1, addr).await.unwrap();
+        receive(&socket).await;
+
+        let mut request2 = vec![0x07];
+        request2.extend(MAGIC);
+        request2.push(4);
+        request2.extend([0x80, 0xff, 0xff, 0xfe]);
+        request2.extend(addr.port().to_be_bytes());
+        request2.extend(1400i16.to_be_bytes());
+        request2.extend(42i64.to_be_bytes());
+        socket.send_to(&request2, addr).await.unwrap();
+        assert_eq!(receive(&socket).await[0], 0x08);
+
+        // The reply goes out just before the handshake is counted.
+        timeout(WAIT, async {
+            while listener.metrics().handshakes_started != 1 {
+                task::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(listener.metrics().active_connections, 0);
+    });
+}
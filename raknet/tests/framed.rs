@@ -0,0 +1,101 @@
+mod common;
+
+use async_std::{future::timeout, task};
+use byte_util::*;
+use packet_builder::*;
+use raknet::*;
+
+use common::*;
+
+#[derive(Debug, PartialEq, Den)]
+struct Text {
+    #[den(with = "RakString")]
+    text: String,
+}
+
+#[derive(Debug, PartialEq, Den)]
+struct Move {
+    #[den(with = "Big")]
+    x: i32,
+    #[den(with = "Big")]
+    y: i32,
+}
+
+#[derive(Debug, PartialEq, Den)]
+enum Packet {
+    #[den(id = 0xfe)]
+    Text(Text),
+    #[den(id = 0xfd)]
+    Move(Move),
+}
+
+#[test]
+fn framed_round_trip() {
+    task::block_on(async {
+        let (mut listener, addr) = bind().await;
+
+        let client = connect(addr).await;
+        let (server, _) = accept(&mut listener).await;
+        let mut client = Framed::<Packet>::new(client);
+        let mut server = Framed::<Packet>::new(server);
+
+        let text = Packet::Text(Text {
+            text: "hello".into(),
+        });
+        client.send(text).await.unwrap();
+        client
+            .send(Packet::Move(Move { x: -1, y: 2 }))
+            .await
+            .unwrap();
+        let received = timeout(WAIT, server.receive()).await.unwrap();
+        assert_eq!(
+            received.unwrap().unwrap(),
+            Packet::Text(Text {
+                text: "hello".into()
+            })
+        );
+        let received = timeout(WAIT, server.receive()).await.unwrap();
+        assert_eq!(
+            received.unwrap().unwrap(),
+            Packet::Move(Move { x: -1, y: 2 })
+        );
+
+        // A packet the enum does not know is handed back with its payload.
+        let mut client = client.into_inner();
+        client.send(vec![0x86, 1, 2]).await;
+        let error = timeout(WAIT, server.receive())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(&error.payload[..], [0x86, 1, 2]);
+        assert_eq!(error.error.kind(), std::io::ErrorKind::InvalidData);
+    });
+}
+
+#[test]
+fn framed_send_fails_once_closed() {
+    task::block_on(async {
+        let (mut listener, addr) = bind().await;
+
+        let client = connect(addr).await;
+        let (server, _) = accept(&mut listener).await;
+        let (mut client, _client_receiver) = Framed::<Packet>::new(client).split();
+        let mut server = Framed::<Packet>::new(server);
+
+        client.clone().disconnect();
+        assert!(timeout(WAIT, server.receive()).await.unwrap().is_none());
+
+        let text = || {
+            Packet::Text(Text {
+                text: "hello".into(),
+            })
+        };
+        for _ in 0..2 {
+            let error = server.send(text()).await.unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::NotConnected);
+            let error = client.send(text()).await.unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::NotConnected);
+        }
+    });
+}
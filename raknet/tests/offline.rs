@@ -0,0 +1,43 @@
+mod common;
+
+use async_std::{future::timeout, net::UdpSocket, task};
+use futures::{FutureExt, StreamExt};
+use raknet::*;
+
+use common::*;
+
+#[test]
+fn offline_packets_are_passed_through() {
+    task::block_on(async {
+        let (listener, addr) = bind().await;
+        let mut events = listener.events();
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        // Without a receiver, unknown datagrams are malformed.
+        socket.send_to(&[0xfe, 0xfd, 0x09], addr).await.unwrap();
+        match timeout(WAIT, events.next()).await.unwrap().unwrap() {
+            ListenerEvent::MalformedPacket { id, .. } => assert_eq!(id, 0xfe),
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        let mut packets = listener.offline_packets();
+        socket.send_to(&[0xfe, 0xfd, 0x09], addr).await.unwrap();
+        let packet = timeout(WAIT, packets.next()).await.unwrap().unwrap();
+        assert_eq!(packet.address, socket.local_addr().unwrap());
+        assert_eq!(&packet.payload[..], [0xfe, 0xfd, 0x09]);
+        packet.reply(b"ok").await.unwrap();
+        assert_eq!(receive(&socket).await, b"ok");
+
+        // RakNet pings are still answered by the listener.
+        let mut ping = vec![0x01];
+        ping.extend(0i64.to_be_bytes());
+        ping.extend([
+            0x00, 0xff, 0xff, 0x00, 0xfe, 0xfe, 0xfe, 0xfe, 0xfd, 0xfd, 0xfd, 0xfd, 0x12, 0x34,
+            0x56, 0x78,
+        ]);
+        ping.extend(0i64.to_be_bytes());
+        socket.send_to(&ping, addr).await.unwrap();
+        assert_eq!(receive(&socket).await[0], 0x1c);
+        assert!(packets.next().now_or_never().is_none());
+    });
+}
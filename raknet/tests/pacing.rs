@@ -0,0 +1,237 @@
+mod common;
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use async_std::{future::timeout, task};
+use futures::{future, FutureExt};
+use raknet::*;
+
+use common::*;
+
+const PAYLOADS: usize = 50;
+/// How far the clock is moved on at a time, the listener's tick.
+const TICK: Duration = Duration::from_millis(10);
+/// Real time after which whatever the last tick let out has arrived, and
+/// the pacer is taken to be holding back the rest.
+const SETTLE: Duration = Duration::from_millis(10);
+
+/// Connects a client and accepts it, returning both ends.
+async fn connect_pair(listener: &mut Listener, addr: SocketAddr) -> (RakStream, RakStream) {
+    let client = connect(addr).await;
+    let (server, _) = accept(listener).await;
+    (client, server)
+}
+
+async fn send_burst(stream: &mut RakStream) {
+    for i in 0..PAYLOADS {
+        stream.send(payload(i)).await;
+    }
+}
+
+/// Receives the next payload, moving `clock` on a tick whenever nothing
+/// arrives.
+async fn next(clock: &ManualClock, stream: &mut RakStream) -> Option<Vec<u8>> {
+    let start = clock.now();
+    loop {
+        if let Ok(received) = timeout(SETTLE, stream.receive()).await {
+            return received;
+        }
+        assert!(clock.now() - start < Duration::from_secs(60), "stalled");
+        clock.advance(TICK);
+    }
+}
+
+/// Receives a burst on every stream, moving `clock` on a tick whenever
+/// nothing arrives, and returns how far it had moved when each payload
+/// arrived.
+async fn receive_bursts(clock: &ManualClock, streams: &mut [&mut RakStream]) -> Vec<Vec<Duration>> {
+    let start = clock.now();
+    let mut arrivals = vec![vec![]; streams.len()];
+    loop {
+        let receiving = streams
+            .iter_mut()
+            .zip(&arrivals)
+            .enumerate()
+            .filter(|(_, (_, arrivals))| arrivals.len() < PAYLOADS)
+            .map(|(index, (stream, _))| stream.receive().map(move |received| (index, received)))
+            .map(FutureExt::boxed)
+            .collect::<Vec<_>>();
+        if receiving.is_empty() {
+            return arrivals;
+        }
+        match timeout(SETTLE, future::select_all(receiving)).await {
+            Ok(((index, received), _, _)) => {
+                let arrivals = &mut arrivals[index];
+                assert_eq!(received, Some(payload(arrivals.len())));
+                arrivals.push(clock.now() - start);
+            }
+            Err(_) => {
+                assert!(clock.now() - start < Duration::from_secs(60), "stalled");
+                clock.advance(TICK);
+            }
+        }
+    }
+}
+
+#[test]
+fn connection_bandwidth_paces_bursts() {
+    task::block_on(async {
+        let clock = ManualClock::new();
+        let (mut listener, addr) = bind_with_config(ListenerConfig {
+            clock: Arc::new(clock.clone()),
+            connection_bandwidth: Some(100_000),
+            ..Default::default()
+        })
+        .await;
+        let (mut client, mut server) = connect_pair(&mut listener, addr).await;
+
+        send_burst(&mut server).await;
+        let arrivals = receive_bursts(&clock, &mut [&mut client]).await.remove(0);
+        // 50 kB at 100 kB/s, less the initial burst.
+        assert!(arrivals[PAYLOADS - 1] >= Duration::from_millis(400));
+        // Spread out rather than sent at once and held up at the end: the
+        // first 100 ms only carry about 12 kB.
+        let early = arrivals
+            .iter()
+            .filter(|arrival| **arrival < Duration::from_millis(100))
+            .count();
+        assert!(early <= 15, "{early} payloads in the first 100 ms");
+    });
+}
+
+#[test]
+fn total_bandwidth_is_shared_between_connections() {
+    task::block_on(async {
+        let clock = ManualClock::new();
+        let (mut listener, addr) = bind_with_config(ListenerConfig {
+            clock: Arc::new(clock.clone()),
+            total_bandwidth: Some(100_000),
+            ..Default::default()
+        })
+        .await;
+        let (mut first_client, mut first_server) = connect_pair(&mut listener, addr).await;
+        let (mut second_client, mut second_server) = connect_pair(&mut listener, addr).await;
+
+        send_burst(&mut first_server).await;
+        send_burst(&mut second_server).await;
+        let arrivals = receive_bursts(&clock, &mut [&mut first_client, &mut second_client]).await;
+        let (first, second) = (arrivals[0][PAYLOADS - 1], arrivals[1][PAYLOADS - 1]);
+        // 100 kB at 100 kB/s in total.
+        assert!(first.max(second) >= Duration::from_millis(900));
+
+        // The first connection queued all of its burst before the second,
+        // yet they take turns rather than the second waiting for the first.
+        assert!(first >= second.mul_f64(0.8), "{first:?} and {second:?}");
+    });
+}
+
+#[test]
+fn client_bandwidth_paces_bursts() {
+    task::block_on(async {
+        let (mut listener, addr) = bind().await;
+        let clock = ManualClock::new();
+        let config = ConnectConfig {
+            clock: Arc::new(clock.clone()),
+            bandwidth: Some(100_000),
+            ..Default::default()
+        };
+        let mut client = connect_with_config(addr, config).await;
+        let (mut server, _) = accept(&mut listener).await;
+
+        send_burst(&mut client).await;
+        let arrivals = receive_bursts(&clock, &mut [&mut server]).await.remove(0);
+        assert!(arrivals[PAYLOADS - 1] >= Duration::from_millis(400));
+    });
+}
+
+#[test]
+fn queue_depth_is_reported_per_priority() {
+    task::block_on(async {
+        let clock = ManualClock::new();
+        let (mut listener, addr) = bind_with_config(ListenerConfig {
+            clock: Arc::new(clock.clone()),
+            connection_bandwidth: Some(20_000),
+            ..Default::default()
+        })
+        .await;
+        let (mut client, mut server) = connect_pair(&mut listener, addr).await;
+
+        for i in 0..20 {
+            server.send_with_priority(payload(i), Priority::Low).await;
+        }
+        server.send_with_priority(payload(20), Priority::High).await;
+        // A few datagrams' worth of bandwidth.
+        for i in 0..3 {
+            assert_eq!(next(&clock, &mut client).await, Some(payload(i)));
+        }
+
+        // The pacer still holds back low priority frames, but the high
+        // priority one went out ahead of them. The stats are published just
+        // after the datagrams go out.
+        timeout(WAIT, async {
+            while server.stats().queue_depth[1] != 0 {
+                task::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let depth = server.stats().queue_depth;
+        assert!(depth[3] > 0, "{depth:?}");
+
+        // Priority does not change the order packets are delivered in.
+        for i in 3..=20 {
+            assert_eq!(next(&clock, &mut client).await, Some(payload(i)));
+        }
+    });
+}
+
+#[test]
+fn disconnect_sends_what_the_pacer_held_back() {
+    task::block_on(async {
+        let clock = ManualClock::new();
+        let (mut listener, addr) = bind_with_config(ListenerConfig {
+            clock: Arc::new(clock.clone()),
+            connection_bandwidth: Some(50_000),
+            ..Default::default()
+        })
+        .await;
+        let (mut client, mut server) = connect_pair(&mut listener, addr).await;
+
+        send_burst(&mut server).await;
+        server.disconnect();
+        // 50 kB at 50 kB/s is well within what a disconnect waits for.
+        receive_bursts(&clock, &mut [&mut client]).await;
+        assert_eq!(next(&clock, &mut client).await, None);
+    });
+}
+
+#[test]
+fn disconnect_reports_what_it_gave_up_on() {
+    task::block_on(async {
+        let clock = ManualClock::new();
+        let (mut listener, addr) = bind_with_config(ListenerConfig {
+            clock: Arc::new(clock.clone()),
+            connection_bandwidth: Some(2_000),
+            ..Default::default()
+        })
+        .await;
+        let client = connect(addr).await;
+        let (mut server, info) = accept(&mut listener).await;
+
+        // 50 kB at 2 kB/s is far more than a disconnect waits for.
+        send_burst(&mut server).await;
+        assert!(listener.kick(info.id, "bye"));
+        assert_eq!(timeout(WAIT, server.receive()).await.unwrap(), None);
+        clock.advance(Duration::from_secs(6));
+
+        timeout(WAIT, async {
+            while server.stats().frames_dropped == 0 {
+                task::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(server.stats().queue_depth, [0; 4]);
+        drop(client);
+    });
+}
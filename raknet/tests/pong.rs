@@ -0,0 +1,184 @@
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use async_std::{
+    future::{self, timeout},
+    net::UdpSocket,
+    task,
+};
+use raknet::*;
+
+use common::*;
+
+async fn ping(socket: &UdpSocket, listener: &Listener, time: i64) -> String {
+    let mut ping = vec![0x01];
+    ping.extend(time.to_be_bytes());
+    ping.extend(MAGIC);
+    ping.extend(7i64.to_be_bytes());
+    socket
+        .send_to(&ping, listener.local_addr().unwrap())
+        .await
+        .unwrap();
+
+    let pong = receive(socket).await;
+    assert_eq!(pong[0], 0x1c);
+    assert_eq!(pong[1..9], time.to_be_bytes());
+    String::from_utf8(pong[35..].to_vec()).unwrap()
+}
+
+#[test]
+fn pong_provider_overrides_server_id() {
+    task::block_on(async {
+        let (listener, loop_task) = Listener::bind("127.0.0.1:0", 1, "static").await.unwrap();
+        task::spawn(loop_task);
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        assert_eq!(ping(&socket, &listener, 1).await, "static");
+
+        listener
+            .set_pong_provider(|addr, time| async move { format!("{};{}", addr, time) })
+            .await;
+        let expected = format!("{};{}", socket.local_addr().unwrap(), 2);
+        assert_eq!(ping(&socket, &listener, 2).await, expected);
+
+        listener.clear_pong_provider().await;
+        assert_eq!(ping(&socket, &listener, 3).await, "static");
+    });
+}
+
+#[test]
+fn ping_returns_pong() {
+    task::block_on(async {
+        let advertisement = ServerAdvertisement {
+            motd: "hello".to_owned(),
+            max_players: 20,
+            ..Default::default()
+        };
+        let (listener, loop_task) = Listener::bind("127.0.0.1:0", 9, "").await.unwrap();
+        task::spawn(loop_task);
+        listener.set_server_id(&advertisement).await;
+
+        let response = raknet::ping(listener.local_addr().unwrap()).await.unwrap();
+        assert_eq!(response.pong.server_guid, 9);
+        assert_eq!(response.advertisement().unwrap(), advertisement);
+        assert!(response.latency < WAIT);
+    });
+}
+
+#[test]
+fn ping_times_out() {
+    task::block_on(async {
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let config = PingConfig {
+            attempts: 2,
+            attempt_timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let err = ping_with_config(silent.local_addr().unwrap(), config)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    });
+}
+
+#[test]
+fn slow_pong_provider_falls_back_to_server_id() {
+    task::block_on(async {
+        let (listener, loop_task) = Listener::bind("127.0.0.1:0", 1, "static").await.unwrap();
+        task::spawn(loop_task);
+        listener
+            .set_pong_provider(|_, _| future::pending::<String>())
+            .await;
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        // Once enough provider calls are stuck, pings are answered right
+        // away instead of piling up.
+        let mut buffer = [0u8; 2048];
+        for time in 0.. {
+            let mut ping = vec![0x01];
+            ping.extend((time as i64).to_be_bytes());
+            ping.extend(MAGIC);
+            ping.extend(7i64.to_be_bytes());
+            socket
+                .send_to(&ping, listener.local_addr().unwrap())
+                .await
+                .unwrap();
+            let received = timeout(Duration::from_millis(20), socket.recv_from(&mut buffer)).await;
+            if let Ok(Ok((size, _))) = received {
+                assert_eq!(buffer[0], 0x1c);
+                assert_eq!(&buffer[35..size], b"static");
+                break;
+            }
+            assert!(time < 1000, "pings never fell back");
+        }
+    });
+}
+
+#[test]
+fn ping_retries_until_server_is_up() {
+    task::block_on(async {
+        // A port with nothing behind it until the second attempt.
+        let addr = UdpSocket::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        task::spawn(async move {
+            task::sleep(Duration::from_millis(150)).await;
+            let (listener, loop_task) = Listener::bind(addr, 3, "late").await.unwrap();
+            loop_task.await;
+            drop(listener);
+        });
+
+        let config = PingConfig {
+            attempts: 10,
+            attempt_timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let response = ping_with_config(addr, config).await.unwrap();
+        assert_eq!(response.pong.server_id, "late");
+    });
+}
+
+#[test]
+fn ping_waits_for_the_attempt_timeout_before_retrying() {
+    task::block_on(async {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let clock = ManualClock::new();
+        let config = PingConfig {
+            attempts: 2,
+            attempt_timeout: Duration::from_secs(1),
+            clock: Arc::new(clock.clone()),
+            ..Default::default()
+        };
+        let addr = server.local_addr().unwrap();
+        let pinging = task::spawn(ping_with_config(addr, config));
+
+        let mut buffer = [0u8; 2048];
+        let (_, client) = timeout(WAIT, server.recv_from(&mut buffer))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(buffer[0], 0x01);
+        // No retry until the clock passes the attempt timeout, however the
+        // first attempt ended.
+        let early = timeout(Duration::from_millis(200), server.recv_from(&mut buffer)).await;
+        assert!(early.is_err());
+
+        clock.advance(Duration::from_secs(1));
+        let ping = receive(&server).await;
+        assert_eq!((ping[0], ping.len()), (0x01, 33));
+        let mut pong = vec![0x1c];
+        pong.extend(&ping[1..9]);
+        pong.extend(5i64.to_be_bytes());
+        pong.extend(MAGIC);
+        pong.extend(4u16.to_be_bytes());
+        pong.extend(b"late");
+        server.send_to(&pong, client).await.unwrap();
+
+        let response = timeout(WAIT, pinging).await.unwrap().unwrap();
+        assert_eq!(response.pong.server_id, "late");
+        assert_eq!(response.latency, Duration::ZERO);
+    });
+}
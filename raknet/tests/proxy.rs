@@ -0,0 +1,103 @@
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use async_std::{future::timeout, task};
+use raknet::*;
+
+use common::*;
+
+#[test]
+fn proxy_relays_through_hook() {
+    task::block_on(async {
+        let (mut upstream, upstream_loop) = Listener::bind("127.0.0.1:0", 1, "upstream;server")
+            .await
+            .unwrap();
+        task::spawn(upstream_loop);
+        let upstream_addr = upstream.local_addr().unwrap();
+
+        let (proxy, proxy_loop) = Proxy::bind("127.0.0.1:0", upstream_addr).await.unwrap();
+        task::spawn(proxy_loop);
+        // The proxy stays usable while it runs.
+        let proxy = Arc::new(proxy);
+        task::spawn({
+            let proxy = proxy.clone();
+            async move { proxy.run().await }
+        });
+        let proxy_addr = proxy.listener().local_addr().unwrap();
+        proxy
+            .set_packet_hook(|session, direction, payload| async move {
+                match (direction, payload[1]) {
+                    (Direction::ClientToServer, 1) => Some(vec![0xfe, 2]),
+                    (Direction::ClientToServer, 0xff) => None,
+                    (Direction::ServerToClient, 3) => {
+                        session
+                            .inject(Direction::ServerToClient, vec![0xfe, 4])
+                            .await;
+                        Some(payload)
+                    }
+                    _ => Some(payload),
+                }
+            })
+            .await;
+
+        let pong = raknet::ping(proxy_addr).await.unwrap().pong;
+        assert_eq!(pong.server_id, "upstream;server");
+
+        let config = ConnectConfig {
+            guid: 42,
+            ..Default::default()
+        };
+        let mut client = connect_with_config(proxy_addr, config).await;
+        let (mut server, info) = accept(&mut upstream).await;
+        assert_eq!(info.guid, 42);
+
+        client.send(vec![0xfe, 0xff]).await;
+        client.send(vec![0xfe, 1]).await;
+        assert_eq!(
+            timeout(WAIT, server.receive()).await.unwrap(),
+            Some(vec![0xfe, 2])
+        );
+
+        server.send(vec![0xfe, 3]).await;
+        assert_eq!(
+            timeout(WAIT, client.receive()).await.unwrap(),
+            Some(vec![0xfe, 4])
+        );
+        assert_eq!(
+            timeout(WAIT, client.receive()).await.unwrap(),
+            Some(vec![0xfe, 3])
+        );
+
+        client.disconnect();
+        assert_eq!(timeout(WAIT, server.receive()).await.unwrap(), None);
+    });
+}
+
+#[test]
+fn upstream_pong_is_cached() {
+    task::block_on(async {
+        let (upstream, upstream_loop) = Listener::bind("127.0.0.1:0", 1, "first").await.unwrap();
+        task::spawn(upstream_loop);
+        let upstream_addr = upstream.local_addr().unwrap();
+        let (proxy, proxy_loop) = Proxy::bind("127.0.0.1:0", upstream_addr).await.unwrap();
+        task::spawn(proxy_loop);
+        let proxy_addr = proxy.listener().local_addr().unwrap();
+
+        assert_eq!(
+            raknet::ping(proxy_addr).await.unwrap().pong.server_id,
+            "first"
+        );
+        upstream.set_server_id("second").await;
+        assert_eq!(
+            raknet::ping(proxy_addr).await.unwrap().pong.server_id,
+            "first"
+        );
+
+        task::sleep(Duration::from_millis(2100)).await;
+        assert_eq!(
+            raknet::ping(proxy_addr).await.unwrap().pong.server_id,
+            "second"
+        );
+    });
+}
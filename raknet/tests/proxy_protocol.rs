@@ -0,0 +1,114 @@
+mod common;
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_std::{future::timeout, net::UdpSocket, task};
+use raknet::*;
+
+use common::*;
+
+const SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+fn header(client: SocketAddr, server: SocketAddr) -> Vec<u8> {
+    let (SocketAddr::V4(client), SocketAddr::V4(server)) = (client, server) else {
+        unreachable!()
+    };
+    let mut header = SIGNATURE.to_vec();
+    // PROXY command, UDP over IPv4.
+    header.extend([0x21, 0x12]);
+    header.extend(12u16.to_be_bytes());
+    header.extend(client.ip().octets());
+    header.extend(server.ip().octets());
+    header.extend(client.port().to_be_bytes());
+    header.extend(server.port().to_be_bytes());
+    header
+}
+
+/// Relays one client to `server`, adding a PROXY header that claims
+/// `claimed` to the first datagram, like a UDP load balancer.
+async fn balancer(server: SocketAddr, claimed: SocketAddr) -> SocketAddr {
+    let front = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    let back = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    let client = Arc::new(Mutex::new(None));
+    let addr = front.local_addr().unwrap();
+
+    let (from_front, to_back, seen) = (front.clone(), back.clone(), client.clone());
+    task::spawn(async move {
+        let mut buffer = [0u8; 4096];
+        while let Ok((size, from)) = from_front.recv_from(&mut buffer).await {
+            let mut datagram = vec![];
+            if seen.lock().unwrap().replace(from).is_none() {
+                datagram.extend(header(claimed, server));
+            }
+            datagram.extend(&buffer[..size]);
+            _ = to_back.send_to(&datagram, server).await;
+        }
+    });
+    task::spawn(async move {
+        let mut buffer = [0u8; 4096];
+        while let Ok((size, _)) = back.recv_from(&mut buffer).await {
+            let Some(client) = *client.lock().unwrap() else {
+                continue;
+            };
+            _ = front.send_to(&buffer[..size], client).await;
+        }
+    });
+    addr
+}
+
+#[test]
+fn proxied_clients_keep_their_address() {
+    task::block_on(async {
+        let config = ListenerConfig {
+            proxy_protocol: true,
+            ..Default::default()
+        };
+        let (mut listener, addr) = bind_with_config(config).await;
+        let claimed: SocketAddr = "203.0.113.7:40000".parse().unwrap();
+        let front = balancer(addr, claimed).await;
+
+        let mut client = connect(front).await;
+        let (mut server, info) = accept(&mut listener).await;
+        assert_eq!(info.address, claimed);
+        assert_eq!(listener.connection_by_address(claimed).unwrap().id, info.id);
+
+        client.send(vec![0xfe, 1]).await;
+        assert_eq!(
+            timeout(WAIT, server.receive()).await.unwrap(),
+            Some(vec![0xfe, 1])
+        );
+        server.send(vec![0xfe, 2]).await;
+        assert_eq!(
+            timeout(WAIT, client.receive()).await.unwrap(),
+            Some(vec![0xfe, 2])
+        );
+    });
+}
+
+#[test]
+fn invalid_headers_are_dropped() {
+    task::block_on(async {
+        let config = ListenerConfig {
+            proxy_protocol: true,
+            ..Default::default()
+        };
+        let (listener, addr) = bind_with_config(config).await;
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        // Version 1 instead of 2.
+        let mut datagram = SIGNATURE.to_vec();
+        datagram.extend([0x11, 0x12, 0, 0, 0x05]);
+        socket.send_to(&datagram, addr).await.unwrap();
+        let mut buffer = [0u8; 64];
+        assert!(
+            timeout(Duration::from_millis(200), socket.recv_from(&mut buffer))
+                .await
+                .is_err()
+        );
+        assert_eq!(listener.metrics().malformed_packets, 0);
+    });
+}
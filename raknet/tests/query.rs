@@ -0,0 +1,100 @@
+mod common;
+
+use std::time::Duration;
+
+use async_std::{future::timeout, net::UdpSocket, task};
+use raknet::*;
+
+use common::*;
+
+const SESSION: [u8; 4] = [0x01, 0x02, 0x03, 0x04];
+
+async fn request(socket: &UdpSocket, addr: std::net::SocketAddr, packet: &[u8]) -> Vec<u8> {
+    socket.send_to(packet, addr).await.unwrap();
+    receive(socket).await
+}
+
+fn strings(buffer: &[u8]) -> Vec<String> {
+    buffer
+        .split(|byte| *byte == 0)
+        .map(|string| String::from_utf8_lossy(string).into_owned())
+        .collect()
+}
+
+#[test]
+fn answers_queries() {
+    task::block_on(async {
+        let (listener, addr) = bind().await;
+        listener
+            .set_query_providers(
+                || async {
+                    QueryInfo {
+                        motd: "Dedicated Server".into(),
+                        version: "1.20.0".into(),
+                        map: "Bedrock level".into(),
+                        online_players: 2,
+                        max_players: 10,
+                        host_ip: "127.0.0.1".into(),
+                        ..Default::default()
+                    }
+                },
+                || async { vec!["Steve".to_owned(), "Alex".to_owned()] },
+            )
+            .await;
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        let mut handshake = vec![0xfe, 0xfd, 0x09];
+        handshake.extend(SESSION);
+        let response = request(&socket, addr, &handshake).await;
+        assert_eq!(response[..5], [0x09, 0x01, 0x02, 0x03, 0x04]);
+        let token: i32 = strings(&response[5..])[0].parse().unwrap();
+
+        let mut basic = vec![0xfe, 0xfd, 0x00];
+        basic.extend(SESSION);
+        basic.extend(token.to_be_bytes());
+        let response = request(&socket, addr, &basic).await;
+        assert_eq!(response[..5], [0x00, 0x01, 0x02, 0x03, 0x04]);
+        let values = strings(&response[5..]);
+        assert_eq!(
+            values[..5],
+            ["Dedicated Server", "SMP", "Bedrock level", "2", "10"]
+        );
+        // The host port is little-endian, followed by the host IP.
+        let tail = &response[response.len() - 12..];
+        assert_eq!(tail[..2], 19132u16.to_le_bytes());
+        assert_eq!(&tail[2..], b"127.0.0.1\0");
+
+        let mut full = basic.clone();
+        full.extend([0; 4]);
+        let response = request(&socket, addr, &full).await;
+        assert_eq!(response[..16], *b"\x00\x01\x02\x03\x04splitnum\x00\x80\x00");
+        let body = &response[16..];
+        let split = body
+            .windows(10)
+            .position(|window| window == b"\x01player_\x00\x00")
+            .unwrap();
+        let pairs = strings(&body[..split]);
+        let find = |key: &str| {
+            let index = pairs.iter().position(|pair| pair == key).unwrap();
+            pairs[index + 1].clone()
+        };
+        assert_eq!(find("hostname"), "Dedicated Server");
+        assert_eq!(find("game_id"), "MINECRAFTPE");
+        assert_eq!(find("numplayers"), "2");
+        assert_eq!(find("hostport"), "19132");
+        let players = strings(&body[split + 10..]);
+        assert_eq!(players[..3], ["Steve", "Alex", ""]);
+
+        // Stat requests with a wrong token are ignored.
+        let mut forged = vec![0xfe, 0xfd, 0x00];
+        forged.extend(SESSION);
+        forged.extend(token.wrapping_add(1).to_be_bytes());
+        socket.send_to(&forged, addr).await.unwrap();
+        let mut buffer = [0u8; 64];
+        assert!(
+            timeout(Duration::from_millis(200), socket.recv_from(&mut buffer))
+                .await
+                .is_err()
+        );
+    });
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use async_std::{future::timeout, task};
+
+use common::*;
+
+#[test]
+fn handshake_estimates_remote_time() {
+    task::block_on(async {
+        let (mut listener, addr) = bind().await;
+
+        let mut client = connect(addr).await;
+        let (mut server, _) = accept(&mut listener).await;
+
+        // The server only gets its sample from NewIncomingConnection, which
+        // is delivered before anything sent after it.
+        client.send(vec![0xfe]).await;
+        assert_eq!(
+            timeout(WAIT, server.receive()).await.unwrap(),
+            Some(vec![0xfe])
+        );
+
+        for stream in [&client, &server] {
+            let offset = stream.remote_time_offset().unwrap();
+            assert!(offset.samples >= 1);
+            assert!(offset.offset_ms.abs() < 100.0, "{:?}", offset);
+            assert_eq!(offset.drift_ppm, 0.0);
+        }
+
+        let sent = server.local_time();
+        let stamped = Instant::now();
+        let converted = client.remote_to_local(sent).unwrap();
+        let error = match converted > stamped {
+            true => converted - stamped,
+            false => stamped - converted,
+        };
+        assert!(error < Duration::from_millis(100), "{:?}", error);
+    });
+}
+
+#[test]
+fn split_halves_keep_the_estimate() {
+    task::block_on(async {
+        let (mut listener, addr) = bind().await;
+
+        let client = connect(addr).await;
+        let (server, _) = accept(&mut listener).await;
+        let (mut sender, _receiver) = client.split();
+        let (_server_sender, mut receiver) = server.split();
+
+        let sent = sender.local_time();
+        sender.send(vec![0xfe]).await;
+        assert_eq!(
+            timeout(WAIT, receiver.receive()).await.unwrap(),
+            Some(vec![0xfe])
+        );
+
+        assert!(receiver.remote_time_offset().unwrap().samples >= 1);
+        assert!(receiver.remote_to_local(sent).is_some());
+        assert!(receiver.stats().datagrams_received >= 1);
+    });
+}
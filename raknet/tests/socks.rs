@@ -0,0 +1,172 @@
+mod common;
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use async_std::{
+    future::timeout,
+    net::{TcpListener, UdpSocket},
+    task,
+};
+use futures::{AsyncReadExt, AsyncWriteExt};
+use raknet::*;
+
+use common::*;
+
+/// A SOCKS5 server that only knows UDP ASSOCIATE with a username and
+/// password, for IPv4 peers. Returns its TCP address, its UDP relay address
+/// and the size of the largest datagram it exchanged with the client.
+async fn socks_server(
+    username: &'static str,
+    password: &'static str,
+) -> (SocketAddr, SocketAddr, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let relay = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let (proxy, relay_addr) = (listener.local_addr().unwrap(), relay.local_addr().unwrap());
+    let largest = Arc::new(AtomicUsize::new(0));
+    let largest_seen = largest.clone();
+
+    task::spawn(async move {
+        let (mut control, _) = listener.accept().await.unwrap();
+        let mut greeting = [0u8; 3];
+        control.read_exact(&mut greeting).await.unwrap();
+        assert_eq!(greeting, [0x05, 0x01, 0x02]);
+        control.write_all(&[0x05, 0x02]).await.unwrap();
+
+        let mut fields = vec![];
+        let mut version = [0u8; 1];
+        control.read_exact(&mut version).await.unwrap();
+        for _ in 0..2 {
+            let mut len = [0u8; 1];
+            control.read_exact(&mut len).await.unwrap();
+            let mut field = vec![0u8; len[0] as usize];
+            control.read_exact(&mut field).await.unwrap();
+            fields.push(String::from_utf8(field).unwrap());
+        }
+        let status = if fields == [username, password] {
+            0x00
+        } else {
+            0x01
+        };
+        control.write_all(&[0x01, status]).await.unwrap();
+        if status != 0 {
+            return;
+        }
+
+        let mut request = [0u8; 10];
+        control.read_exact(&mut request).await.unwrap();
+        assert_eq!(request[..4], [0x05, 0x03, 0x00, 0x01]);
+        // Answer with an unspecified address, which stands for the proxy's.
+        let mut reply = vec![0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0];
+        reply.extend(relay_addr.port().to_be_bytes());
+        control.write_all(&reply).await.unwrap();
+
+        let mut client = None;
+        let mut buffer = [0u8; 4096];
+        loop {
+            let (size, from) = relay.recv_from(&mut buffer).await.unwrap();
+            let datagram = &buffer[..size];
+            // The first datagram comes from the client.
+            let client = *client.get_or_insert(from);
+            if from == client {
+                largest_seen.fetch_max(size, Ordering::Relaxed);
+                assert_eq!(datagram[..4], [0, 0, 0, 0x01]);
+                let ip = Ipv4Addr::new(datagram[4], datagram[5], datagram[6], datagram[7]);
+                let port = u16::from_be_bytes([datagram[8], datagram[9]]);
+                let target = SocketAddr::new(IpAddr::V4(ip), port);
+                relay.send_to(&datagram[10..], target).await.unwrap();
+            } else {
+                let SocketAddr::V4(source) = from else {
+                    unreachable!()
+                };
+                let mut wrapped = vec![0, 0, 0, 0x01];
+                wrapped.extend(source.ip().octets());
+                wrapped.extend(source.port().to_be_bytes());
+                wrapped.extend(datagram);
+                largest_seen.fetch_max(wrapped.len(), Ordering::Relaxed);
+                relay.send_to(&wrapped, client).await.unwrap();
+            }
+        }
+    });
+    (proxy, relay_addr, largest)
+}
+
+fn config(proxy: SocketAddr, password: &str) -> ConnectConfig {
+    ConnectConfig {
+        socks5: Some(Socks5Config {
+            proxy,
+            credentials: Some(("bot".to_owned(), password.to_owned())),
+        }),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn connects_through_socks5() {
+    task::block_on(async {
+        let (mut listener, addr) = bind().await;
+        let (proxy, relay, _) = socks_server("bot", "secret").await;
+
+        let mut client = connect_with_config(addr, config(proxy, "secret")).await;
+        let (mut server, info) = accept(&mut listener).await;
+        // The listener only sees the relay.
+        assert_eq!(info.address, relay);
+
+        client.send(vec![0xfe, 1]).await;
+        assert_eq!(
+            timeout(WAIT, server.receive()).await.unwrap(),
+            Some(vec![0xfe, 1])
+        );
+        server.send(vec![0xfe, 2]).await;
+        assert_eq!(
+            timeout(WAIT, client.receive()).await.unwrap(),
+            Some(vec![0xfe, 2])
+        );
+    });
+}
+
+#[test]
+fn wrong_credentials_are_refused() {
+    task::block_on(async {
+        let (proxy, _, _) = socks_server("bot", "secret").await;
+        let addr: SocketAddr = "127.0.0.1:19132".parse().unwrap();
+        let err = RakStream::connect_with_config(addr, config(proxy, "wrong"))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+    });
+}
+
+#[test]
+fn datagrams_leave_room_for_the_socks_header() {
+    task::block_on(async {
+        let (mut listener, addr) = bind().await;
+        let (proxy, _, largest) = socks_server("bot", "secret").await;
+
+        let mut client = connect_with_config(addr, config(proxy, "secret")).await;
+        let (mut server, _) = accept(&mut listener).await;
+
+        // Split into datagrams as large as the MTU allows.
+        let payload = vec![0xfe; 10_000];
+        client.send(payload.clone()).await;
+        assert_eq!(
+            timeout(WAIT, server.receive()).await.unwrap(),
+            Some(payload.clone())
+        );
+        server.send(payload.clone()).await;
+        assert_eq!(
+            timeout(WAIT, client.receive()).await.unwrap(),
+            Some(payload)
+        );
+
+        // The largest MTU the client tries, less the IP and UDP headers,
+        // with the SOCKS header included.
+        assert!(largest.load(Ordering::Relaxed) <= 1492 - 28);
+    });
+}
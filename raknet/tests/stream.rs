@@ -0,0 +1,171 @@
+mod common;
+
+use std::time::Duration;
+
+use async_std::{future::timeout, task};
+use futures::{stream, AsyncReadExt, AsyncWriteExt, SinkExt, StreamExt};
+use raknet::*;
+
+use common::*;
+
+#[test]
+fn round_trip() {
+    task::block_on(async {
+        let (mut listener, addr) = bind().await;
+
+        let mut client = connect(addr).await;
+        let (mut server, info) = accept(&mut listener).await;
+        assert_eq!(info.address.ip(), addr.ip());
+
+        let small = vec![0xfe, 1, 2, 3];
+        let mut large = (0..5000).map(|i| i as u8).collect::<Vec<_>>();
+        large[0] = 0xfe;
+        client.send(small.clone()).await;
+        client.send(large.clone()).await;
+        let received = timeout(WAIT, server.receive_bytes()).await.unwrap();
+        assert_eq!(received.as_deref(), Some(&small[..]));
+        assert_eq!(timeout(WAIT, server.receive()).await.unwrap(), Some(large));
+
+        server.send(vec![0xfe, 4, 5]).await;
+        assert_eq!(
+            timeout(WAIT, client.receive()).await.unwrap(),
+            Some(vec![0xfe, 4, 5])
+        );
+
+        let client_stats = client.stats();
+        assert!(client_stats.bytes_sent > 5000);
+        assert!(client_stats.datagrams_sent >= 4);
+        assert!(client_stats.datagrams_received >= 1);
+        let server_stats = server.stats();
+        assert!(server_stats.bytes_received > 5000);
+        assert!(server_stats.datagrams_sent >= 1);
+        assert!(server_stats.frame_sets_sent >= 1);
+        // The ACKs for the client's frames go out on the next tick, and are
+        // not frame sets.
+        timeout(WAIT, async {
+            while server.stats().frame_sets_sent == server.stats().datagrams_sent {
+                task::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        client.disconnect();
+        assert_eq!(timeout(WAIT, server.receive()).await.unwrap(), None);
+    });
+}
+
+#[test]
+fn burst_arrives_in_order() {
+    task::block_on(async {
+        let (mut listener, addr) = bind().await;
+
+        let mut client = connect(addr).await;
+        let (mut server, _) = accept(&mut listener).await;
+
+        // More datagrams than a single batched send or receive holds.
+        for i in 0..64u8 {
+            client.send([0xfe, i].repeat(500)).await;
+        }
+        for i in 0..64u8 {
+            assert_eq!(
+                timeout(WAIT, server.receive()).await.unwrap(),
+                Some([0xfe, i].repeat(500))
+            );
+        }
+        assert!(client.stats().datagrams_sent >= 64);
+    });
+}
+
+#[test]
+fn halves_are_sink_and_stream() {
+    task::block_on(async {
+        let (mut listener, addr) = bind().await;
+
+        let client = connect(addr).await;
+        let (server, _) = accept(&mut listener).await;
+        let (mut sender, _) = client.split();
+        // Dropping the server's sender would close the connection.
+        let (_server_sender, receiver) = server.split();
+
+        let packets = (0..8u8).map(|i| vec![0xfe, i]).collect::<Vec<_>>();
+        sender
+            .send_all(&mut stream::iter(packets.clone()).map(Ok))
+            .await
+            .unwrap();
+        let received = timeout(WAIT, receiver.take(8).collect::<Vec<_>>())
+            .await
+            .unwrap();
+        assert_eq!(received, packets);
+        assert!(sender.stats().datagrams_sent >= 1);
+
+        sender.clone().disconnect();
+        task::sleep(Duration::from_millis(100)).await;
+        assert!(SinkExt::send(&mut sender, vec![0xfe]).await.is_err());
+    });
+}
+
+#[test]
+fn byte_stream_round_trip() {
+    task::block_on(async {
+        let (mut listener, addr) = bind().await;
+
+        let client = connect(addr).await;
+        let (server, _) = accept(&mut listener).await;
+        let mut client = ByteStream::new(client, 0xfe);
+        let mut server = ByteStream::new(server, 0xfe);
+
+        let data = (0..40_000).map(|i| i as u8).collect::<Vec<_>>();
+        client.write_all(&data).await.unwrap();
+        client.flush().await.unwrap();
+        let mut received = vec![0; data.len()];
+        timeout(WAIT, server.read_exact(&mut received))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(received, data);
+
+        server.write_all(b"done").await.unwrap();
+        let mut reply = [0; 4];
+        timeout(WAIT, client.read_exact(&mut reply))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&reply, b"done");
+    });
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn sharded_listener_accepts_clients() {
+    task::block_on(async {
+        let config = ListenerConfig {
+            shards: 4,
+            ..Default::default()
+        };
+        let (mut listener, loop_task) =
+            Listener::bind_with_config("127.0.0.1:0", 1, "test", config)
+                .await
+                .unwrap();
+        let shards = loop_task.into_shards();
+        assert_eq!(shards.len(), 4);
+        for shard in shards {
+            task::spawn(shard);
+        }
+        let addr = listener.local_addr().unwrap();
+
+        let mut clients = vec![];
+        for i in 0..8u8 {
+            let mut client = connect(addr).await;
+            let (mut server, _) = accept(&mut listener).await;
+            client.send(vec![0xfe, i]).await;
+            assert_eq!(
+                timeout(WAIT, server.receive()).await.unwrap(),
+                Some(vec![0xfe, i])
+            );
+            clients.push((client, server));
+        }
+        assert_eq!(listener.metrics().active_connections, 8);
+        listener.destroy();
+    });
+}
@@ -0,0 +1,84 @@
+#![cfg(feature = "tracing")]
+
+mod common;
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use async_std::{future::timeout, task};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Metadata, Subscriber,
+};
+
+use common::*;
+
+/// Records the message of every event.
+#[derive(Clone, Default)]
+struct Recorder {
+    messages: Arc<Mutex<Vec<String>>>,
+}
+
+struct MessageVisitor<'a>(&'a mut Vec<String>);
+
+impl Visit for MessageVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.0.push(format!("{:?}", value));
+        }
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+        span::Id::from_u64(1)
+    }
+
+    fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+    fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        event.record(&mut MessageVisitor(&mut self.messages.lock().unwrap()));
+    }
+
+    fn enter(&self, _: &span::Id) {}
+
+    fn exit(&self, _: &span::Id) {}
+}
+
+#[test]
+fn handshake_is_traced() {
+    let recorder = Recorder::default();
+    tracing::subscriber::set_global_default(recorder.clone()).unwrap();
+
+    task::block_on(async {
+        let (mut listener, addr) = bind().await;
+        let client = connect(addr).await;
+        let (mut stream, _) = accept(&mut listener).await;
+        client.disconnect();
+        assert_eq!(timeout(WAIT, stream.receive()).await.unwrap(), None);
+    });
+
+    let messages = recorder.messages.lock().unwrap();
+    for expected in [
+        "open connection request 1",
+        "open connection request 2",
+        "accepting connection request",
+        "connected",
+        "connection closed",
+    ] {
+        assert!(
+            messages.iter().any(|message| message == expected),
+            "missing {:?} in {:?}",
+            expected,
+            messages
+        );
+    }
+}